@@ -24,6 +24,13 @@ const VERSION_SEED = Buffer.from("v1");
 const MARKET_SEED = Buffer.from("market");
 const MVA_SEED = Buffer.from("mva");
 
+// Market.pause_flags bits (lib.rs PAUSE_*)
+const PAUSE_OPEN = 1 << 0;
+const PAUSE_MARGIN = 1 << 1;
+const PAUSE_CLOSE = 1 << 2;
+const PAUSE_LIQUIDATE = 1 << 3;
+const PAUSE_NAV = 1 << 4;
+
 // ---------------- Utilities ----------------
 function toPubkey(x) {
   if (x instanceof web3.PublicKey) return x;
@@ -54,6 +61,14 @@ async function deriveDealPda(marketPda, longPubkey, shortPubkey, clientOrderId)
   return { dealPda, bump };
 }
 
+// derive deal vault accounts: dva = [v1, "deal_vault_auth", deal]; vaults = [v1, "long_vault" | "short_vault", deal]
+async function deriveDealVaults(dealPda) {
+  const [dvaPda] = await web3.PublicKey.findProgramAddress([VERSION_SEED, Buffer.from("deal_vault_auth"), dealPda.toBuffer()], PROGRAM_ID);
+  const [longVault] = await web3.PublicKey.findProgramAddress([VERSION_SEED, Buffer.from("long_vault"), dealPda.toBuffer()], PROGRAM_ID);
+  const [shortVault] = await web3.PublicKey.findProgramAddress([VERSION_SEED, Buffer.from("short_vault"), dealPda.toBuffer()], PROGRAM_ID);
+  return { dvaPda, longVault, shortVault };
+}
+
 // derive ATA for an owner (PDA or Pubkey): associated token seeds = [owner, token_program_id, mint], program = associated token program
 function deriveAtaForOwner(ownerPubkey, mintPubkey) {
   return web3.PublicKey.findProgramAddressSync(
@@ -96,8 +111,8 @@ async function initMarket(stackIdPubkey, paramsObj) {
   return { tx, marketPda, mvaPda, feeVault };
 }
 
-// postNav(market, nav (u64), confidence (Option<u64>))
-async function postNav(marketPda, nav, confidence = null) {
+// postNav(market, nav (u64), confidence (Option<u64>), navHistory (required once init_nav_history ran))
+async function postNav(marketPda, nav, confidence = null, navHistory = null) {
   if (!PROGRAM) throw new Error("PROGRAM missing.");
   const navBn = new BN(nav.toString());
   const confBn = confidence !== null && confidence !== undefined ? new BN(confidence.toString()) : null;
@@ -106,12 +121,246 @@ async function postNav(marketPda, nav, confidence = null) {
     .accounts({
       market: marketPda,
       oracleAuthority: WALLET.publicKey,
+      navHistory,
     })
     .rpc();
   console.log("postNav tx:", tx);
   return tx;
 }
 
+// disputeNav(market, quoteMint) — posts market.disputeBond from the wallet's quote ATA
+async function disputeNav(marketPda, quoteMint) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const market = toPubkey(marketPda);
+  const mint = toPubkey(quoteMint);
+  const { mvaPda } = await deriveMvaPda(market);
+  const tx = await PROGRAM.methods
+    .disputeNav()
+    .accounts({
+      disputer: WALLET.publicKey,
+      market,
+      disputerSource: deriveAtaForOwner(WALLET.publicKey, mint),
+      feeVault: deriveAtaForOwner(mvaPda, mint),
+      marketVaultAuth: mvaPda,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    })
+    .rpc();
+  console.log("disputeNav tx:", tx);
+  return tx;
+}
+
+// resolveNavDispute(market, quoteMint, navValid, disputer|null, navHistory|null) — admin
+// navValid = true applies the NAV and slashes the bond; false discards it and refunds the disputer
+async function resolveNavDispute(marketPda, quoteMint, navValid, disputer = null, navHistory = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const market = toPubkey(marketPda);
+  const mint = toPubkey(quoteMint);
+  const { mvaPda } = await deriveMvaPda(market);
+  const tx = await PROGRAM.methods
+    .resolveNavDispute(navValid)
+    .accounts({
+      authority: WALLET.publicKey,
+      market,
+      feeVault: deriveAtaForOwner(mvaPda, mint),
+      marketVaultAuth: mvaPda,
+      disputerRefundAta: disputer ? deriveAtaForOwner(toPubkey(disputer), mint) : null,
+      navHistory,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    })
+    .rpc();
+  console.log("resolveNavDispute tx:", tx);
+  return tx;
+}
+
+// finalizeNav(market, navHistory|null) — permissionless, after the dispute window
+async function finalizeNav(marketPda, navHistory = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .finalizeNav()
+    .accounts({
+      market: marketPda,
+      navHistory,
+    })
+    .rpc();
+  console.log("finalizeNav tx:", tx);
+  return tx;
+}
+
+// declareOracleDead(market, navHistory|null) — permissionless after price_stale_seconds * oracleDeadMultiple
+async function declareOracleDead(marketPda, navHistory = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .declareOracleDead()
+    .accounts({
+      market: marketPda,
+      navHistory,
+    })
+    .rpc();
+  console.log("declareOracleDead tx:", tx);
+  return tx;
+}
+
+// reviveOracle(market) — admin
+async function reviveOracle(marketPda) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .reviveOracle()
+    .accounts({
+      authority: WALLET.publicKey,
+      market: marketPda,
+    })
+    .rpc();
+  console.log("reviveOracle tx:", tx);
+  return tx;
+}
+
+// derive NAV history PDA: seeds = [v1, "nav_history", market]
+async function deriveNavHistoryPda(marketPda) {
+  const seeds = [VERSION_SEED, Buffer.from("nav_history"), marketPda.toBuffer()];
+  const [navHistoryPda] = await web3.PublicKey.findProgramAddress(seeds, PROGRAM_ID);
+  return navHistoryPda;
+}
+
+// initNavHistory(market) — admin; afterwards pass the returned PDA as navHistory to postNav/submitNav
+async function initNavHistory(marketPda) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const navHistory = await deriveNavHistoryPda(marketPda);
+  const tx = await PROGRAM.methods
+    .initNavHistory()
+    .accounts({
+      authority: WALLET.publicKey,
+      market: marketPda,
+      navHistory,
+      systemProgram: web3.SystemProgram.programId,
+    })
+    .rpc();
+  console.log("initNavHistory tx:", tx);
+  return { tx, navHistory };
+}
+
+// derive oracle set PDA: seeds = [v1, "oracle_set", market]
+async function deriveOracleSetPda(marketPda) {
+  const seeds = [VERSION_SEED, Buffer.from("oracle_set"), marketPda.toBuffer()];
+  const [oracleSetPda] = await web3.PublicKey.findProgramAddress(seeds, PROGRAM_ID);
+  return oracleSetPda;
+}
+
+// initOracleSet(market, publishers[], quorum) — authority, before the first NAV; switches the market to median-of-publishers NAV
+async function initOracleSet(marketPda, publishers, quorum) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .initOracleSet(publishers.map(toPubkey), quorum)
+    .accounts({
+      authority: WALLET.publicKey,
+      market: marketPda,
+      oracleSet: await deriveOracleSetPda(marketPda),
+      systemProgram: web3.SystemProgram.programId,
+    })
+    .rpc();
+  console.log("initOracleSet tx:", tx);
+  return tx;
+}
+
+// setOraclePublishers(market, publishers[], quorum) — authority, before the first NAV; clears all submissions.
+// Live markets use a timelocked { setOraclePublishers } proposal instead.
+async function setOraclePublishers(marketPda, publishers, quorum) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .setOraclePublishers(publishers.map(toPubkey), quorum)
+    .accounts({ authority: WALLET.publicKey, market: marketPda, oracleSet: await deriveOracleSetPda(marketPda) })
+    .rpc();
+  console.log("setOraclePublishers tx:", tx);
+  return tx;
+}
+
+// submitNav(market, nav, confidence, navHistory) — signed by a publisher in the market's oracle set
+async function submitNav(marketPda, nav, confidence = null, navHistory = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const confBn = confidence !== null && confidence !== undefined ? new BN(confidence.toString()) : null;
+  const tx = await PROGRAM.methods
+    .submitNav(new BN(nav.toString()), confBn)
+    .accounts({ market: marketPda, oracleSet: await deriveOracleSetPda(marketPda), publisher: WALLET.publicKey, navHistory })
+    .rpc();
+  console.log("submitNav tx:", tx);
+  return tx;
+}
+
+// derive stack definition PDA: seeds = [v1, "stack", market]
+async function deriveStackDefinitionPda(marketPda) {
+  const seeds = [VERSION_SEED, Buffer.from("stack"), marketPda.toBuffer()];
+  const [stackDefinitionPda] = await web3.PublicKey.findProgramAddress(seeds, PROGRAM_ID);
+  return stackDefinitionPda;
+}
+
+// initStackDefinition(market, constituents[{ feed, source: { pyth: {} } | { switchboard: {} }, units }], divisor) — authority, before the first NAV
+async function initStackDefinition(marketPda, constituents, divisor) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const stackDefinition = await deriveStackDefinitionPda(marketPda);
+  const tx = await PROGRAM.methods
+    .initStackDefinition(
+      constituents.map((c) => ({ feed: toPubkey(c.feed), source: c.source, units: new BN(c.units.toString()) })),
+      new BN(divisor.toString())
+    )
+    .accounts({
+      authority: WALLET.publicKey,
+      market: marketPda,
+      stackDefinition,
+      systemProgram: web3.SystemProgram.programId,
+    })
+    .rpc();
+  console.log("initStackDefinition tx:", tx);
+  return { tx, stackDefinition };
+}
+
+// computeStackNav(market, feeds[], navHistory) — permissionless; feeds in the definition's constituent order
+async function computeStackNav(marketPda, feeds, navHistory = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .computeStackNav()
+    .accounts({ market: marketPda, stackDefinition: await deriveStackDefinitionPda(marketPda), navHistory })
+    .remainingAccounts(feeds.map((f) => ({ pubkey: toPubkey(f), isSigner: false, isWritable: false })))
+    .rpc();
+  console.log("computeStackNav tx:", tx);
+  return tx;
+}
+
+// refreshNavFromFeed(market, feed, navHistory) — permissionless; feed = market.oracleFeed (Pyth / Switchboard)
+async function refreshNavFromFeed(marketPda, feed, navHistory = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .refreshNavFromFeed()
+    .accounts({ market: marketPda, feed: toPubkey(feed), navHistory })
+    .rpc();
+  console.log("refreshNavFromFeed tx:", tx);
+  return tx;
+}
+
+// postFundingPremium(market, premiumBps) — daily premium in bps (signed), Premium funding mode
+async function postFundingPremium(marketPda, premiumBps) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .postFundingPremium(premiumBps)
+    .accounts({
+      market: marketPda,
+      oracleAuthority: WALLET.publicKey,
+      navHistory: null,
+    })
+    .rpc();
+  console.log("postFundingPremium tx:", tx);
+  return tx;
+}
+
+// updateFunding(market) — permissionless crank
+async function updateFunding(marketPda) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .updateFunding()
+    .accounts({ market: marketPda })
+    .rpc();
+  console.log("updateFunding tx:", tx);
+  return tx;
+}
+
 // pauseMarket(market, paused)
 async function pauseMarket(marketPda, paused) {
   if (!PROGRAM) throw new Error("PROGRAM missing.");
@@ -126,7 +375,257 @@ async function pauseMarket(marketPda, paused) {
   return tx;
 }
 
-// propose_market_params(market, paramsObj, delay_secs)
+// setPauseFlags(market, flags) — admin; e.g. setPauseFlags(m, PAUSE_OPEN) keeps closes/liquidations live
+async function setPauseFlags(marketPda, flags) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .setPauseFlags(flags)
+    .accounts({
+      authority: WALLET.publicKey,
+      market: marketPda,
+    })
+    .rpc();
+  console.log("setPauseFlags tx:", tx);
+  return tx;
+}
+
+// tripCircuitBreaker(market, durationSecs|null) — admin; null = market.circuitBreakerSecs
+async function tripCircuitBreaker(marketPda, durationSecs = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .tripCircuitBreaker(durationSecs)
+    .accounts({
+      authority: WALLET.publicKey,
+      market: marketPda,
+    })
+    .rpc();
+  console.log("tripCircuitBreaker tx:", tx);
+  return tx;
+}
+
+// resetCircuitBreaker(market) — admin
+async function resetCircuitBreaker(marketPda) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .resetCircuitBreaker()
+    .accounts({
+      authority: WALLET.publicKey,
+      market: marketPda,
+    })
+    .rpc();
+  console.log("resetCircuitBreaker tx:", tx);
+  return tx;
+}
+
+// setReduceOnly(market, reduceOnly) — admin
+async function setReduceOnly(marketPda, reduceOnly) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .setReduceOnly(reduceOnly)
+    .accounts({
+      authority: WALLET.publicKey,
+      market: marketPda,
+    })
+    .rpc();
+  console.log("setReduceOnly tx:", tx);
+  return tx;
+}
+
+// beginShutdown(market, navHistory|null) — admin; freezes final NAV, market -> Settling
+async function beginShutdown(marketPda, navHistory = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .beginShutdown()
+    .accounts({
+      authority: WALLET.publicKey,
+      market: marketPda,
+      navHistory,
+    })
+    .rpc();
+  console.log("beginShutdown tx:", tx);
+  return tx;
+}
+
+// closeMarket(market, quoteMint, attached = { oracleSet, stackDefinition, navHistory }) — admin, once Settled
+async function closeMarket(marketPda, quoteMint, attached = {}) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const market = toPubkey(marketPda);
+  const { mvaPda } = await deriveMvaPda(market);
+  const marketAcc = await PROGRAM.account.market.fetch(market);
+  const tx = await PROGRAM.methods
+    .closeMarket()
+    .accounts({
+      authority: WALLET.publicKey,
+      market,
+      marketVaultAuth: mvaPda,
+      feeVault: deriveAtaForOwner(mvaPda, toPubkey(quoteMint)),
+      oracleSet: attached.oracleSet ?? null,
+      stackDefinition: attached.stackDefinition ?? null,
+      navHistory: attached.navHistory ?? null,
+      marketAuthority: marketAcc.authority,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    })
+    .rpc();
+  console.log("closeMarket tx:", tx);
+  return tx;
+}
+
+// addAdmin / removeAdmin / replaceAdmin — authority; multisig markets use createProposal({ addAdmin: { admin } }) etc.
+async function addAdmin(marketPda, admin) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .addAdmin(toPubkey(admin))
+    .accounts({ authority: WALLET.publicKey, market: marketPda })
+    .rpc();
+  console.log("addAdmin tx:", tx);
+  return tx;
+}
+
+async function removeAdmin(marketPda, admin) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .removeAdmin(toPubkey(admin))
+    .accounts({ authority: WALLET.publicKey, market: marketPda })
+    .rpc();
+  console.log("removeAdmin tx:", tx);
+  return tx;
+}
+
+async function replaceAdmin(marketPda, oldAdmin, newAdmin) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .replaceAdmin(toPubkey(oldAdmin), toPubkey(newAdmin))
+    .accounts({ authority: WALLET.publicKey, market: marketPda })
+    .rpc();
+  console.log("replaceAdmin tx:", tx);
+  return tx;
+}
+
+// setRole(market, role, holder) — authority; role = { guardian: {} } | { riskManager: {} } | { feeManager: {} },
+// holder = web3.PublicKey.default clears the role
+async function setRole(marketPda, role, holder) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .setRole(role, toPubkey(holder))
+    .accounts({ authority: WALLET.publicKey, market: marketPda })
+    .rpc();
+  console.log("setRole tx:", tx);
+  return tx;
+}
+
+// tightenMarginParams(market, paramsObj) — risk manager or authority; only
+// initialMarginBps / maintenanceMarginBps / mmBufferBps (up) and maxLeverageBps (down), all others null
+async function tightenMarginParams(marketPda, paramsObj) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .tightenMarginParams(paramsObj)
+    .accounts({ authority: WALLET.publicKey, market: marketPda })
+    .rpc();
+  console.log("tightenMarginParams tx:", tx);
+  return tx;
+}
+
+// derive proposal PDA: seeds = [v1, "proposal", market, proposal_id(le u64)]
+async function deriveProposalPda(marketPda, proposalId) {
+  const id = Buffer.from(new BN(proposalId.toString()).toArray("le", 8));
+  const [proposalPda, bump] = await web3.PublicKey.findProgramAddress(
+    [VERSION_SEED, Buffer.from("proposal"), toPubkey(marketPda).toBuffer(), id],
+    PROGRAM_ID
+  );
+  return { proposalPda, bump };
+}
+
+// createProposal(market, action, delaySecs) — admin; action e.g. { pause: { paused: true } },
+// { updateParams: { params } }, { withdrawFees: { amount: null } },
+// { resolveNavDispute: { nav: market.pendingNav.nav, navValid: false } }, { setReduceOnly: { reduceOnly: true } },
+// { resetCircuitBreaker: {} }, { reviveOracle: {} }, { beginShutdown: {} },
+// { setOraclePublishers: { publishers, quorum } } (delaySecs >= market.minTimelockSecs).
+// Once adminThreshold > 1, owner actions only run this way (direct calls fail with ProposalRequired).
+async function createProposal(marketPda, action, delaySecs = 0) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const marketAcc = await PROGRAM.account.market.fetch(marketPda);
+  const { proposalPda } = await deriveProposalPda(marketPda, marketAcc.proposalCount);
+  const tx = await PROGRAM.methods
+    .createProposal(action, delaySecs)
+    .accounts({
+      proposer: WALLET.publicKey,
+      market: marketPda,
+      proposal: proposalPda,
+      systemProgram: web3.SystemProgram.programId,
+    })
+    .rpc();
+  console.log("createProposal tx:", tx);
+  return { tx, proposalPda, proposalId: marketAcc.proposalCount };
+}
+
+// approveProposal(market, proposalId) — admin
+async function approveProposal(marketPda, proposalId) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const { proposalPda } = await deriveProposalPda(marketPda, proposalId);
+  const tx = await PROGRAM.methods
+    .approveProposal()
+    .accounts({
+      admin: WALLET.publicKey,
+      market: marketPda,
+      proposal: proposalPda,
+    })
+    .rpc();
+  console.log("approveProposal tx:", tx);
+  return tx;
+}
+
+// executeProposal(market, proposalId, extra) — permissionless after threshold + eta.
+// extra: { stackDefinition } for basket rebalances, or fee accounts
+// { marketVaultAuth, feeVault, treasuryAta, insuranceAta, oracleOperatorAta, tokenProgram } for withdrawFees,
+// { marketVaultAuth, feeVault, tokenProgram, disputerRefundAta, navHistory } for resolveNavDispute,
+// { oracleSet } for setOraclePublishers
+async function executeProposal(marketPda, proposalId, extra = {}) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const { proposalPda } = await deriveProposalPda(marketPda, proposalId);
+  const proposal = await PROGRAM.account.proposal.fetch(proposalPda);
+  const tx = await PROGRAM.methods
+    .executeProposal()
+    .accounts({
+      executor: WALLET.publicKey,
+      market: marketPda,
+      proposal: proposalPda,
+      proposer: proposal.proposer,
+      stackDefinition: null,
+      marketVaultAuth: null,
+      feeVault: null,
+      treasuryAta: null,
+      insuranceAta: null,
+      oracleOperatorAta: null,
+      tokenProgram: null,
+      disputerRefundAta: null,
+      navHistory: null,
+      oracleSet: null,
+      ...extra,
+    })
+    .rpc();
+  console.log("executeProposal tx:", tx);
+  return tx;
+}
+
+// cancelProposal(market, proposalId) — proposer or market authority
+async function cancelProposal(marketPda, proposalId) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const { proposalPda } = await deriveProposalPda(marketPda, proposalId);
+  const proposal = await PROGRAM.account.proposal.fetch(proposalPda);
+  const tx = await PROGRAM.methods
+    .cancelProposal()
+    .accounts({
+      signer: WALLET.publicKey,
+      market: marketPda,
+      proposal: proposalPda,
+      proposer: proposal.proposer,
+    })
+    .rpc();
+  console.log("cancelProposal tx:", tx);
+  return tx;
+}
+
+// propose_market_params(market, paramsObj, delay_secs) — delay_secs >= market.minTimelockSecs
 async function proposeMarketParams(marketPda, paramsObj, delaySecs) {
   if (!PROGRAM) throw new Error("PROGRAM missing.");
   const tx = await PROGRAM.methods
@@ -140,20 +639,35 @@ async function proposeMarketParams(marketPda, paramsObj, delaySecs) {
   return tx;
 }
 
-// execute_market_params(market)
-async function executeMarketParams(marketPda) {
+// execute_market_params(market, stackDefinition) — pass the StackDefinition PDA when the pending params rebalance the basket
+async function executeMarketParams(marketPda, stackDefinition = null) {
   if (!PROGRAM) throw new Error("PROGRAM missing.");
   const tx = await PROGRAM.methods
     .executeMarketParams()
     .accounts({
       authority: WALLET.publicKey,
       market: marketPda,
+      stackDefinition,
     })
     .rpc();
   console.log("executeMarketParams tx:", tx);
   return tx;
 }
 
+// cancel_pending_params(market) — authority; drops a live or expired pending update
+async function cancelPendingParams(marketPda) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .cancelPendingParams()
+    .accounts({
+      authority: WALLET.publicKey,
+      market: marketPda,
+    })
+    .rpc();
+  console.log("cancelPendingParams tx:", tx);
+  return tx;
+}
+
 // rotate_authority(market, new_authority_pubkey)
 async function rotateAuthority(marketPda, newAuthorityPubkey) {
   if (!PROGRAM) throw new Error("PROGRAM missing.");
@@ -168,7 +682,34 @@ async function rotateAuthority(marketPda, newAuthorityPubkey) {
   return tx;
 }
 
+// set_fee_split(market, { treasury, treasuryBps, insurance, insuranceBps, oracleOperator, oracleOperatorBps })
+async function setFeeSplit(marketPda, split) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .setFeeSplit(split)
+    .accounts({
+      authority: WALLET.publicKey,
+      market: marketPda,
+    })
+    .rpc();
+  console.log("setFeeSplit tx:", tx);
+  return tx;
+}
+
+// withdrawFees(accountsObj, amount|null) — accounts must match lib.rs WithdrawFees context
+async function withdrawFees(accountsObj, amount = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const amt = amount !== null && amount !== undefined ? new BN(amount.toString()) : null;
+  const tx = await PROGRAM.methods
+    .withdrawFees(amt)
+    .accounts(accountsObj)
+    .rpc();
+  console.log("withdrawFees tx:", tx);
+  return tx;
+}
+
 // openDeal(opts) where opts includes: marketPda, quoteMint, long, short, longSourceAta, shortSourceAta, clientOrderId, size, longDeposit, shortDeposit
+// and optionally guard (see tradeGuard)
 async function openDeal(opts) {
   if (!PROGRAM) throw new Error("PROGRAM missing.");
   const marketPda = toPubkey(opts.marketPda);
@@ -183,6 +724,7 @@ async function openDeal(opts) {
   const shortDeposit = new BN(opts.shortDeposit.toString());
 
   const { dealPda } = await deriveDealPda(marketPda, long, short, clientOrderId);
+  const { dvaPda, longVault, shortVault } = await deriveDealVaults(dealPda);
   const { mvaPda } = await deriveMvaPda(marketPda);
   const feeVault = deriveAtaForOwner(mvaPda, quoteMint);
 
@@ -192,7 +734,7 @@ async function openDeal(opts) {
 
   // Call program; Anchor will create PDAs and ATAs as specified by lib.rs (payer = long for many inits)
   const tx = await PROGRAM.methods
-    .openDeal(new BN(clientOrderId.toString()), size, longDeposit, shortDeposit)
+    .openDeal(new BN(clientOrderId.toString()), size, longDeposit, shortDeposit, opts.guard ?? null)
     .accounts({
       long: long,
       short: short,
@@ -201,11 +743,9 @@ async function openDeal(opts) {
       longSource: longSource,
       shortSource: shortSource,
       deal: dealPda,
-      // For init accounts (deal_vault_auth, long_margin_vault, short_margin_vault) Anchor expects the client to
-      // pass the addresses. In practice you should derive them and pass here; leaving null will fail at runtime.
-      dealVaultAuth: null,
-      longMarginVault: null,
-      shortMarginVault: null,
+      dealVaultAuth: dvaPda,
+      longMarginVault: longVault,
+      shortMarginVault: shortVault,
       feeVault: feeVault,
       marketVaultAuth: mvaPda,
       systemProgram: web3.SystemProgram.programId,
@@ -216,7 +756,7 @@ async function openDeal(opts) {
     .rpc();
 
   console.log("openDeal tx:", tx);
-  return { tx, dealPda, feeVault, mvaPda };
+  return { tx, dealPda, dvaPda, longVault, shortVault, feeVault, mvaPda };
 }
 
 // add_margin_long(deal, longSourceAta, longMarginVault, market)
@@ -261,17 +801,203 @@ async function addMarginShort(dealPda, shortSourceAta, shortMarginVault, marketP
   return tx;
 }
 
-// closeDeal(accountsObj) — provide full accounts object matching lib.rs CloseDeal context
-async function closeDeal(accountsObj) {
+// withdrawMarginLong(accountsObj, amount) — accounts must match WithdrawMarginLong context
+async function withdrawMarginLong(accountsObj, amount) {
   if (!PROGRAM) throw new Error("PROGRAM missing.");
   const tx = await PROGRAM.methods
-    .closeDeal()
+    .withdrawMarginLong(new BN(amount.toString()))
+    .accounts(accountsObj)
+    .rpc();
+  console.log("withdrawMarginLong tx:", tx);
+  return tx;
+}
+
+// withdrawMarginShort(accountsObj, amount) — accounts must match WithdrawMarginShort context
+async function withdrawMarginShort(accountsObj, amount) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .withdrawMarginShort(new BN(amount.toString()))
+    .accounts(accountsObj)
+    .rpc();
+  console.log("withdrawMarginShort tx:", tx);
+  return tx;
+}
+
+// tradeGuard(minNav|null, maxNav|null, deadlineTs|null) — optional slippage/deadline guard for open/close/increase/reduce/acceptClose
+function tradeGuard(minNav = null, maxNav = null, deadlineTs = null) {
+  const bn = (v) => (v === null ? null : new BN(v.toString()));
+  return { minNav: bn(minNav), maxNav: bn(maxNav), deadlineTs: bn(deadlineTs) };
+}
+
+// closeDeal(accountsObj, guard) — provide full accounts object matching lib.rs CloseDeal context
+async function closeDeal(accountsObj, guard = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .closeDeal(guard)
     .accounts(accountsObj)
     .rpc();
   console.log("closeDeal tx:", tx);
   return tx;
 }
 
+// increaseDeal(accountsObj, addSize, longDeposit, shortDeposit, guard) — both long & short sign; accounts must match IncreaseDeal context
+async function increaseDeal(accountsObj, addSize, longDeposit, shortDeposit, guard = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .increaseDeal(new BN(addSize.toString()), new BN(longDeposit.toString()), new BN(shortDeposit.toString()), guard)
+    .accounts(accountsObj)
+    .rpc();
+  console.log("increaseDeal tx:", tx);
+  return tx;
+}
+
+// openDealFromOffer(accountsObj, offer, takerDeposit, ed25519Ix) — taker signs; ed25519Ix must verify the maker's
+// signature over OFFER_DOMAIN || borsh(offer) and is placed right before the program instruction
+async function openDealFromOffer(accountsObj, offer, takerDeposit, ed25519Ix) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .openDealFromOffer(offer, new BN(takerDeposit.toString()))
+    .accounts(accountsObj)
+    .preInstructions([ed25519Ix])
+    .rpc();
+  console.log("openDealFromOffer tx:", tx);
+  return tx;
+}
+
+// makeOffer(accountsObj, offerId, makerIsLong, size, bandBps, makerDeposit, expiryTs, allowedTaker) — maker signs
+async function makeOffer(accountsObj, offerId, makerIsLong, size, bandBps, makerDeposit, expiryTs = null, allowedTaker = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .makeOffer(
+      new BN(offerId.toString()),
+      makerIsLong,
+      new BN(size.toString()),
+      bandBps,
+      new BN(makerDeposit.toString()),
+      expiryTs === null ? null : new BN(expiryTs.toString()),
+      allowedTaker
+    )
+    .accounts(accountsObj)
+    .rpc();
+  console.log("makeOffer tx:", tx);
+  return tx;
+}
+
+// takeOffer(accountsObj, takerDeposit) — taker signs; accounts must match TakeOffer context (only the taker's takerSource, the maker side comes from escrow)
+async function takeOffer(accountsObj, takerDeposit) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods.takeOffer(new BN(takerDeposit.toString())).accounts(accountsObj).rpc();
+  console.log("takeOffer tx:", tx);
+  return tx;
+}
+
+// cancelOffer(accountsObj) — maker signs
+async function cancelOffer(accountsObj) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods.cancelOffer().accounts(accountsObj).rpc();
+  console.log("cancelOffer tx:", tx);
+  return tx;
+}
+
+// reduceDeal(accountsObj, reduceBy, releaseMargin, guard) — both long & short sign; accounts must match ReduceDeal context
+async function reduceDeal(accountsObj, reduceBy, releaseMargin = false, guard = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .reduceDeal(new BN(reduceBy.toString()), releaseMargin, guard)
+    .accounts(accountsObj)
+    .rpc();
+  console.log("reduceDeal tx:", tx);
+  return tx;
+}
+
+// derive close request PDA: seeds = [v1, "close_req", deal, requester] (one per party)
+async function deriveCloseRequestPda(dealPda, requester) {
+  const [closeRequestPda, bump] = await web3.PublicKey.findProgramAddress(
+    [VERSION_SEED, Buffer.from("close_req"), toPubkey(dealPda).toBuffer(), toPubkey(requester).toBuffer()],
+    PROGRAM_ID
+  );
+  return { closeRequestPda, bump };
+}
+
+// requestClose(accountsObj, minNav|null, maxNav|null, expiryTs, reduceSize|null) — accounts must match RequestClose context;
+// expiryTs is required and at most close_notice_secs + 7 days ahead
+async function requestClose(accountsObj, minNav = null, maxNav = null, expiryTs, reduceSize = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const opt = (x) => (x !== null && x !== undefined ? new BN(x.toString()) : null);
+  const tx = await PROGRAM.methods
+    .requestClose(opt(minNav), opt(maxNav), new BN(expiryTs.toString()), opt(reduceSize))
+    .accounts(accountsObj)
+    .rpc();
+  console.log("requestClose tx:", tx);
+  return tx;
+}
+
+// cancelCloseRequest(accountsObj)
+async function cancelCloseRequest(accountsObj) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .cancelCloseRequest()
+    .accounts(accountsObj)
+    .rpc();
+  console.log("cancelCloseRequest tx:", tx);
+  return tx;
+}
+
+// acceptClose(accountsObj, guard) — signer is the counterparty; accounts must match SettleCloseRequest context
+async function acceptClose(accountsObj, guard = null) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .acceptClose(guard)
+    .accounts(accountsObj)
+    .rpc();
+  console.log("acceptClose tx:", tx);
+  return tx;
+}
+
+// forceClose(accountsObj) — signer is the requester, after the market's close notice period
+async function forceClose(accountsObj) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .forceClose()
+    .accounts(accountsObj)
+    .rpc();
+  console.log("forceClose tx:", tx);
+  return tx;
+}
+
+// settleExpiredDeal(accountsObj) — permissionless; accounts must match SettleExpiredDeal context
+async function settleExpiredDeal(accountsObj) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .settleExpiredDeal()
+    .accounts(accountsObj)
+    .rpc();
+  console.log("settleExpiredDeal tx:", tx);
+  return tx;
+}
+
+// settleDealAtFinalNav(accountsObj) — permissionless during Settling; accounts match SettleDealAtFinalNav
+async function settleDealAtFinalNav(accountsObj) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .settleDealAtFinalNav()
+    .accounts(accountsObj)
+    .rpc();
+  console.log("settleDealAtFinalNav tx:", tx);
+  return tx;
+}
+
+// emergencySettleDeal(accountsObj) — permissionless once the oracle is dead; accounts match EmergencySettleDeal
+async function emergencySettleDeal(accountsObj) {
+  if (!PROGRAM) throw new Error("PROGRAM missing.");
+  const tx = await PROGRAM.methods
+    .emergencySettleDeal()
+    .accounts(accountsObj)
+    .rpc();
+  console.log("emergencySettleDeal tx:", tx);
+  return tx;
+}
+
 // liquidate(accountsObj) — provide full accounts matching Liquidate context
 async function liquidate(accountsObj) {
   if (!PROGRAM) throw new Error("PROGRAM missing.");
@@ -354,6 +1080,17 @@ async function inspectMarket(authority, quoteMint, stackId) {
     max_confidence_bps: 0,
     mm_buffer_bps: 100,
     admin_threshold: 1,
+    close_notice_secs: 86400,
+    circuit_breaker_secs: 300, // cool-off after a NAV jump trip
+    dispute_window_secs: null, // > 0 => post_nav proposes, finalize_nav applies after the window
+    dispute_bond: null, // quote units posted by dispute_nav (required when dispute_window_secs > 0)
+    oracle_dead_multiple: null, // default 10 x price_stale_seconds; 0 disables declare_oracle_dead
+    min_timelock_secs: null, // default 3600; floor for propose_market_params delays
+    expiry_ts: null, // or unix ts for a dated (monthly/quarterly) contract
+    mark_mode: null, // { spot: {} } default, or { twap: {} } with twap_window_secs + initNavHistory
+    twap_window_secs: null,
+    oracle_source: null, // { authority: {} } default, { pyth: {} } or { switchboard: {} } with oracle_feed
+    oracle_feed: null,
   };
 
   const res = await initMarket(stackId, params);
@@ -1,4 +1,8 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    ed25519_program,
+    sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+};
 use anchor_spl::{
     associated_token::AssociatedToken,
     token::{self, CloseAccount, Mint, Token, TokenAccount, Transfer},
@@ -17,6 +21,31 @@ declare_id!("FSBdeh58ourJm9Wjf1BFZ8jSGrgbhN2jrF3Vw4BdiQx1");
 pub const UNIT_DECIMALS: u8 = 6; // size units precision (1e6)
 pub const VERSION_SEED: &[u8] = b"v1";
 pub const MAX_ADMINS: usize = 5;
+pub const MAX_PUBLISHERS: usize = 7;
+pub const NAV_HISTORY_LEN: usize = 256; // ring buffer capacity (samples)
+pub const MAX_CONSTITUENTS: usize = 8;
+
+// Feed account layouts (parsed manually; the feed address itself is pinned by market admin)
+pub const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+pub const PYTH_ATYPE_PRICE: u32 = 3;
+pub const PYTH_STATUS_TRADING: u32 = 1;
+pub const SWITCHBOARD_ROUND_OFFSET: usize = 341; // AggregatorAccountData.latest_confirmed_round
+pub const MAX_FEED_DECIMALS: u32 = 38; // 10^38 is the largest power of ten that fits a u128
+pub const FUNDING_INDEX_SCALE: u128 = 1_000_000_000; // extra precision on the per-unit funding index
+pub const SECONDS_PER_DAY: i64 = 86_400;
+pub const CLOSE_REQUEST_GRACE_SECS: i64 = 7 * SECONDS_PER_DAY; // max close request life past the notice period
+pub const PENDING_PARAMS_GRACE_SECS: i64 = 7 * SECONDS_PER_DAY; // pending params expire this long after their ETA
+pub const PROPOSAL_GRACE_SECS: i64 = 7 * SECONDS_PER_DAY; // proposals expire this long after their ETA
+pub const OFFER_DOMAIN: &[u8] = b"synthetic_stack_futures:offer:v1"; // prefix of signed offer messages
+pub const MAX_INSTANT_MARGIN_STEP_BPS: u16 = 500; // largest IM / MM / buffer raise per instant update
+
+// Granular pause flags (Market.pause_flags); `paused` = everything
+pub const PAUSE_OPEN: u8 = 1 << 0; // open_deal, offers, increase_deal
+pub const PAUSE_MARGIN: u8 = 1 << 1; // add/withdraw margin
+pub const PAUSE_CLOSE: u8 = 1 << 2; // close_deal, reduce_deal, close requests
+pub const PAUSE_LIQUIDATE: u8 = 1 << 3; // liquidate, liquidate_to_im
+pub const PAUSE_NAV: u8 = 1 << 4; // NAV / funding premium posts
+pub const PAUSE_ALL: u8 = PAUSE_OPEN | PAUSE_MARGIN | PAUSE_CLOSE | PAUSE_LIQUIDATE | PAUSE_NAV;
 
 #[program]
 pub mod synthetic_stack_futures {
@@ -53,17 +82,78 @@ pub mod synthetic_stack_futures {
         market.max_confidence_bps = params.max_confidence_bps.unwrap_or(0);
         market.mm_buffer_bps = params.mm_buffer_bps.unwrap_or(100); // 1% default
         market.circuit_breaker_until = 0;
+        market.circuit_breaker_secs = params.circuit_breaker_secs.unwrap_or(300); // 5 min default
+        market.breaker_nav = 0;
+        market.breaker_nav_ts = 0;
+
+        // Optimistic NAV: posts wait out a dispute window (0 = applied immediately)
+        market.dispute_window_secs = params.dispute_window_secs.unwrap_or(0);
+        market.dispute_bond = params.dispute_bond.unwrap_or(0);
+        ensure_dispute_bond(market)?;
+        ensure_risk_bounds(market)?;
+        market.pending_nav = PendingNav::default();
+        market.bonds_held = 0;
+
+        // Oracle liveness: dead after price_stale_seconds * multiple without a NAV (0 = never)
+        market.oracle_dead_multiple = params.oracle_dead_multiple.unwrap_or(10);
+        market.oracle_dead = false;
+        market.dead_nav = 0;
+        market.oracle_dead_ts = 0;
+
+        market.status = MarketStatus::Active;
+        market.open_deals = 0;
+        market.close_notice_secs = params.close_notice_secs.unwrap_or(86_400); // 1 day default
+
+        // Dated contract: expiry 0 = perpetual. Final NAV is the first post at/after expiry.
+        let expiry_ts = params.expiry_ts.unwrap_or(0);
+        if expiry_ts != 0 {
+            require!(expiry_ts > Clock::get()?.unix_timestamp, ErrorCode::InvalidExpiry);
+        }
+        market.expiry_ts = expiry_ts;
+        market.final_nav = 0;
+        market.final_nav_ts = 0;
+
+        // Funding (perpetuals): daily rate in bps, positive => longs pay shorts
+        market.funding_mode = params.funding_mode.unwrap_or_default();
+        market.funding_rate_bps = params.funding_rate_bps.unwrap_or(0);
+        market.funding_premium_bps = 0;
+        market.max_funding_rate_bps = params.max_funding_rate_bps.unwrap_or(0);
+        market.cumulative_funding = 0;
+        market.last_funding_ts = Clock::get()?.unix_timestamp;
+
+        // Mark price for close/liquidation/expiry; TWAP needs a NavHistory (init_nav_history)
+        market.mark_mode = params.mark_mode.unwrap_or_default();
+        market.twap_window_secs = params.twap_window_secs.unwrap_or(0);
+        market.nav_history = Pubkey::default();
+
+        // NAV source: manual oracle authority by default, or a Pyth / Switchboard feed account
+        market.oracle_source = params.oracle_source.unwrap_or_default();
+        market.oracle_feed = params.oracle_feed.unwrap_or_default();
+        market.stack_definition = Pubkey::default();
 
         // Multisig defaults (PoC: authority is admin[0], threshold = 1 or provided)
         market.admin_threshold = params.admin_threshold.unwrap_or(1);
         market.admins = [Pubkey::default(); MAX_ADMINS];
         market.admins[0] = market.authority;
+        ensure_admin_threshold(market)?;
+        market.proposal_count = 0;
+        market.open_proposals = 0;
+
+        // Roles are unassigned until the owner sets them (authority covers every role)
+        market.guardian = Pubkey::default();
+        market.risk_manager = Pubkey::default();
+        market.fee_manager = Pubkey::default();
+
+        // Fee distribution is unset until the admin configures recipients
+        market.fee_split = FeeSplit::default();
 
         market.last_nav = 0;
         market.last_ts = 0;
         market.paused = false;
+        market.pause_flags = 0;
         market.bump = ctx.bumps.market;
         market.pending = None;
+        market.min_timelock_secs = params.min_timelock_secs.unwrap_or(3600); // 1h default
 
         let mva = &mut ctx.accounts.market_vault_auth;
         mva.market = market.key();
@@ -84,88 +174,724 @@ pub mod synthetic_stack_futures {
         Ok(())
     }
 
+    /// The guardian may pause; only the owner may unpause.
     pub fn pause_market(ctx: Context<AdminMarketToggle>, paused: bool) -> Result<()> {
-        // FIX: avoid lifetime coupling by passing key + remaining infos
-        require_admin_or_multisig(&ctx.accounts.market, ctx.accounts.authority.key(), &ctx.remaining_accounts)?;
+        if paused {
+            require_authority_or_role(&ctx.accounts.market, ctx.accounts.authority.key(), Role::Guardian)?;
+        } else {
+            require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        }
         ctx.accounts.market.paused = paused;
         Ok(())
     }
 
+    /// Pause individual operation groups (PAUSE_* bitmask), e.g. stop opens during an incident while
+    /// closes and liquidations continue. `pause_market(true)` still pauses everything.
+    /// The guardian may only add flags; clearing any needs the owner.
+    pub fn set_pause_flags(ctx: Context<AdminMarketToggle>, flags: u8) -> Result<()> {
+        let current = ctx.accounts.market.pause_flags;
+        if flags & current == current {
+            require_authority_or_role(&ctx.accounts.market, ctx.accounts.authority.key(), Role::Guardian)?;
+        } else {
+            require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        }
+        require!(flags & !PAUSE_ALL == 0, ErrorCode::InvalidPauseFlags);
+        ctx.accounts.market.pause_flags = flags;
+        Ok(())
+    }
+
+    /// Manually trip the circuit breaker for `duration_secs` (default: market.circuit_breaker_secs).
+    /// Only extends an active trip; ending one early is `reset_circuit_breaker` (owner only).
+    pub fn trip_circuit_breaker(ctx: Context<AdminMarketParams>, duration_secs: Option<u32>) -> Result<()> {
+        require_authority_or_role(&ctx.accounts.market, ctx.accounts.authority.key(), Role::Guardian)?;
+        let market = &mut ctx.accounts.market;
+        let duration = duration_secs.unwrap_or(market.circuit_breaker_secs);
+        require!(duration > 0, ErrorCode::InvalidBreakerDuration);
+        let now = Clock::get()?.unix_timestamp;
+        market.circuit_breaker_until = market.circuit_breaker_until.max(now + duration as i64);
+        emit!(CircuitBreakerTripped {
+            market: market.key(),
+            until: market.circuit_breaker_until,
+            rejected_nav: 0,
+            last_nav: market.last_nav,
+            ts: now,
+        });
+        Ok(())
+    }
+
+    /// Clear an active circuit breaker early.
+    pub fn reset_circuit_breaker(ctx: Context<AdminMarketParams>) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        reset_market_breaker(&mut ctx.accounts.market)
+    }
+
+    /// Instant path: risk-reducing changes only (see `require_risk_reducing`). Everything else goes
+    /// through propose/execute_market_params or an UpdateParams proposal.
     pub fn update_market_params(ctx: Context<AdminMarketParams>, params: MarketUpdateParams) -> Result<()> {
-        require_admin_or_multisig(&ctx.accounts.market, ctx.accounts.authority.key(), &ctx.remaining_accounts)?;
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        // Basket rebalances only go through propose/execute (timelock)
+        require!(
+            params.stack_units.is_none() && params.stack_divisor.is_none(),
+            ErrorCode::RebalanceNeedsTimelock
+        );
+        require_risk_reducing(&ctx.accounts.market, &params)?;
+        apply_market_updates(&mut ctx.accounts.market, &params);
+        ensure_risk_bounds(&ctx.accounts.market)?;
+        ensure_dispute_bond(&ctx.accounts.market)
+    }
+
+    /// Risk manager (or owner): raise IM / MM / MM buffer or lower the leverage cap immediately,
+    /// within the instant step caps and risk bounds. Any other field, or a loosening change, is rejected.
+    pub fn tighten_margin_params(ctx: Context<AdminMarketParams>, params: MarketUpdateParams) -> Result<()> {
+        require_authority_or_role(&ctx.accounts.market, ctx.accounts.authority.key(), Role::RiskManager)?;
+        require_margin_tightening(&ctx.accounts.market, &params)?;
         apply_market_updates(&mut ctx.accounts.market, &params);
+        ensure_risk_bounds(&ctx.accounts.market)
+    }
+
+    /// Assign (or clear with the default key) a role.
+    pub fn set_role(ctx: Context<AdminMarketParams>, role: Role, holder: Pubkey) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        set_market_role(&mut ctx.accounts.market, role, holder);
         Ok(())
     }
 
-    /// Propose market params (timelocked)
+    /// Propose market params (timelocked). `delay_secs` must be at least `min_timelock_secs`; a live
+    /// pending update has to be cancelled first (an expired one is replaced).
     pub fn propose_market_params(
         ctx: Context<AdminMarketParams>,
         params: MarketUpdateParams,
         delay_secs: i64,
     ) -> Result<()> {
-        require_admin_or_multisig(&ctx.accounts.market, ctx.accounts.authority.key(), &ctx.remaining_accounts)?;
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        let market = &mut ctx.accounts.market;
         let now = Clock::get()?.unix_timestamp;
-        ctx.accounts.market.pending = Some(PendingParams { params, eta: now + delay_secs });
+        require!(delay_secs >= market.min_timelock_secs as i64, ErrorCode::TimelockTooShort);
+        if let Some(p) = &market.pending {
+            require!(now > p.eta + PENDING_PARAMS_GRACE_SECS, ErrorCode::PendingParamsExist);
+        }
+        let eta = now.checked_add(delay_secs).ok_or(ErrorCode::MathOverflow)?;
+        emit!(ParamsProposed {
+            market: market.key(),
+            params: params.clone(),
+            eta,
+            expires_ts: eta + PENDING_PARAMS_GRACE_SECS,
+        });
+        market.pending = Some(PendingParams { params, eta });
         Ok(())
     }
 
-    /// Execute pending market params after ETA
-    pub fn execute_market_params(ctx: Context<AdminMarketParams>) -> Result<()> {
-        require_admin_or_multisig(&ctx.accounts.market, ctx.accounts.authority.key(), &ctx.remaining_accounts)?;
+    /// Execute pending market params between ETA and ETA + grace (pass the StackDefinition when
+    /// rebalancing the basket)
+    pub fn execute_market_params(ctx: Context<ExecuteMarketParams>) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
         let now = Clock::get()?.unix_timestamp;
         let Some(p) = ctx.accounts.market.pending.clone() else { return err!(ErrorCode::NoPendingParams); };
         require!(now >= p.eta, ErrorCode::TimelockNotExpired);
-        apply_market_updates(&mut ctx.accounts.market, &p.params);
+        require!(now <= p.eta + PENDING_PARAMS_GRACE_SECS, ErrorCode::PendingParamsExpired);
+        execute_params_update(&mut ctx.accounts.market, &p.params, ctx.accounts.stack_definition.as_mut(), now)?;
         ctx.accounts.market.pending = None;
+        emit!(ParamsExecuted { market: ctx.accounts.market.key(), eta: p.eta, ts: now });
+        Ok(())
+    }
+
+    /// Drop the pending params update (live or expired).
+    pub fn cancel_pending_params(ctx: Context<AdminMarketParams>) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        let market = &mut ctx.accounts.market;
+        let Some(p) = market.pending.take() else { return err!(ErrorCode::NoPendingParams); };
+        emit!(ParamsCancelled { market: market.key(), eta: p.eta, ts: Clock::get()?.unix_timestamp });
         Ok(())
     }
 
-    /// Rotate authority (multisig or authority)
+    /// Rotate authority (authority or proposal); the new key takes over the old authority's admin slot.
     pub fn rotate_authority(ctx: Context<AdminMarketParams>, new_authority: Pubkey) -> Result<()> {
-        require_admin_or_multisig(&ctx.accounts.market, ctx.accounts.authority.key(), &ctx.remaining_accounts)?;
-        ctx.accounts.market.authority = new_authority;
-        // PoC: also update admins[0] to keep UX simple
-        ctx.accounts.market.admins[0] = new_authority;
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        rotate_market_authority(&mut ctx.accounts.market, new_authority)
+    }
+
+    /// Add an admin to a free slot.
+    pub fn add_admin(ctx: Context<AdminMarketParams>, admin: Pubkey) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        change_admin(&mut ctx.accounts.market, Pubkey::default(), admin)
+    }
+
+    /// Remove an admin; fails if admin_threshold would exceed the remaining admins.
+    pub fn remove_admin(ctx: Context<AdminMarketParams>, admin: Pubkey) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        change_admin(&mut ctx.accounts.market, admin, Pubkey::default())
+    }
+
+    /// Swap one admin key for another in place (e.g. key rotation).
+    pub fn replace_admin(ctx: Context<AdminMarketParams>, old_admin: Pubkey, new_admin: Pubkey) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        require!(old_admin != Pubkey::default() && new_admin != Pubkey::default(), ErrorCode::InvalidAdminSet);
+        change_admin(&mut ctx.accounts.market, old_admin, new_admin)
+    }
+
+    /// Configure fee recipients (quote-mint token accounts) and their bps shares (must sum to 10_000).
+    pub fn set_fee_split(ctx: Context<AdminMarketParams>, split: FeeSplit) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        require!(split.is_valid(), ErrorCode::InvalidFeeSplit);
+        ctx.accounts.market.fee_split = split;
+        Ok(())
+    }
+
+    /// Withdraw collected fees from fee_vault, split across the configured recipients.
+    /// - amount: None = withdraw the full fee_vault balance
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: Option<u64>) -> Result<()> {
+        require_authority_or_role(&ctx.accounts.market, ctx.accounts.authority.key(), Role::FeeManager)?;
+        FeeDistribution {
+            token_program: &ctx.accounts.token_program,
+            fee_vault: &ctx.accounts.fee_vault,
+            market_vault_auth: &ctx.accounts.market_vault_auth,
+            treasury_ata: &ctx.accounts.treasury_ata,
+            insurance_ata: &ctx.accounts.insurance_ata,
+            oracle_operator_ata: &ctx.accounts.oracle_operator_ata,
+        }
+        .distribute(&ctx.accounts.market, amount)
+    }
+
+    // ──────────────────────────────────────────────────────────────────────────────
+    // Multisig proposals
+    // ──────────────────────────────────────────────────────────────────────────────
+
+    /// An admin proposes an action; the proposer's approval is counted. Executable once
+    /// `admin_threshold` distinct current admins have approved and `delay_secs` has passed.
+    pub fn create_proposal(ctx: Context<CreateProposal>, action: ProposalAction, delay_secs: u32) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let proposer = ctx.accounts.proposer.key();
+        require!(is_admin(market, proposer), ErrorCode::Unauthorized);
+        if matches!(action, ProposalAction::UpdateParams { .. } | ProposalAction::SetOraclePublishers { .. }) {
+            require!(delay_secs >= market.min_timelock_secs, ErrorCode::TimelockTooShort);
+        }
+        let now = Clock::get()?.unix_timestamp;
+
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.market = market.key();
+        proposal.proposal_id = market.proposal_count;
+        proposal.proposer = proposer;
+        proposal.action = action;
+        proposal.approvals = [Pubkey::default(); MAX_ADMINS];
+        proposal.num_approvals = 0;
+        proposal.eta = now + delay_secs as i64;
+        proposal.expires_at = proposal.eta + PROPOSAL_GRACE_SECS;
+        proposal.bump = ctx.bumps.proposal;
+        proposal.approve(market, proposer)?;
+        market.proposal_count = market.proposal_count.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        market.open_proposals = market.open_proposals.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(ProposalCreated {
+            market: proposal.market,
+            proposal: proposal.key(),
+            proposal_id: proposal.proposal_id,
+            proposer,
+            eta: proposal.eta,
+        });
+        Ok(())
+    }
+
+    /// Another admin approves in its own transaction; each key counts once.
+    pub fn approve_proposal(ctx: Context<ApproveProposal>) -> Result<()> {
+        let admin = ctx.accounts.admin.key();
+        require!(is_admin(&ctx.accounts.market, admin), ErrorCode::Unauthorized);
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.approve(&ctx.accounts.market, admin)?;
+        emit!(ProposalApproved {
+            proposal: proposal.key(),
+            admin,
+            approvals: proposal.num_approvals,
+        });
+        Ok(())
+    }
+
+    /// Permissionless once approved and past its ETA. Only approvals from keys that are still admins
+    /// count. The proposal account is closed to the proposer. WithdrawFees needs the fee accounts;
+    /// UpdateParams with a basket rebalance needs the StackDefinition; ResolveNavDispute needs the
+    /// fee vault accounts to refund a rejected NAV's bond, and the NavHistory to apply a valid one;
+    /// BeginShutdown needs the NavHistory in Twap mark mode; SetOraclePublishers needs the OracleSet.
+    pub fn execute_proposal(ctx: Context<ExecuteProposal>) -> Result<()> {
+        let proposal = &ctx.accounts.proposal;
+        require!(
+            proposal.current_approvals(&ctx.accounts.market) >= ctx.accounts.market.admin_threshold,
+            ErrorCode::NotEnoughSigners
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= proposal.eta, ErrorCode::TimelockNotExpired);
+        require!(now <= proposal.expires_at, ErrorCode::ProposalExpired);
+
+        match proposal.action.clone() {
+            ProposalAction::UpdateParams { params } => {
+                execute_params_update(&mut ctx.accounts.market, &params, ctx.accounts.stack_definition.as_mut(), now)?;
+            }
+            ProposalAction::Pause { paused } => ctx.accounts.market.paused = paused,
+            ProposalAction::SetPauseFlags { flags } => {
+                require!(flags & !PAUSE_ALL == 0, ErrorCode::InvalidPauseFlags);
+                ctx.accounts.market.pause_flags = flags;
+            }
+            ProposalAction::RotateAuthority { new_authority } => {
+                rotate_market_authority(&mut ctx.accounts.market, new_authority)?;
+            }
+            ProposalAction::AddAdmin { admin } => change_admin(&mut ctx.accounts.market, Pubkey::default(), admin)?,
+            ProposalAction::RemoveAdmin { admin } => change_admin(&mut ctx.accounts.market, admin, Pubkey::default())?,
+            ProposalAction::ReplaceAdmin { old_admin, new_admin } => {
+                require!(old_admin != Pubkey::default() && new_admin != Pubkey::default(), ErrorCode::InvalidAdminSet);
+                change_admin(&mut ctx.accounts.market, old_admin, new_admin)?;
+            }
+            ProposalAction::SetRole { role, holder } => set_market_role(&mut ctx.accounts.market, role, holder),
+            ProposalAction::SetFeeSplit { split } => {
+                require!(split.is_valid(), ErrorCode::InvalidFeeSplit);
+                ctx.accounts.market.fee_split = split;
+            }
+            ProposalAction::WithdrawFees { amount } => {
+                let a = &ctx.accounts;
+                FeeDistribution {
+                    token_program: a.token_program.as_ref().ok_or(ErrorCode::MissingProposalAccounts)?,
+                    fee_vault: a.fee_vault.as_ref().ok_or(ErrorCode::MissingProposalAccounts)?,
+                    market_vault_auth: a.market_vault_auth.as_ref().ok_or(ErrorCode::MissingProposalAccounts)?,
+                    treasury_ata: a.treasury_ata.as_ref().ok_or(ErrorCode::MissingProposalAccounts)?,
+                    insurance_ata: a.insurance_ata.as_ref().ok_or(ErrorCode::MissingProposalAccounts)?,
+                    oracle_operator_ata: a.oracle_operator_ata.as_ref().ok_or(ErrorCode::MissingProposalAccounts)?,
+                }
+                .distribute(&a.market, amount)?;
+            }
+            ProposalAction::ResolveNavDispute { nav, nav_valid } => {
+                let a = &mut *ctx.accounts;
+                require!(a.market.pending_nav.nav == nav, ErrorCode::PendingNavChanged);
+                NavDisputeResolution {
+                    token_program: a.token_program.as_ref(),
+                    fee_vault: a.fee_vault.as_ref(),
+                    market_vault_auth: a.market_vault_auth.as_ref(),
+                    disputer_refund_ata: a.disputer_refund_ata.as_ref(),
+                    nav_history: &a.nav_history,
+                }
+                .resolve(&mut a.market, nav_valid)?;
+            }
+            ProposalAction::ResetCircuitBreaker => reset_market_breaker(&mut ctx.accounts.market)?,
+            ProposalAction::ReviveOracle => revive_market_oracle(&mut ctx.accounts.market)?,
+            ProposalAction::SetReduceOnly { reduce_only } => {
+                set_market_reduce_only(&mut ctx.accounts.market, reduce_only)?;
+            }
+            ProposalAction::BeginShutdown => {
+                begin_market_shutdown(&mut ctx.accounts.market, &ctx.accounts.nav_history)?;
+            }
+            ProposalAction::SetOraclePublishers { publishers, quorum } => {
+                let set = ctx.accounts.oracle_set.as_mut().ok_or(ErrorCode::MissingProposalAccounts)?;
+                set.set_publishers(&publishers, quorum)?;
+            }
+        }
+
+        ctx.accounts.market.open_proposals = ctx.accounts.market.open_proposals.saturating_sub(1);
+        emit!(ProposalExecuted {
+            market: ctx.accounts.market.key(),
+            proposal: ctx.accounts.proposal.key(),
+            proposal_id: ctx.accounts.proposal.proposal_id,
+        });
+        Ok(())
+    }
+
+    /// The proposer (or, on a single-sig market, the authority) withdraws a proposal; rent returns
+    /// to the proposer.
+    pub fn cancel_proposal(ctx: Context<CancelProposal>) -> Result<()> {
+        let signer = ctx.accounts.signer.key();
+        let proposal = &ctx.accounts.proposal;
+        require!(
+            signer == proposal.proposer || require_authority(&ctx.accounts.market, signer).is_ok(),
+            ErrorCode::Unauthorized
+        );
+        ctx.accounts.market.open_proposals = ctx.accounts.market.open_proposals.saturating_sub(1);
+        emit!(ProposalCancelled {
+            market: proposal.market,
+            proposal: proposal.key(),
+            proposal_id: proposal.proposal_id,
+        });
         Ok(())
     }
 
     // Oracle posts NAV (scaled by market.price_decimals). Optional confidence gate.
+    // With dispute_window_secs > 0 the NAV is only proposed (PendingNav) and applied by finalize_nav.
     pub fn post_nav(ctx: Context<PostNav>, nav: u64, nav_confidence: Option<u64>) -> Result<()> {
         let market = &mut ctx.accounts.market;
-        require!(!market.paused, ErrorCode::MarketPaused);
+        ensure_not_paused(market, PAUSE_NAV)?;
         require_keys_eq!(market.oracle_authority, ctx.accounts.oracle_authority.key(), ErrorCode::Unauthorized);
+        require!(market.oracle_source == OracleSource::Authority, ErrorCode::WrongOracleSource);
+        require!(market.oracle_set == Pubkey::default(), ErrorCode::OracleSetActive);
 
-        // Circuit breaker window check
         let now = Clock::get()?.unix_timestamp;
-        if now < market.circuit_breaker_until {
-            return err!(ErrorCode::CircuitBreaker);
+        let market_key = market.key();
+        if market.dispute_window_secs == 0 {
+            return apply_nav(market, market_key, nav, nav_confidence, now, now, &ctx.accounts.nav_history);
         }
 
-        // Confidence (if configured and provided)
-        if market.max_confidence_bps > 0 {
-            if let Some(conf) = nav_confidence {
-                let conf_bps = ratio_bps_u128(conf as u128, (nav as u128).max(1))? as u16;
-                require!(conf_bps <= market.max_confidence_bps, ErrorCode::OracleConfidenceTooWide);
-            }
+        // Optimistic mode: a new proposal replaces an undisputed one (restarting the window)
+        require!(market.pending_nav.disputer == Pubkey::default(), ErrorCode::NavDisputed);
+        market.pending_nav = PendingNav {
+            nav,
+            confidence: nav_confidence.unwrap_or(0),
+            posted_ts: now,
+            disputer: Pubkey::default(),
+            bond: 0,
+        };
+        emit!(NavProposed {
+            market: market_key,
+            nav,
+            ts: now,
+            finalizes_at: now + market.dispute_window_secs as i64,
+        });
+        Ok(())
+    }
+
+    /// Challenge the pending NAV inside its dispute window by posting `dispute_bond` (quote mint)
+    /// into the fee vault. The NAV then waits for admin resolution.
+    pub fn dispute_nav(ctx: Context<DisputeNav>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let p = market.pending_nav;
+        require!(p.posted_ts != 0, ErrorCode::NoPendingNav);
+        require!(p.disputer == Pubkey::default(), ErrorCode::NavDisputed);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < p.posted_ts + market.dispute_window_secs as i64, ErrorCode::DisputeWindowClosed);
+
+        let bond = market.dispute_bond;
+        transfer_from_user(
+            &ctx.accounts.token_program,
+            &ctx.accounts.disputer_source,
+            &ctx.accounts.fee_vault,
+            &ctx.accounts.disputer,
+            bond,
+        )?;
+        market.bonds_held = market.bonds_held.checked_add(bond).ok_or(ErrorCode::MathOverflow)?;
+        market.pending_nav.disputer = ctx.accounts.disputer.key();
+        market.pending_nav.bond = bond;
+
+        emit!(NavDisputeRaised { market: market.key(), nav: p.nav, disputer: ctx.accounts.disputer.key(), bond });
+        Ok(())
+    }
+
+    /// Admin/multisig ruling on the pending NAV (disputed or not). `nav_valid = true` applies it and
+    /// slashes any bond into fees; `false` discards it and refunds the bond to the disputer.
+    /// Multisig markets resolve through a ResolveNavDispute proposal instead.
+    pub fn resolve_nav_dispute(ctx: Context<ResolveNavDispute>, nav_valid: bool) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        let a = &mut *ctx.accounts;
+        NavDisputeResolution {
+            token_program: Some(&a.token_program),
+            fee_vault: Some(&a.fee_vault),
+            market_vault_auth: Some(&a.market_vault_auth),
+            disputer_refund_ata: a.disputer_refund_ata.as_ref(),
+            nav_history: &a.nav_history,
         }
+        .resolve(&mut a.market, nav_valid)
+    }
 
-        // Jump limit check
-        if market.last_nav != 0 {
-            let old = market.last_nav as u128;
-            let newv = nav as u128;
-            let diff = if newv > old { newv - old } else { old - newv };
-            let jump_bps = ratio_bps_u128(diff, old.max(1))? as u16;
-            if jump_bps > market.max_nav_jump_bps {
-                // Trip circuit breaker for a short cool-off (PoC: 5 minutes)
-                market.circuit_breaker_until = now + 300;
-                return err!(ErrorCode::PriceJumpTooLarge);
-            }
+    /// Permissionless: apply an undisputed pending NAV once its dispute window has passed.
+    pub fn finalize_nav(ctx: Context<FinalizeNav>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        ensure_not_paused(market, PAUSE_NAV)?;
+        let p = market.pending_nav;
+        require!(p.posted_ts != 0, ErrorCode::NoPendingNav);
+        require!(p.disputer == Pubkey::default(), ErrorCode::NavDisputed);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= p.posted_ts + market.dispute_window_secs as i64, ErrorCode::DisputeWindowOpen);
+
+        // Stamped with the time it was posted, so a late finalize does not make an old NAV look fresh
+        market.pending_nav = PendingNav::default();
+        let market_key = market.key();
+        apply_nav(market, market_key, p.nav, Some(p.confidence), p.posted_ts, now, &ctx.accounts.nav_history)
+    }
+
+    /// Permissionless: once no NAV has been accepted for `price_stale_seconds * oracle_dead_multiple`,
+    /// mark the oracle dead and fix the emergency settlement NAV (last good NAV, or TWAP in Twap mode).
+    pub fn declare_oracle_dead(ctx: Context<DeclareOracleDead>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(!market.oracle_dead, ErrorCode::OracleDead);
+        require!(market.oracle_dead_multiple > 0, ErrorCode::OracleAlive);
+        require!(market.last_nav > 0, ErrorCode::PriceNotSet);
+
+        let now = Clock::get()?.unix_timestamp;
+        let dead_after = (market.price_stale_seconds as i64)
+            .checked_mul(market.oracle_dead_multiple as i64)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(now.saturating_sub(market.last_ts) > dead_after, ErrorCode::OracleAlive);
+
+        let settle_nav = mark_nav(market, &ctx.accounts.nav_history)?;
+        require!(settle_nav > 0, ErrorCode::PriceNotSet);
+        accrue_funding(market, now)?; // funding is frozen while the oracle is dead
+        market.oracle_dead = true;
+        market.dead_nav = settle_nav;
+        market.oracle_dead_ts = now;
+
+        emit!(OracleDeclaredDead {
+            market: market.key(),
+            settle_nav,
+            last_nav_ts: market.last_ts,
+            ts: now,
+        });
+        Ok(())
+    }
+
+    /// Leave the oracle-dead state once the feed is healthy again; trading resumes with the next NAV.
+    pub fn revive_oracle(ctx: Context<AdminMarketParams>) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        revive_market_oracle(&mut ctx.accounts.market)
+    }
+
+    /// Toggle reduce-only mode: no new deals, offers or size increases; closes, reductions,
+    /// margin changes and liquidations continue.
+    pub fn set_reduce_only(ctx: Context<AdminMarketParams>, reduce_only: bool) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        set_market_reduce_only(&mut ctx.accounts.market, reduce_only)
+    }
+
+    /// Begin winding the market down: freeze the final NAV and move to Settling (Settled if no deals
+    /// are open). An already fixed expiry NAV is kept; otherwise the final NAV is `dead_nav` if the
+    /// oracle is dead, or else the current mark at a fresh price.
+    pub fn begin_shutdown(ctx: Context<BeginShutdown>) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        begin_market_shutdown(&mut ctx.accounts.market, &ctx.accounts.nav_history)
+    }
+
+    /// Close a Settled market: the (emptied) fee vault, vault authority, market and its attached
+    /// oracle set / stack definition / NAV history accounts (each required when set); rent goes to
+    /// the market authority. Run `withdraw_fees` first; outstanding dispute bonds must be resolved
+    /// and open proposals executed or cancelled. Only rent moves, so this stays a direct authority
+    /// call on multisig markets too.
+    pub fn close_market(ctx: Context<CloseMarket>) -> Result<()> {
+        require_keys_eq!(ctx.accounts.authority.key(), ctx.accounts.market.authority, ErrorCode::Unauthorized);
+        let market = &ctx.accounts.market;
+        require!(market.status == MarketStatus::Settled, ErrorCode::MarketNotSettled);
+        require!(market.open_deals == 0 && market.bonds_held == 0, ErrorCode::MarketNotSettled);
+        require!(ctx.accounts.fee_vault.amount == 0, ErrorCode::FeeVaultNotEmpty);
+        require!(market.open_proposals == 0, ErrorCode::ProposalsOutstanding);
+        require!(
+            market.oracle_set == Pubkey::default() || ctx.accounts.oracle_set.is_some(),
+            ErrorCode::InvalidOracleSet
+        );
+        require!(
+            market.stack_definition == Pubkey::default() || ctx.accounts.stack_definition.is_some(),
+            ErrorCode::InvalidStackDefinition
+        );
+        require!(
+            market.nav_history == Pubkey::default() || ctx.accounts.nav_history.is_some(),
+            ErrorCode::InvalidNavHistory
+        );
+
+        let market_key = market.key();
+        let seeds: [&[u8]; 4] = [VERSION_SEED, b"mva", market_key.as_ref(), &[ctx.accounts.market_vault_auth.bump]];
+        let signer_groups = [&seeds[..]];
+        let cpi = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.fee_vault.to_account_info(),
+                destination: ctx.accounts.market_authority.to_account_info(),
+                authority: ctx.accounts.market_vault_auth.to_account_info(),
+            },
+            &signer_groups,
+        );
+        token::close_account(cpi)?;
+
+        emit!(MarketClosed { market: market_key, ts: Clock::get()?.unix_timestamp });
+        Ok(())
+    }
+
+    /// Create the market's NAV history ring buffer; once set, every accepted NAV is recorded in it.
+    /// Any admin may create it: it only records NAVs (switching the mark to TWAP is a timelocked param).
+    pub fn init_nav_history(ctx: Context<InitNavHistory>) -> Result<()> {
+        require!(is_admin(&ctx.accounts.market, ctx.accounts.authority.key()), ErrorCode::Unauthorized);
+        let mut h = ctx.accounts.nav_history.load_init()?;
+        h.market = ctx.accounts.market.key();
+        ctx.accounts.market.nav_history = ctx.accounts.nav_history.key();
+        Ok(())
+    }
+
+    /// Create the market's oracle set; from then on NAV is the median of fresh publisher submissions
+    /// (via `submit_nav`) once `quorum` is met, and single-authority `post_nav` is disabled.
+    /// Setup only (before the first accepted NAV).
+    pub fn init_oracle_set(ctx: Context<InitOracleSet>, publishers: Vec<Pubkey>, quorum: u8) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        ensure_market_setup(&ctx.accounts.market)?;
+        // Submissions older than price_stale_seconds are ignored, so 0 would leave no quorum
+        require!(ctx.accounts.market.price_stale_seconds > 0, ErrorCode::InvalidRiskParams);
+        let set = &mut ctx.accounts.oracle_set;
+        set.market = ctx.accounts.market.key();
+        set.bump = ctx.bumps.oracle_set;
+        set.set_publishers(&publishers, quorum)?;
+        ctx.accounts.market.oracle_set = set.key();
+        Ok(())
+    }
+
+    /// Replace the oracle set's publishers and quorum (clears all submissions). Setup only; a live
+    /// market changes publishers through a timelocked SetOraclePublishers proposal.
+    pub fn set_oracle_publishers(ctx: Context<AdminOracleSet>, publishers: Vec<Pubkey>, quorum: u8) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        ensure_market_setup(&ctx.accounts.market)?;
+        ctx.accounts.oracle_set.set_publishers(&publishers, quorum)
+    }
+
+    /// A publisher submits its NAV. When at least `quorum` submissions are fresh (within
+    /// price_stale_seconds), the median becomes the market NAV, subject to the usual
+    /// confidence / jump / circuit breaker gates on the aggregated value.
+    pub fn submit_nav(ctx: Context<SubmitNav>, nav: u64, nav_confidence: Option<u64>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let set = &mut ctx.accounts.oracle_set;
+        ensure_not_paused(market, PAUSE_NAV)?;
+        require!(market.oracle_source == OracleSource::Authority, ErrorCode::WrongOracleSource);
+
+        let now = Clock::get()?.unix_timestamp;
+        let i = set.publisher_index(&ctx.accounts.publisher.key()).ok_or(ErrorCode::Unauthorized)?;
+        set.submissions[i] = OracleSubmission { nav, confidence: nav_confidence.unwrap_or(0), ts: now };
+
+        emit!(NavSubmitted { market: market.key(), publisher: ctx.accounts.publisher.key(), nav, ts: now });
+
+        let fresh: Vec<OracleSubmission> = set.submissions[..set.num_publishers as usize]
+            .iter()
+            .filter(|s| s.ts != 0 && now - s.ts <= market.price_stale_seconds as i64)
+            .copied()
+            .collect();
+        if fresh.len() < set.quorum as usize {
+            return Ok(());
+        }
+        let agg_nav = median_u64(fresh.iter().map(|s| s.nav).collect());
+        let agg_conf = median_u64(fresh.iter().map(|s| s.confidence).collect());
+        let market_key = market.key();
+        apply_nav(market, market_key, agg_nav, Some(agg_conf), now, now, &ctx.accounts.nav_history)
+    }
+
+    /// Permissionless: pull NAV from the market's Pyth / Switchboard feed account. The feed price is
+    /// rescaled to price_decimals, must be fresh (price_stale_seconds), and goes through the same
+    /// confidence / jump / circuit breaker gates as post_nav.
+    pub fn refresh_nav_from_feed(ctx: Context<RefreshNavFromFeed>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        ensure_not_paused(market, PAUSE_NAV)?;
+
+        let data = ctx.accounts.feed.try_borrow_data()?;
+        let feed = match market.oracle_source {
+            OracleSource::Pyth => parse_pyth_price(&data)?,
+            OracleSource::Switchboard => parse_switchboard_price(&data)?,
+            OracleSource::Authority | OracleSource::Stack => return err!(ErrorCode::WrongOracleSource),
+        };
+        drop(data);
+
+        let now = Clock::get()?.unix_timestamp;
+        let age = now.saturating_sub(feed.publish_ts);
+        require!(age >= 0, ErrorCode::ClockWentBackwards);
+        require!((age as u64) <= market.price_stale_seconds as u64, ErrorCode::PriceStale);
+
+        let to_dec = market.price_decimals as u32;
+        let nav: u64 = scale_amount(feed.price, feed.decimals, to_dec)?
+            .try_into()
+            .map_err(|_| ErrorCode::MathOverflow)?;
+        require!(nav > 0, ErrorCode::InvalidFeed);
+        let conf: u64 = scale_amount(feed.conf, feed.decimals, to_dec)?
+            .try_into()
+            .map_err(|_| ErrorCode::MathOverflow)?;
+
+        let market_key = market.key();
+        apply_nav(market, market_key, nav, Some(conf), now, now, &ctx.accounts.nav_history)
+    }
+
+    /// Define the market's stack basket: constituent Pyth / Switchboard feeds with units per stack unit,
+    /// and a divisor. Switches the market's oracle source to Stack (NAV via compute_stack_nav).
+    /// Setup only; later basket changes are timelocked rebalances.
+    pub fn init_stack_definition(
+        ctx: Context<InitStackDefinition>,
+        constituents: Vec<Constituent>,
+        divisor: u64,
+    ) -> Result<()> {
+        require_authority(&ctx.accounts.market, ctx.accounts.authority.key())?;
+        ensure_market_setup(&ctx.accounts.market)?;
+        require!(
+            !constituents.is_empty() && constituents.len() <= MAX_CONSTITUENTS,
+            ErrorCode::InvalidStackDefinition
+        );
+        for c in constituents.iter() {
+            require!(
+                matches!(c.source, OracleSource::Pyth | OracleSource::Switchboard) && c.feed != Pubkey::default(),
+                ErrorCode::InvalidStackDefinition
+            );
+        }
+
+        let def = &mut ctx.accounts.stack_definition;
+        def.market = ctx.accounts.market.key();
+        def.num_constituents = constituents.len() as u8;
+        def.constituents = [Constituent::default(); MAX_CONSTITUENTS];
+        def.constituents[..constituents.len()].copy_from_slice(&constituents);
+        def.bump = ctx.bumps.stack_definition;
+        let units = def.constituents.map(|c| c.units);
+        def.rebalance(Some(units), Some(divisor))?;
+
+        let market = &mut ctx.accounts.market;
+        market.stack_definition = def.key();
+        market.oracle_source = OracleSource::Stack;
+        Ok(())
+    }
+
+    /// Permissionless: compute the stack NAV from constituent feeds (passed as remaining accounts, in
+    /// definition order): nav = Σ price_i * units_i / divisor, with confidence aggregated the same way.
+    /// Every constituent must be fresh; the result goes through the usual post_nav gates.
+    pub fn compute_stack_nav(ctx: Context<ComputeStackNav>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let def = &ctx.accounts.stack_definition;
+        ensure_not_paused(market, PAUSE_NAV)?;
+        require!(market.oracle_source == OracleSource::Stack, ErrorCode::WrongOracleSource);
+        let n = def.num_constituents as usize;
+        require!(ctx.remaining_accounts.len() == n, ErrorCode::InvalidStackDefinition);
+
+        let now = Clock::get()?.unix_timestamp;
+        let to_dec = market.price_decimals as u32;
+        let mut value: u128 = 0;
+        let mut conf_value: u128 = 0;
+        for (c, info) in def.constituents[..n].iter().zip(ctx.remaining_accounts.iter()) {
+            require_keys_eq!(info.key(), c.feed, ErrorCode::InvalidStackDefinition);
+            let data = info.try_borrow_data()?;
+            let feed = match c.source {
+                OracleSource::Pyth => parse_pyth_price(&data)?,
+                OracleSource::Switchboard => parse_switchboard_price(&data)?,
+                _ => return err!(ErrorCode::InvalidStackDefinition),
+            };
+            let age = now.saturating_sub(feed.publish_ts);
+            require!(age >= 0, ErrorCode::ClockWentBackwards);
+            require!((age as u64) <= market.price_stale_seconds as u64, ErrorCode::PriceStale);
+
+            let price = scale_amount(feed.price, feed.decimals, to_dec)?;
+            let conf = scale_amount(feed.conf, feed.decimals, to_dec)?;
+            value = value
+                .checked_add(price.checked_mul(c.units as u128).ok_or(ErrorCode::MathOverflow)?)
+                .ok_or(ErrorCode::MathOverflow)?;
+            conf_value = conf_value
+                .checked_add(conf.checked_mul(c.units as u128).ok_or(ErrorCode::MathOverflow)?)
+                .ok_or(ErrorCode::MathOverflow)?;
         }
 
-        market.last_nav = nav;
-        market.last_ts = now;
+        let nav: u64 = (value / def.divisor as u128).try_into().map_err(|_| ErrorCode::MathOverflow)?;
+        require!(nav > 0, ErrorCode::InvalidFeed);
+        let conf: u64 = (conf_value / def.divisor as u128).try_into().map_err(|_| ErrorCode::MathOverflow)?;
+
+        let market_key = market.key();
+        apply_nav(market, market_key, nav, Some(conf), now, now, &ctx.accounts.nav_history)
+    }
+
+    /// Oracle posts the funding premium (daily bps, signed) used in Premium funding mode.
+    pub fn post_funding_premium(ctx: Context<PostNav>, premium_bps: i32) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        ensure_not_paused(market, PAUSE_NAV)?;
+        require_keys_eq!(market.oracle_authority, ctx.accounts.oracle_authority.key(), ErrorCode::Unauthorized);
+        // Accrue under the previous premium before switching
+        accrue_funding(market, Clock::get()?.unix_timestamp)?;
+        market.funding_premium_bps = premium_bps;
+        Ok(())
+    }
 
-        emit!(NavPosted { market: market.key(), nav, ts: market.last_ts });
+    /// Permissionless crank: accrue the market funding index up to now.
+    pub fn update_funding(ctx: Context<UpdateFunding>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let now = Clock::get()?.unix_timestamp;
+        accrue_funding(market, now)?;
+        emit!(FundingUpdated {
+            market: market.key(),
+            cumulative_funding: market.cumulative_funding,
+            rate_bps: effective_funding_rate_bps(market),
+            ts: now,
+        });
         Ok(())
     }
 
@@ -183,31 +909,18 @@ pub mod synthetic_stack_futures {
         size: u64,
         long_deposit: u64,
         short_deposit: u64,
+        guard: Option<TradeGuard>,
     ) -> Result<()> {
-        let market = &ctx.accounts.market;
-        require!(!market.paused, ErrorCode::MarketPaused);
+        let market = &mut ctx.accounts.market;
+        ensure_not_paused(market, PAUSE_OPEN)?;
         require!(size > 0, ErrorCode::ZeroSize);
+        ensure_active(market)?;
+        ensure_not_expired(market)?;
         ensure_price_fresh(market)?;
+        check_trade_guard(&guard, market.last_nav)?;
 
-        // Entry price and notional (in quote mint decimals)
-        let entry_nav = market.last_nav;
-        let notional_q = notional_quote(size, entry_nav, market.price_decimals, market.quote_decimals)?;
-
-        // Fees & margin requirements
-        let open_fee_total = bps(notional_q, market.fee_bps)?;
-        let open_fee_each = open_fee_total / 2;
-        let im_required_each = bps(notional_q, market.initial_margin_bps)?;
-
-        require!(long_deposit as u128 >= im_required_each + open_fee_each, ErrorCode::InsufficientMargin);
-        require!(short_deposit as u128 >= im_required_each + open_fee_each, ErrorCode::InsufficientMargin);
-
-        // Leverage cap at open: based on total effective margin after fees
-        let effective_total_margin = (long_deposit as u128)
-            .saturating_add(short_deposit as u128)
-            .saturating_sub(open_fee_total);
-        require!(effective_total_margin > 0, ErrorCode::InsufficientMargin);
-        let lev_bps = ratio_bps_u128(notional_q, effective_total_margin)? as u16;
-        require!(lev_bps <= market.max_leverage_bps, ErrorCode::LeverageTooHigh);
+        // Entry price, notional, fees; IM and leverage checks
+        let OpenQuote { entry_nav, notional_q, open_fee_each } = quote_open(market, size, long_deposit, short_deposit)?;
 
         // Init deal PDA
         let deal = &mut ctx.accounts.deal;
@@ -221,7 +934,10 @@ pub mod synthetic_stack_futures {
         deal.long_margin = 0;
         deal.short_margin = 0;
         deal.client_order_id = client_order_id;
+        accrue_funding(market, Clock::get()?.unix_timestamp)?;
+        deal.funding_index = market.cumulative_funding;
         deal.bump = ctx.bumps.deal;
+        market.deal_opened()?;
 
         // Init deal vault auth PDA
         let dva = &mut ctx.accounts.deal_vault_auth;
@@ -284,838 +1000,3991 @@ pub mod synthetic_stack_futures {
         Ok(())
     }
 
-    /// Add margin for the LONG side.
-    pub fn add_margin_long(ctx: Context<AddMarginLong>, amount: u64) -> Result<()> {
-        require!(ctx.accounts.deal.is_open, ErrorCode::NotOpen);
-        require_keys_eq!(ctx.accounts.deal.long, ctx.accounts.long.key(), ErrorCode::Unauthorized);
-        transfer_from_user(
-            &ctx.accounts.token_program,
-            &ctx.accounts.long_source,
-            &ctx.accounts.long_margin_vault,
-            &ctx.accounts.long,
-            amount,
-        )?;
-        ctx.accounts.deal.long_margin = ctx.accounts.long_margin_vault.amount;
-        Ok(())
-    }
+    /// Open a deal from a maker's off-chain signed offer; the taker submits and signs.
+    /// The transaction must carry an ed25519 verify instruction (immediately before this one) over
+    /// `OFFER_DOMAIN || borsh(offer)` signed by `offer.maker`. The maker funds its side through an SPL
+    /// delegate approval to the market vault authority PDA. `offer.nonce` is single-use per maker and
+    /// doubles as the deal's client_order_id.
+    pub fn open_deal_from_offer(
+        ctx: Context<OpenDealFromOffer>,
+        offer: SignedOffer,
+        taker_deposit: u64,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        ensure_not_paused(market, PAUSE_OPEN)?;
+        require!(offer.size > 0, ErrorCode::ZeroSize);
+        require_keys_eq!(offer.market, market.key(), ErrorCode::OfferMismatch);
+        ensure_active(market)?;
+        ensure_not_expired(market)?;
+        ensure_price_fresh(market)?;
 
-    /// Add margin for the SHORT side.
-    pub fn add_margin_short(ctx: Context<AddMarginShort>, amount: u64) -> Result<()> {
-        require!(ctx.accounts.deal.is_open, ErrorCode::NotOpen);
-        require_keys_eq!(ctx.accounts.deal.short, ctx.accounts.short.key(), ErrorCode::Unauthorized);
-        transfer_from_user(
-            &ctx.accounts.token_program,
-            &ctx.accounts.short_source,
-            &ctx.accounts.short_margin_vault,
-            &ctx.accounts.short,
-            amount,
-        )?;
-        ctx.accounts.deal.short_margin = ctx.accounts.short_margin_vault.amount;
-        Ok(())
-    }
+        let now = Clock::get()?.unix_timestamp;
+        require!(now <= offer.expiry_ts, ErrorCode::OfferExpired);
+        require!(
+            market.last_nav >= offer.min_nav && market.last_nav <= offer.max_nav,
+            ErrorCode::NavOutOfRange
+        );
 
-    /// Close the deal at current NAV; pays both sides and closes vaults.
-    pub fn close_deal(ctx: Context<CloseDeal>) -> Result<()> {
-        let market = &ctx.accounts.market;
+        // Maker's signature over the offer terms
+        let msg = offer_message(&offer)?;
+        verify_ed25519_ix(&ctx.accounts.instructions_sysvar, &offer.maker, &msg)?;
+
+        let (long_deposit, short_deposit) = if offer.maker_is_long {
+            (offer.maker_deposit, taker_deposit)
+        } else {
+            (taker_deposit, offer.maker_deposit)
+        };
+        let OpenQuote { entry_nav, notional_q, open_fee_each } =
+            quote_open(market, offer.size, long_deposit, short_deposit)?;
+
+        // Burn the nonce
+        let nonce_acc = &mut ctx.accounts.offer_nonce;
+        nonce_acc.maker = offer.maker;
+        nonce_acc.nonce = offer.nonce;
+        nonce_acc.bump = ctx.bumps.offer_nonce;
+
+        // Init deal PDA
         let deal = &mut ctx.accounts.deal;
-        require!(deal.is_open, ErrorCode::NotOpen);
-        require!(!market.paused, ErrorCode::MarketPaused);
-        ensure_price_fresh(market)?;
+        require!(!deal.is_open, ErrorCode::AlreadyOpen);
+        deal.market = market.key();
+        deal.long = ctx.accounts.long.key();
+        deal.short = ctx.accounts.short.key();
+        deal.size = offer.size;
+        deal.entry_nav = entry_nav;
+        deal.is_open = true;
+        deal.long_margin = 0;
+        deal.short_margin = 0;
+        deal.client_order_id = offer.nonce;
+        accrue_funding(market, Clock::get()?.unix_timestamp)?;
+        deal.funding_index = market.cumulative_funding;
+        deal.bump = ctx.bumps.deal;
+        market.deal_opened()?;
 
-        let long_v = &ctx.accounts.long_margin_vault;
-        let short_v = &ctx.accounts.short_margin_vault;
-        let total_pool = (long_v.amount as u128) + (short_v.amount as u128);
-
-        // PnL for LONG in quote units (signed)
-        let pnl_long = pnl_quote(
-            deal.size,
-            deal.entry_nav,
-            market.last_nav,
-            market.price_decimals,
-            market.quote_decimals,
-        )?;
+        let dva = &mut ctx.accounts.deal_vault_auth;
+        dva.deal = deal.key();
+        dva.bump = ctx.bumps.deal_vault_auth;
 
-        // Desired payouts before clamping
-        let desired_long = (long_v.amount as i128) + pnl_long;
-        let long_payout = clamp_i128(desired_long, 0, total_pool as i128) as u128;
-        let short_payout = total_pool.saturating_sub(long_payout);
+        // Maker side moves under the mva delegate approval; taker side is signed directly
+        let (maker_source, maker_vault, taker_source, taker_vault) = if offer.maker_is_long {
+            (&ctx.accounts.long_source, &ctx.accounts.long_margin_vault, &ctx.accounts.short_source, &ctx.accounts.short_margin_vault)
+        } else {
+            (&ctx.accounts.short_source, &ctx.accounts.short_margin_vault, &ctx.accounts.long_source, &ctx.accounts.long_margin_vault)
+        };
+        transfer_signed_mva(
+            &ctx.accounts.token_program,
+            maker_source,
+            maker_vault,
+            &ctx.accounts.market_vault_auth,
+            &market.key(),
+            offer.maker_deposit,
+        )?;
+        transfer_from_user(&ctx.accounts.token_program, taker_source, taker_vault, &ctx.accounts.taker, taker_deposit)?;
 
-        // Payouts (drain vaults)
+        // Collect open fees from vaults to market fee_vault
         drain_to(
             &ctx.accounts.token_program,
             &ctx.accounts.long_margin_vault,
-            &ctx.accounts.long_payout_ata,
+            &ctx.accounts.fee_vault,
             &ctx.accounts.deal_vault_auth,
-            &deal,
-            long_payout as u64,
+            deal,
+            open_fee_each as u64,
         )?;
         drain_to(
             &ctx.accounts.token_program,
             &ctx.accounts.short_margin_vault,
-            &ctx.accounts.short_payout_ata,
+            &ctx.accounts.fee_vault,
             &ctx.accounts.deal_vault_auth,
-            &deal,
-            short_payout as u64,
+            deal,
+            open_fee_each as u64,
         )?;
 
-        // Close empty vaults back to market authority (receives rent)
-        close_signed_token_account(
-            &ctx.accounts.token_program,
-            &ctx.accounts.long_margin_vault,
-            &ctx.accounts.market_authority,
-            &ctx.accounts.deal_vault_auth,
-            &deal,
-        )?;
-        close_signed_token_account(
-            &ctx.accounts.token_program,
-            &ctx.accounts.short_margin_vault,
-            &ctx.accounts.market_authority,
-            &ctx.accounts.deal_vault_auth,
-            &deal,
-        )?;
-
-        deal.is_open = false;
+        ctx.accounts.long_margin_vault.reload()?;
+        ctx.accounts.short_margin_vault.reload()?;
+        deal.long_margin = ctx.accounts.long_margin_vault.amount;
+        deal.short_margin = ctx.accounts.short_margin_vault.amount;
 
-        emit!(DealClosed {
+        emit!(DealOpened {
             deal: deal.key(),
             market: deal.market,
-            long_payout: long_payout as u64,
-            short_payout: short_payout as u64,
-            close_nav: market.last_nav,
+            long: deal.long,
+            short: deal.short,
+            size: offer.size,
+            entry_nav,
+            notional_quote: notional_q as u64,
+            long_deposit,
+            short_deposit,
+            open_fee_each: open_fee_each as u64,
+        });
+        emit!(SignedOfferFilled {
+            deal: deal.key(),
+            maker: offer.maker,
+            taker: ctx.accounts.taker.key(),
+            nonce: offer.nonce,
         });
-
         Ok(())
     }
 
-    /// Liquidate if maintenance breached OR leverage > cap; pays bounty then settle like close.
-    pub fn liquidate(ctx: Context<Liquidate>) -> Result<()> {
-        let m = &mut ctx.accounts.market;
-        let d = &mut ctx.accounts.deal;
-        require!(d.is_open, ErrorCode::NotOpen);
-        require!(!m.paused, ErrorCode::MarketPaused);
-        ensure_price_fresh(m)?;
+    /// Post an on-chain offer: the maker escrows its margin and terms; any (or one allowed) taker can fill it.
+    /// The acceptable NAV band is `band_bps` around `last_nav` at the time the offer is made.
+    #[allow(clippy::too_many_arguments)]
+    pub fn make_offer(
+        ctx: Context<MakeOffer>,
+        offer_id: u64,
+        maker_is_long: bool,
+        size: u64,
+        band_bps: u16,
+        maker_deposit: u64,
+        expiry_ts: Option<i64>,
+        allowed_taker: Option<Pubkey>,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        ensure_not_paused(market, PAUSE_OPEN)?;
+        require!(size > 0, ErrorCode::ZeroSize);
+        require!(maker_deposit > 0, ErrorCode::InsufficientMargin);
+        require!(band_bps <= 10_000, ErrorCode::InvalidNavBand);
+        ensure_active(market)?;
+        ensure_not_expired(market)?;
+        ensure_price_fresh(market)?;
 
-        let notional_q = notional_quote(d.size, m.last_nav, m.price_decimals, m.quote_decimals)?;
-        let mm_required = bps(notional_q, m.maintenance_margin_bps.saturating_add(m.mm_buffer_bps))?;
+        let now = Clock::get()?.unix_timestamp;
+        if let Some(t) = expiry_ts {
+            require!(t > now, ErrorCode::OfferExpired);
+        }
 
-        // PnL & equity
-        let pnl_long = pnl_quote(d.size, d.entry_nav, m.last_nav, m.price_decimals, m.quote_decimals)?;
-        let long_eq = (ctx.accounts.long_margin_vault.amount as i128) + pnl_long;
-        let short_eq = (ctx.accounts.short_margin_vault.amount as i128) - pnl_long;
+        let offer = &mut ctx.accounts.offer;
+        offer.market = market.key();
+        offer.maker = ctx.accounts.maker.key();
+        offer.offer_id = offer_id;
+        offer.maker_is_long = maker_is_long;
+        offer.size = size;
+        offer.ref_nav = market.last_nav;
+        offer.band_bps = band_bps;
+        offer.maker_deposit = maker_deposit;
+        offer.expiry_ts = expiry_ts.unwrap_or(0);
+        offer.allowed_taker = allowed_taker.unwrap_or_default();
+        offer.bump = ctx.bumps.offer;
 
-        let pool = (ctx.accounts.long_margin_vault.amount as u128)
-            .saturating_add(ctx.accounts.short_margin_vault.amount as u128);
-        let lev_bps = if pool > 0 { ratio_bps_u128(notional_q, pool)? as u16 } else { u16::MAX };
-        let over_lev = lev_bps > m.max_leverage_bps;
+        transfer_from_user(
+            &ctx.accounts.token_program,
+            &ctx.accounts.maker_source,
+            &ctx.accounts.escrow,
+            &ctx.accounts.maker,
+            maker_deposit,
+        )?;
 
-        // Liquidatable if either equity < MM or over leverage
+        emit!(OfferMade {
+            offer: offer.key(),
+            market: offer.market,
+            maker: offer.maker,
+            maker_is_long,
+            size,
+            ref_nav: offer.ref_nav,
+            band_bps,
+            maker_deposit,
+            expiry_ts: offer.expiry_ts,
+            allowed_taker: offer.allowed_taker,
+        });
+        Ok(())
+    }
+
+    /// Fill an on-chain offer: the taker deposits its side and the maker's escrow funds the other side.
+    /// Same margin, fee and leverage checks as `open_deal`; `offer_id` becomes the deal's client_order_id.
+    pub fn take_offer(ctx: Context<TakeOffer>, taker_deposit: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let offer = &ctx.accounts.offer;
+        ensure_not_paused(market, PAUSE_OPEN)?;
+        ensure_active(market)?;
+        ensure_not_expired(market)?;
+        ensure_price_fresh(market)?;
+
+        let now = Clock::get()?.unix_timestamp;
+        require!(offer.expiry_ts == 0 || now <= offer.expiry_ts, ErrorCode::OfferExpired);
         require!(
-            long_eq < mm_required as i128 || short_eq < mm_required as i128 || over_lev,
-            ErrorCode::NotLiquidatable
+            offer.allowed_taker == Pubkey::default() || offer.allowed_taker == ctx.accounts.taker.key(),
+            ErrorCode::TakerNotAllowed
         );
+        require!(nav_within_band(market.last_nav, offer.ref_nav, offer.band_bps)?, ErrorCode::NavOutOfRange);
 
-        // Bounty from pool
-        let bounty = bps(pool, m.liquidator_bps)? as u64;
-        if bounty > 0 {
-            // pay from long first then short
-            let mut remaining = bounty;
-            let take_long = remaining.min(ctx.accounts.long_margin_vault.amount);
-            if take_long > 0 {
-                drain_to(
-                    &ctx.accounts.token_program,
-                    &ctx.accounts.long_margin_vault,
-                    &ctx.accounts.liquidator_ata,
-                    &ctx.accounts.deal_vault_auth,
-                    &d,
-                    take_long,
-                )?;
-                remaining -= take_long;
-            }
-            if remaining > 0 {
-                drain_to(
-                    &ctx.accounts.token_program,
-                    &ctx.accounts.short_margin_vault,
-                    &ctx.accounts.liquidator_ata,
-                    &ctx.accounts.deal_vault_auth,
-                    &d,
-                    remaining,
-                )?;
-            }
-        }
-
-        // Recompute pool after bounty
-        let long_amt = ctx.accounts.long_margin_vault.amount as u128;
-        let short_amt = ctx.accounts.short_margin_vault.amount as u128;
-        let new_pool = long_amt + short_amt;
+        let (long_deposit, short_deposit) = if offer.maker_is_long {
+            (offer.maker_deposit, taker_deposit)
+        } else {
+            (taker_deposit, offer.maker_deposit)
+        };
+        let OpenQuote { entry_nav, notional_q, open_fee_each } =
+            quote_open(market, offer.size, long_deposit, short_deposit)?;
 
-        let desired_long = (long_amt as i128) + pnl_long;
-        let long_payout = clamp_i128(desired_long, 0, new_pool as i128) as u128;
-        let short_payout = new_pool.saturating_sub(long_payout);
+        // Init deal PDA
+        let deal = &mut ctx.accounts.deal;
+        require!(!deal.is_open, ErrorCode::AlreadyOpen);
+        deal.market = market.key();
+        deal.long = ctx.accounts.long.key();
+        deal.short = ctx.accounts.short.key();
+        deal.size = offer.size;
+        deal.entry_nav = entry_nav;
+        deal.is_open = true;
+        deal.long_margin = 0;
+        deal.short_margin = 0;
+        deal.client_order_id = offer.offer_id;
+        accrue_funding(market, Clock::get()?.unix_timestamp)?;
+        deal.funding_index = market.cumulative_funding;
+        deal.bump = ctx.bumps.deal;
+        market.deal_opened()?;
 
-        if long_payout > 0 {
-            drain_to(
-                &ctx.accounts.token_program,
-                &ctx.accounts.long_margin_vault,
-                &ctx.accounts.long_payout_ata,
-                &ctx.accounts.deal_vault_auth,
-                &d,
-                long_payout as u64,
-            )?;
-        }
-        if short_payout > 0 {
-            drain_to(
-                &ctx.accounts.token_program,
-                &ctx.accounts.short_margin_vault,
-                &ctx.accounts.short_payout_ata,
-                &ctx.accounts.deal_vault_auth,
-                &d,
-                short_payout as u64,
-            )?;
-        }
+        let dva = &mut ctx.accounts.deal_vault_auth;
+        dva.deal = deal.key();
+        dva.bump = ctx.bumps.deal_vault_auth;
 
-        // Check depletion before closing
-        let depleted = ctx.accounts.long_margin_vault.amount == 0 || ctx.accounts.short_margin_vault.amount == 0;
+        // Escrow funds the maker's vault (escrow account closed, rent to maker); taker deposits directly
+        let (maker_vault, taker_vault) = if offer.maker_is_long {
+            (&ctx.accounts.long_margin_vault, &ctx.accounts.short_margin_vault)
+        } else {
+            (&ctx.accounts.short_margin_vault, &ctx.accounts.long_margin_vault)
+        };
+        let taker_source = &ctx.accounts.taker_source;
+        release_offer_escrow(
+            &ctx.accounts.token_program,
+            offer,
+            &ctx.accounts.escrow,
+            maker_vault,
+            &ctx.accounts.maker,
+        )?;
+        transfer_from_user(&ctx.accounts.token_program, taker_source, taker_vault, &ctx.accounts.taker, taker_deposit)?;
 
-        // Close vaults
-        close_signed_token_account(
+        // Collect open fees from vaults to market fee_vault
+        drain_to(
             &ctx.accounts.token_program,
             &ctx.accounts.long_margin_vault,
-            &ctx.accounts.market_authority,
+            &ctx.accounts.fee_vault,
             &ctx.accounts.deal_vault_auth,
-            &d,
+            deal,
+            open_fee_each as u64,
         )?;
-        close_signed_token_account(
+        drain_to(
             &ctx.accounts.token_program,
             &ctx.accounts.short_margin_vault,
-            &ctx.accounts.market_authority,
+            &ctx.accounts.fee_vault,
             &ctx.accounts.deal_vault_auth,
-            &d,
+            deal,
+            open_fee_each as u64,
         )?;
 
-        d.is_open = false;
+        ctx.accounts.long_margin_vault.reload()?;
+        ctx.accounts.short_margin_vault.reload()?;
+        deal.long_margin = ctx.accounts.long_margin_vault.amount;
+        deal.short_margin = ctx.accounts.short_margin_vault.amount;
 
-        // Socialized loss floor (PoC): if a vault depleted during liquidation, pause market
-        if depleted {
-            m.paused = true;
-        }
+        emit!(DealOpened {
+            deal: deal.key(),
+            market: deal.market,
+            long: deal.long,
+            short: deal.short,
+            size: offer.size,
+            entry_nav,
+            notional_quote: notional_q as u64,
+            long_deposit,
+            short_deposit,
+            open_fee_each: open_fee_each as u64,
+        });
+        emit!(OfferTaken {
+            offer: offer.key(),
+            deal: deal.key(),
+            maker: offer.maker,
+            taker: ctx.accounts.taker.key(),
+        });
+        Ok(())
+    }
 
-        emit!(DealLiquidated { deal: d.key(), market: d.market, bounty_paid: bounty, close_nav: m.last_nav });
+    /// Maker withdraws an unfilled offer; escrowed margin and rent are returned.
+    pub fn cancel_offer(ctx: Context<CancelOffer>) -> Result<()> {
+        let offer = &ctx.accounts.offer;
+        release_offer_escrow(
+            &ctx.accounts.token_program,
+            offer,
+            &ctx.accounts.escrow,
+            &ctx.accounts.maker_destination,
+            &ctx.accounts.maker.to_account_info(),
+        )?;
+        emit!(OfferCancelled { offer: offer.key(), maker: offer.maker });
         Ok(())
     }
 
-    /// Partial liquidation: move just enough to bring the under-margined side back to **initial** margin.
-    /// Rewards liquidator with bounty on the skimmed amount. Keeps deal open if successful.
-    pub fn liquidate_to_im(ctx: Context<PartialLiquidate>, max_bounty_take: u64) -> Result<()> {
+    /// Add margin for the LONG side.
+    pub fn add_margin_long(ctx: Context<AddMarginLong>, amount: u64) -> Result<()> {
+        require!(ctx.accounts.deal.is_open, ErrorCode::NotOpen);
+        require_keys_eq!(ctx.accounts.deal.long, ctx.accounts.long.key(), ErrorCode::Unauthorized);
+        ensure_not_paused(&ctx.accounts.market, PAUSE_MARGIN)?;
+        transfer_from_user(
+            &ctx.accounts.token_program,
+            &ctx.accounts.long_source,
+            &ctx.accounts.long_margin_vault,
+            &ctx.accounts.long,
+            amount,
+        )?;
+        ctx.accounts.deal.long_margin = ctx.accounts.long_margin_vault.amount;
+        Ok(())
+    }
+
+    /// Add margin for the SHORT side.
+    pub fn add_margin_short(ctx: Context<AddMarginShort>, amount: u64) -> Result<()> {
+        require!(ctx.accounts.deal.is_open, ErrorCode::NotOpen);
+        require_keys_eq!(ctx.accounts.deal.short, ctx.accounts.short.key(), ErrorCode::Unauthorized);
+        ensure_not_paused(&ctx.accounts.market, PAUSE_MARGIN)?;
+        transfer_from_user(
+            &ctx.accounts.token_program,
+            &ctx.accounts.short_source,
+            &ctx.accounts.short_margin_vault,
+            &ctx.accounts.short,
+            amount,
+        )?;
+        ctx.accounts.deal.short_margin = ctx.accounts.short_margin_vault.amount;
+        Ok(())
+    }
+
+    /// Withdraw excess margin for the LONG side, down to initial margin + mm buffer at a fresh NAV.
+    pub fn withdraw_margin_long(ctx: Context<WithdrawMarginLong>, amount: u64) -> Result<()> {
         let m = &mut ctx.accounts.market;
         let d = &mut ctx.accounts.deal;
+        accrue_funding(m, Clock::get()?.unix_timestamp)?;
         require!(d.is_open, ErrorCode::NotOpen);
-        require!(!m.paused, ErrorCode::MarketPaused);
+        ensure_not_paused(m, PAUSE_MARGIN)?;
+        ensure_not_settling(m)?;
+        ensure_not_expired(m)?;
         ensure_price_fresh(m)?;
 
-        let notional_q = notional_quote(d.size, m.last_nav, m.price_decimals, m.quote_decimals)?;
-        let im_required = bps(notional_q, m.initial_margin_bps)? as i128;
+        let mark = mark_nav(m, &ctx.accounts.nav_history)?;
+        let notional_q = notional_quote(d.size, mark, m.price_decimals, m.quote_decimals)?;
+        let pnl_long = deal_pnl_long(d, m, mark)?;
+        check_withdrawal(
+            m,
+            notional_q,
+            ctx.accounts.long_margin_vault.amount,
+            ctx.accounts.short_margin_vault.amount,
+            pnl_long,
+            amount,
+        )?;
 
-        let pnl_long = pnl_quote(d.size, d.entry_nav, m.last_nav, m.price_decimals, m.quote_decimals)?;
-        let long_eq = (ctx.accounts.long_margin_vault.amount as i128) + pnl_long;
-        let short_eq = (ctx.accounts.short_margin_vault.amount as i128) - pnl_long;
+        drain_to(
+            &ctx.accounts.token_program,
+            &ctx.accounts.long_margin_vault,
+            &ctx.accounts.long_destination,
+            &ctx.accounts.deal_vault_auth,
+            d,
+            amount,
+        )?;
+        ctx.accounts.long_margin_vault.reload()?;
+        d.long_margin = ctx.accounts.long_margin_vault.amount;
 
-        // Who's under IM?
-        let (under_is_long, deficit) = if long_eq < im_required {
-            (true, (im_required - long_eq) as u64)
-        } else if short_eq < im_required {
-            (false, (im_required - short_eq) as u64)
-        } else {
-            return err!(ErrorCode::NotLiquidatable);
-        };
+        emit!(MarginWithdrawn { deal: d.key(), owner: d.long, amount, remaining_margin: d.long_margin });
+        Ok(())
+    }
 
-        // Compute bounty and capped take
-        let bounty = bps(deficit as u128, m.liquidator_bps)? as u64;
-        let take_total = deficit.saturating_add(bounty).min(max_bounty_take);
+    /// Withdraw excess margin for the SHORT side, down to initial margin + mm buffer at a fresh NAV.
+    pub fn withdraw_margin_short(ctx: Context<WithdrawMarginShort>, amount: u64) -> Result<()> {
+        let m = &mut ctx.accounts.market;
+        let d = &mut ctx.accounts.deal;
+        accrue_funding(m, Clock::get()?.unix_timestamp)?;
+        require!(d.is_open, ErrorCode::NotOpen);
+        ensure_not_paused(m, PAUSE_MARGIN)?;
+        ensure_not_settling(m)?;
+        ensure_not_expired(m)?;
+        ensure_price_fresh(m)?;
 
-        if under_is_long {
-            // from short → liquidator (bounty) and → long (deficit)
-            let deficit_take = take_total.saturating_sub(bounty);
-            if bounty > 0 {
-                drain_to(&ctx.accounts.token_program, &ctx.accounts.short_margin_vault, &ctx.accounts.liquidator_ata, &ctx.accounts.deal_vault_auth, &d, bounty)?;
-            }
-            if deficit_take > 0 {
-                drain_to(&ctx.accounts.token_program, &ctx.accounts.short_margin_vault, &ctx.accounts.long_margin_vault, &ctx.accounts.deal_vault_auth, &d, deficit_take)?;
-            }
-        } else {
-            let deficit_take = take_total.saturating_sub(bounty);
-            if bounty > 0 {
-                drain_to(&ctx.accounts.token_program, &ctx.accounts.long_margin_vault, &ctx.accounts.liquidator_ata, &ctx.accounts.deal_vault_auth, &d, bounty)?;
-            }
-            if deficit_take > 0 {
-                drain_to(&ctx.accounts.token_program, &ctx.accounts.long_margin_vault, &ctx.accounts.short_margin_vault, &ctx.accounts.deal_vault_auth, &d, deficit_take)?;
-            }
-        }
+        let mark = mark_nav(m, &ctx.accounts.nav_history)?;
+        let notional_q = notional_quote(d.size, mark, m.price_decimals, m.quote_decimals)?;
+        let pnl_long = deal_pnl_long(d, m, mark)?;
+        check_withdrawal(
+            m,
+            notional_q,
+            ctx.accounts.short_margin_vault.amount,
+            ctx.accounts.long_margin_vault.amount,
+            -pnl_long,
+            amount,
+        )?;
 
-        // Refresh cached balances
-        d.long_margin = ctx.accounts.long_margin_vault.amount;
+        drain_to(
+            &ctx.accounts.token_program,
+            &ctx.accounts.short_margin_vault,
+            &ctx.accounts.short_destination,
+            &ctx.accounts.deal_vault_auth,
+            d,
+            amount,
+        )?;
+        ctx.accounts.short_margin_vault.reload()?;
         d.short_margin = ctx.accounts.short_margin_vault.amount;
 
-        // If still under IM after attempt, pause (PoC socialized loss guard)
-        let long_eq2 = (d.long_margin as i128) + pnl_long;
-        let short_eq2 = (d.short_margin as i128) - pnl_long;
-        if long_eq2 < im_required || short_eq2 < im_required {
-            m.paused = true;
-        }
-
+        emit!(MarginWithdrawn { deal: d.key(), owner: d.short, amount, remaining_margin: d.short_margin });
         Ok(())
     }
-}
 
-// ──────────────────────────────────────────────────────────────────────────────
-// Accounts
-// ──────────────────────────────────────────────────────────────────────────────
+    /// Close the deal at current NAV; pays both sides and closes vaults.
+    pub fn close_deal(ctx: Context<CloseDeal>, guard: Option<TradeGuard>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let deal = &mut ctx.accounts.deal;
+        accrue_funding(market, Clock::get()?.unix_timestamp)?;
+        require!(deal.is_open, ErrorCode::NotOpen);
+        ensure_not_paused(market, PAUSE_CLOSE)?;
+        ensure_not_settling(market)?;
+        ensure_not_expired(market)?;
+        ensure_price_fresh(market)?;
+        let mark = mark_nav(market, &ctx.accounts.nav_history)?;
+        check_trade_guard(&guard, mark)?;
+
+        // PnL for LONG in quote units (signed), net of accrued funding
+        let pnl_long = deal_pnl_long(deal, market, mark)?;
+
+        // Payouts (drain vaults) and close empty vaults back to market authority (receives rent)
+        let (long_payout, short_payout) = Settlement {
+            token_program: &ctx.accounts.token_program,
+            long_margin_vault: &mut ctx.accounts.long_margin_vault,
+            short_margin_vault: &mut ctx.accounts.short_margin_vault,
+            long_payout_ata: &ctx.accounts.long_payout_ata,
+            short_payout_ata: &ctx.accounts.short_payout_ata,
+            rent_destination: &ctx.accounts.market_authority,
+            deal_vault_auth: &ctx.accounts.deal_vault_auth,
+        }
+        .settle(deal, pnl_long)?;
 
-#[account]
-pub struct Market {
-    pub authority: Pubkey,
-    pub quote_mint: Pubkey,
-    pub oracle_authority: Pubkey,
-    pub stack_id: Pubkey,
+        deal.is_open = false;
+        market.deal_closed();
 
+        emit!(DealClosed {
+            deal: deal.key(),
+            market: deal.market,
+            long_payout,
+            short_payout,
+            close_nav: mark,
+        });
+
+        Ok(())
+    }
+
+    /// Add size to an open deal at current NAV (both parties sign). Entry NAV and funding index are
+    /// re-blended size-weighted; fee_bps is charged on the incremental notional; IM and leverage are
+    /// enforced on the enlarged notional.
+    pub fn increase_deal(
+        ctx: Context<IncreaseDeal>,
+        add_size: u64,
+        long_deposit: u64,
+        short_deposit: u64,
+        guard: Option<TradeGuard>,
+    ) -> Result<()> {
+        let m = &mut ctx.accounts.market;
+        let d = &mut ctx.accounts.deal;
+        accrue_funding(m, Clock::get()?.unix_timestamp)?;
+        require!(d.is_open, ErrorCode::NotOpen);
+        ensure_not_paused(m, PAUSE_OPEN)?;
+        require!(add_size > 0, ErrorCode::ZeroSize);
+        ensure_active(m)?;
+        ensure_not_expired(m)?;
+        ensure_price_fresh(m)?;
+        let nav = m.last_nav;
+        check_trade_guard(&guard, nav)?;
+        // The added size trades at last_nav; the enlarged position is margined at the mark
+        let mark = mark_nav(m, &ctx.accounts.nav_history)?;
+
+        // Fee on the incremental notional, split between the parties
+        let add_notional = notional_quote(add_size, nav, m.price_decimals, m.quote_decimals)?;
+        let fee_total = bps(add_notional, m.fee_bps)?;
+        let fee_each = fee_total / 2;
+
+        // Equity after deposits and fees, against IM on the enlarged notional
+        let new_size = d.size.checked_add(add_size).ok_or(ErrorCode::MathOverflow)?;
+        let total_notional = notional_quote(new_size, mark, m.price_decimals, m.quote_decimals)?;
+        let im_required = bps(total_notional, m.initial_margin_bps)? as i128;
+        let pnl_long = deal_pnl_long(d, m, mark)?;
+        let long_funds = (ctx.accounts.long_margin_vault.amount as u128) + (long_deposit as u128);
+        let short_funds = (ctx.accounts.short_margin_vault.amount as u128) + (short_deposit as u128);
+        require!(long_funds >= fee_each && short_funds >= fee_each, ErrorCode::InsufficientMargin);
+        let long_eq = (long_funds - fee_each) as i128 + pnl_long;
+        let short_eq = (short_funds - fee_each) as i128 - pnl_long;
+        require!(long_eq >= im_required && short_eq >= im_required, ErrorCode::InsufficientMargin);
+
+        // Leverage cap on the enlarged notional
+        let pool_after = (long_funds + short_funds).saturating_sub(fee_total);
+        require!(pool_after > 0, ErrorCode::InsufficientMargin);
+        let lev_bps = ratio_bps_u128(total_notional, pool_after)?;
+        require!(lev_bps <= m.max_leverage_bps as u128, ErrorCode::LeverageTooHigh);
+
+        // Size-weighted entry NAV and funding index
+        let new_entry = ((d.size as u128) * (d.entry_nav as u128) + (add_size as u128) * (nav as u128))
+            .checked_div(new_size as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let new_index = (d.size as i128)
+            .checked_mul(d.funding_index)
+            .and_then(|x| x.checked_add((add_size as i128).checked_mul(m.cumulative_funding)?))
+            .and_then(|x| x.checked_div(new_size as i128))
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Deposits in, fees out to the market fee vault
+        transfer_from_user(
+            &ctx.accounts.token_program,
+            &ctx.accounts.long_source,
+            &ctx.accounts.long_margin_vault,
+            &ctx.accounts.long,
+            long_deposit,
+        )?;
+        transfer_from_user(
+            &ctx.accounts.token_program,
+            &ctx.accounts.short_source,
+            &ctx.accounts.short_margin_vault,
+            &ctx.accounts.short,
+            short_deposit,
+        )?;
+        drain_to(
+            &ctx.accounts.token_program,
+            &ctx.accounts.long_margin_vault,
+            &ctx.accounts.fee_vault,
+            &ctx.accounts.deal_vault_auth,
+            d,
+            fee_each as u64,
+        )?;
+        drain_to(
+            &ctx.accounts.token_program,
+            &ctx.accounts.short_margin_vault,
+            &ctx.accounts.fee_vault,
+            &ctx.accounts.deal_vault_auth,
+            d,
+            fee_each as u64,
+        )?;
+
+        d.size = new_size;
+        d.entry_nav = new_entry as u64;
+        d.funding_index = new_index;
+        ctx.accounts.long_margin_vault.reload()?;
+        ctx.accounts.short_margin_vault.reload()?;
+        d.long_margin = ctx.accounts.long_margin_vault.amount;
+        d.short_margin = ctx.accounts.short_margin_vault.amount;
+
+        emit!(DealIncreased {
+            deal: d.key(),
+            market: d.market,
+            added_size: add_size,
+            new_size,
+            fill_nav: nav,
+            new_entry_nav: d.entry_nav,
+            long_deposit,
+            short_deposit,
+            fee_each: fee_each as u64,
+        });
+        Ok(())
+    }
+
+    /// Reduce deal size by `reduce_by` (both parties sign). Realizes the proportional PnL between
+    /// the margin vaults at current NAV; optionally releases margin freed on the smaller notional.
+    pub fn reduce_deal(
+        ctx: Context<ReduceDeal>,
+        reduce_by: u64,
+        release_margin: bool,
+        guard: Option<TradeGuard>,
+    ) -> Result<()> {
+        let m = &mut ctx.accounts.market;
+        let d = &mut ctx.accounts.deal;
+        accrue_funding(m, Clock::get()?.unix_timestamp)?;
+        require!(d.is_open, ErrorCode::NotOpen);
+        ensure_not_paused(m, PAUSE_CLOSE)?;
+        require!(reduce_by > 0, ErrorCode::ZeroSize);
+        require!(reduce_by < d.size, ErrorCode::ReduceExceedsSize);
+        ensure_not_settling(m)?;
+        ensure_not_expired(m)?;
+        ensure_price_fresh(m)?;
+        let nav = mark_nav(m, &ctx.accounts.nav_history)?;
+        check_trade_guard(&guard, nav)?;
+
+        // Realize PnL on the reduced slice
+        let realized = deal_pnl_long_for_size(d, m, nav, reduce_by)?;
+        let moved = transfer_realized_pnl(
+            &ctx.accounts.token_program,
+            &ctx.accounts.long_margin_vault,
+            &ctx.accounts.short_margin_vault,
+            &ctx.accounts.deal_vault_auth,
+            d,
+            realized,
+        )?;
+        d.size -= reduce_by;
+        ctx.accounts.long_margin_vault.reload()?;
+        ctx.accounts.short_margin_vault.reload()?;
+
+        // Optionally release margin no longer needed for the remaining size
+        let (mut long_released, mut short_released) = (0u64, 0u64);
+        if release_margin {
+            let long_amt = ctx.accounts.long_margin_vault.amount;
+            let short_amt = ctx.accounts.short_margin_vault.amount;
+            let notional_q = notional_quote(d.size, nav, m.price_decimals, m.quote_decimals)?;
+            let pnl_long = deal_pnl_long(d, m, nav)?;
+            long_released = free_margin(m, notional_q, long_amt, pnl_long)?;
+            short_released = free_margin(m, notional_q, short_amt, -pnl_long)?;
+
+            // Keep the remaining pool within the leverage cap
+            let pool = long_amt as u128 + short_amt as u128;
+            let headroom = pool.saturating_sub(min_pool_for_leverage(m, notional_q)?);
+            let total = long_released as u128 + short_released as u128;
+            if total > headroom {
+                long_released = ((long_released as u128) * headroom / total) as u64;
+                short_released = short_released.min((headroom - long_released as u128) as u64);
+            }
+
+            drain_to(
+                &ctx.accounts.token_program,
+                &ctx.accounts.long_margin_vault,
+                &ctx.accounts.long_payout_ata,
+                &ctx.accounts.deal_vault_auth,
+                d,
+                long_released,
+            )?;
+            drain_to(
+                &ctx.accounts.token_program,
+                &ctx.accounts.short_margin_vault,
+                &ctx.accounts.short_payout_ata,
+                &ctx.accounts.deal_vault_auth,
+                d,
+                short_released,
+            )?;
+            ctx.accounts.long_margin_vault.reload()?;
+            ctx.accounts.short_margin_vault.reload()?;
+        }
+
+        d.long_margin = ctx.accounts.long_margin_vault.amount;
+        d.short_margin = ctx.accounts.short_margin_vault.amount;
+
+        emit!(DealReduced {
+            deal: d.key(),
+            market: d.market,
+            reduced_by: reduce_by,
+            remaining_size: d.size,
+            realized_pnl_long: moved as i64,
+            nav,
+            long_released,
+            short_released,
+        });
+        Ok(())
+    }
+
+    /// Post a close request for the counterparty to accept. After `market.close_notice_secs`
+    /// the requester may close unilaterally via `force_close`.
+    /// - min_nav / max_nav: optional acceptable NAV band for settlement
+    /// - expiry_ts: time after which the request can no longer be used; at most
+    ///   `close_notice_secs + CLOSE_REQUEST_GRACE_SECS` ahead
+    /// - reduce_size: optional partial close (None = close the whole deal)
+    /// Each party has its own request PDA, so a request cannot block the counterparty's.
+    pub fn request_close(
+        ctx: Context<RequestClose>,
+        min_nav: Option<u64>,
+        max_nav: Option<u64>,
+        expiry_ts: i64,
+        reduce_size: Option<u64>,
+    ) -> Result<()> {
+        let deal = &ctx.accounts.deal;
+        require!(deal.is_open, ErrorCode::NotOpen);
+        let requester = ctx.accounts.requester.key();
+        require!(requester == deal.long || requester == deal.short, ErrorCode::Unauthorized);
+        if let (Some(lo), Some(hi)) = (min_nav, max_nav) {
+            require!(lo <= hi, ErrorCode::InvalidNavBand);
+        }
+        if let Some(sz) = reduce_size {
+            require!(sz > 0, ErrorCode::ZeroSize);
+            require!(sz < deal.size, ErrorCode::ReduceExceedsSize);
+        }
+        let now = Clock::get()?.unix_timestamp;
+        require!(expiry_ts > now, ErrorCode::CloseRequestExpired);
+        let max_expiry = now + ctx.accounts.market.close_notice_secs as i64 + CLOSE_REQUEST_GRACE_SECS;
+        require!(expiry_ts <= max_expiry, ErrorCode::CloseRequestExpiryTooFar);
+
+        let req = &mut ctx.accounts.close_request;
+        req.deal = deal.key();
+        req.requester = requester;
+        req.min_nav = min_nav;
+        req.max_nav = max_nav;
+        req.expiry_ts = expiry_ts;
+        req.reduce_size = reduce_size.unwrap_or(0);
+        req.created_ts = now;
+        req.bump = ctx.bumps.close_request;
+
+        emit!(CloseRequested {
+            deal: req.deal,
+            market: deal.market,
+            requester,
+            min_nav,
+            max_nav,
+            expiry_ts,
+            reduce_size: req.reduce_size,
+            unilateral_after: now + ctx.accounts.market.close_notice_secs as i64,
+        });
+        Ok(())
+    }
+
+    /// Withdraw a close request (requester only); rent returns to the requester.
+    pub fn cancel_close_request(ctx: Context<CancelCloseRequest>) -> Result<()> {
+        emit!(CloseRequestCancelled {
+            deal: ctx.accounts.close_request.deal,
+            requester: ctx.accounts.requester.key(),
+        });
+        Ok(())
+    }
+
+    /// Counterparty accepts a close request; settles at current NAV within the requested band
+    /// and the counterparty's own optional guard.
+    pub fn accept_close(ctx: Context<SettleCloseRequest>, guard: Option<TradeGuard>) -> Result<()> {
+        let req = &ctx.accounts.close_request;
+        let deal = &ctx.accounts.deal;
+        let counterparty = if req.requester == deal.long { deal.short } else { deal.long };
+        require_keys_eq!(ctx.accounts.signer.key(), counterparty, ErrorCode::Unauthorized);
+        settle_close_request(ctx, guard)
+    }
+
+    /// Requester closes unilaterally once the notice period has elapsed.
+    pub fn force_close(ctx: Context<SettleCloseRequest>) -> Result<()> {
+        let req = &ctx.accounts.close_request;
+        require_keys_eq!(ctx.accounts.signer.key(), req.requester, ErrorCode::Unauthorized);
+        let now = Clock::get()?.unix_timestamp;
+        let notice_until = req.created_ts.saturating_add(ctx.accounts.market.close_notice_secs as i64);
+        require!(now >= notice_until, ErrorCode::CloseNoticeNotElapsed);
+        settle_close_request(ctx, None)
+    }
+
+    /// Liquidate if maintenance breached OR leverage > cap; pays bounty then settle like close.
+    pub fn liquidate(ctx: Context<Liquidate>) -> Result<()> {
+        let m = &mut ctx.accounts.market;
+        let d = &mut ctx.accounts.deal;
+        accrue_funding(m, Clock::get()?.unix_timestamp)?;
+        require!(d.is_open, ErrorCode::NotOpen);
+        ensure_not_paused(m, PAUSE_LIQUIDATE)?;
+        ensure_not_settling(m)?;
+        ensure_not_expired(m)?;
+        ensure_price_fresh(m)?;
+        let mark = mark_nav(m, &ctx.accounts.nav_history)?;
+
+        let notional_q = notional_quote(d.size, mark, m.price_decimals, m.quote_decimals)?;
+        let mm_required = bps(notional_q, m.maintenance_margin_bps.saturating_add(m.mm_buffer_bps))?;
+
+        // PnL (net of funding) & equity
+        let pnl_long = deal_pnl_long(d, m, mark)?;
+        let long_eq = (ctx.accounts.long_margin_vault.amount as i128) + pnl_long;
+        let short_eq = (ctx.accounts.short_margin_vault.amount as i128) - pnl_long;
+
+        let pool = (ctx.accounts.long_margin_vault.amount as u128)
+            .saturating_add(ctx.accounts.short_margin_vault.amount as u128);
+        let lev_bps = if pool > 0 { ratio_bps_u128(notional_q, pool)? as u16 } else { u16::MAX };
+        let over_lev = lev_bps > m.max_leverage_bps;
+
+        // Liquidatable if either equity < MM or over leverage
+        require!(
+            long_eq < mm_required as i128 || short_eq < mm_required as i128 || over_lev,
+            ErrorCode::NotLiquidatable
+        );
+
+        // Bounty from pool
+        let bounty = bps(pool, m.liquidator_bps)? as u64;
+        if bounty > 0 {
+            // pay from long first then short
+            let mut remaining = bounty;
+            let take_long = remaining.min(ctx.accounts.long_margin_vault.amount);
+            if take_long > 0 {
+                drain_to(
+                    &ctx.accounts.token_program,
+                    &ctx.accounts.long_margin_vault,
+                    &ctx.accounts.liquidator_ata,
+                    &ctx.accounts.deal_vault_auth,
+                    &d,
+                    take_long,
+                )?;
+                remaining -= take_long;
+            }
+            if remaining > 0 {
+                drain_to(
+                    &ctx.accounts.token_program,
+                    &ctx.accounts.short_margin_vault,
+                    &ctx.accounts.liquidator_ata,
+                    &ctx.accounts.deal_vault_auth,
+                    &d,
+                    remaining,
+                )?;
+            }
+        }
+
+        // Recompute pool after bounty
+        ctx.accounts.long_margin_vault.reload()?;
+        ctx.accounts.short_margin_vault.reload()?;
+
+        // Check depletion before paying out: a side whose equity is wiped out leaves the pool short
+        let depleted = (ctx.accounts.long_margin_vault.amount as i128) + pnl_long <= 0
+            || (ctx.accounts.short_margin_vault.amount as i128) - pnl_long <= 0;
+
+        // Pay out and close vaults
+        Settlement {
+            token_program: &ctx.accounts.token_program,
+            long_margin_vault: &mut ctx.accounts.long_margin_vault,
+            short_margin_vault: &mut ctx.accounts.short_margin_vault,
+            long_payout_ata: &ctx.accounts.long_payout_ata,
+            short_payout_ata: &ctx.accounts.short_payout_ata,
+            rent_destination: &ctx.accounts.market_authority,
+            deal_vault_auth: &ctx.accounts.deal_vault_auth,
+        }
+        .settle(d, pnl_long)?;
+
+        d.is_open = false;
+        m.deal_closed();
+
+        // Socialized loss floor (PoC): if a vault depleted during liquidation, stop new exposure
+        // (PAUSE_OPEN) while closes, margin top-ups and further liquidations keep running
+        if depleted {
+            m.pause_flags |= PAUSE_OPEN;
+        }
+
+        emit!(DealLiquidated { deal: d.key(), market: d.market, bounty_paid: bounty, close_nav: mark });
+        Ok(())
+    }
+
+    /// Partial liquidation: move just enough to bring the under-margined side back to **initial** margin.
+    /// Rewards liquidator with bounty on the skimmed amount. Keeps deal open if successful.
+    pub fn liquidate_to_im(ctx: Context<PartialLiquidate>, max_bounty_take: u64) -> Result<()> {
+        let m = &mut ctx.accounts.market;
+        let d = &mut ctx.accounts.deal;
+        accrue_funding(m, Clock::get()?.unix_timestamp)?;
+        require!(d.is_open, ErrorCode::NotOpen);
+        ensure_not_paused(m, PAUSE_LIQUIDATE)?;
+        ensure_not_settling(m)?;
+        ensure_not_expired(m)?;
+        ensure_price_fresh(m)?;
+
+        let mark = mark_nav(m, &ctx.accounts.nav_history)?;
+        let notional_q = notional_quote(d.size, mark, m.price_decimals, m.quote_decimals)?;
+        let im_required = bps(notional_q, m.initial_margin_bps)? as i128;
+
+        let pnl_long = deal_pnl_long(d, m, mark)?;
+        let long_eq = (ctx.accounts.long_margin_vault.amount as i128) + pnl_long;
+        let short_eq = (ctx.accounts.short_margin_vault.amount as i128) - pnl_long;
+
+        // Who's under IM?
+        let (under_is_long, deficit) = if long_eq < im_required {
+            (true, (im_required - long_eq) as u64)
+        } else if short_eq < im_required {
+            (false, (im_required - short_eq) as u64)
+        } else {
+            return err!(ErrorCode::NotLiquidatable);
+        };
+
+        // Compute bounty and capped take
+        let bounty = bps(deficit as u128, m.liquidator_bps)? as u64;
+        let take_total = deficit.saturating_add(bounty).min(max_bounty_take);
+
+        if under_is_long {
+            // from short → liquidator (bounty) and → long (deficit)
+            let deficit_take = take_total.saturating_sub(bounty);
+            if bounty > 0 {
+                drain_to(&ctx.accounts.token_program, &ctx.accounts.short_margin_vault, &ctx.accounts.liquidator_ata, &ctx.accounts.deal_vault_auth, &d, bounty)?;
+            }
+            if deficit_take > 0 {
+                drain_to(&ctx.accounts.token_program, &ctx.accounts.short_margin_vault, &ctx.accounts.long_margin_vault, &ctx.accounts.deal_vault_auth, &d, deficit_take)?;
+            }
+        } else {
+            let deficit_take = take_total.saturating_sub(bounty);
+            if bounty > 0 {
+                drain_to(&ctx.accounts.token_program, &ctx.accounts.long_margin_vault, &ctx.accounts.liquidator_ata, &ctx.accounts.deal_vault_auth, &d, bounty)?;
+            }
+            if deficit_take > 0 {
+                drain_to(&ctx.accounts.token_program, &ctx.accounts.long_margin_vault, &ctx.accounts.short_margin_vault, &ctx.accounts.deal_vault_auth, &d, deficit_take)?;
+            }
+        }
+
+        // Refresh cached balances
+        ctx.accounts.long_margin_vault.reload()?;
+        ctx.accounts.short_margin_vault.reload()?;
+        d.long_margin = ctx.accounts.long_margin_vault.amount;
+        d.short_margin = ctx.accounts.short_margin_vault.amount;
+
+        // If still under IM after attempt, stop new opens (PoC socialized loss guard); the deal
+        // itself must stay liquidatable
+        let long_eq2 = (d.long_margin as i128) + pnl_long;
+        let short_eq2 = (d.short_margin as i128) - pnl_long;
+        if long_eq2 < im_required || short_eq2 < im_required {
+            m.pause_flags |= PAUSE_OPEN;
+        }
+
+        Ok(())
+    }
+
+    /// Permissionless keeper crank: settle a deal on an expired market at the final NAV.
+    pub fn settle_expired_deal(ctx: Context<SettleExpiredDeal>) -> Result<()> {
+        let m = &mut ctx.accounts.market;
+        let d = &mut ctx.accounts.deal;
+        accrue_funding(m, Clock::get()?.unix_timestamp)?;
+        require!(d.is_open, ErrorCode::NotOpen);
+        require!(m.expiry_ts != 0, ErrorCode::NotExpired);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= m.expiry_ts, ErrorCode::NotExpired);
+        require!(m.final_nav > 0, ErrorCode::FinalNavNotSet);
+
+        let pnl_long = deal_pnl_long(d, m, m.final_nav)?;
+        let (long_payout, short_payout) = Settlement {
+            token_program: &ctx.accounts.token_program,
+            long_margin_vault: &mut ctx.accounts.long_margin_vault,
+            short_margin_vault: &mut ctx.accounts.short_margin_vault,
+            long_payout_ata: &ctx.accounts.long_payout_ata,
+            short_payout_ata: &ctx.accounts.short_payout_ata,
+            rent_destination: &ctx.accounts.market_authority,
+            deal_vault_auth: &ctx.accounts.deal_vault_auth,
+        }
+        .settle(d, pnl_long)?;
+
+        d.is_open = false;
+
+        emit!(DealSettled {
+            deal: d.key(),
+            market: d.market,
+            keeper: ctx.accounts.keeper.key(),
+            long_payout,
+            short_payout,
+            settle_nav: m.final_nav,
+        });
+        m.deal_closed();
+        Ok(())
+    }
+
+    /// Permissionless keeper crank: settle an open deal at `dead_nav` while the market's oracle is dead,
+    /// so margin is never locked behind a stale price.
+    pub fn emergency_settle_deal(ctx: Context<EmergencySettleDeal>) -> Result<()> {
+        let m = &mut ctx.accounts.market;
+        let d = &mut ctx.accounts.deal;
+        accrue_funding(m, Clock::get()?.unix_timestamp)?;
+        require!(d.is_open, ErrorCode::NotOpen);
+        require!(m.oracle_dead, ErrorCode::OracleAlive);
+
+        let pnl_long = deal_pnl_long(d, m, m.dead_nav)?;
+        let (long_payout, short_payout) = Settlement {
+            token_program: &ctx.accounts.token_program,
+            long_margin_vault: &mut ctx.accounts.long_margin_vault,
+            short_margin_vault: &mut ctx.accounts.short_margin_vault,
+            long_payout_ata: &ctx.accounts.long_payout_ata,
+            short_payout_ata: &ctx.accounts.short_payout_ata,
+            rent_destination: &ctx.accounts.market_authority,
+            deal_vault_auth: &ctx.accounts.deal_vault_auth,
+        }
+        .settle(d, pnl_long)?;
+
+        d.is_open = false;
+
+        emit!(DealSettled {
+            deal: d.key(),
+            market: d.market,
+            keeper: ctx.accounts.keeper.key(),
+            long_payout,
+            short_payout,
+            settle_nav: m.dead_nav,
+        });
+        m.deal_closed();
+        Ok(())
+    }
+
+    /// Permissionless keeper crank: settle an open deal at the frozen final NAV of a market that is
+    /// shutting down. The last settlement moves the market to Settled.
+    pub fn settle_deal_at_final_nav(ctx: Context<SettleDealAtFinalNav>) -> Result<()> {
+        let m = &mut ctx.accounts.market;
+        let d = &mut ctx.accounts.deal;
+        accrue_funding(m, Clock::get()?.unix_timestamp)?;
+        require!(d.is_open, ErrorCode::NotOpen);
+        require!(m.status == MarketStatus::Settling, ErrorCode::MarketNotSettling);
+
+        let pnl_long = deal_pnl_long(d, m, m.final_nav)?;
+        let (long_payout, short_payout) = Settlement {
+            token_program: &ctx.accounts.token_program,
+            long_margin_vault: &mut ctx.accounts.long_margin_vault,
+            short_margin_vault: &mut ctx.accounts.short_margin_vault,
+            long_payout_ata: &ctx.accounts.long_payout_ata,
+            short_payout_ata: &ctx.accounts.short_payout_ata,
+            rent_destination: &ctx.accounts.market_authority,
+            deal_vault_auth: &ctx.accounts.deal_vault_auth,
+        }
+        .settle(d, pnl_long)?;
+
+        d.is_open = false;
+
+        emit!(DealSettled {
+            deal: d.key(),
+            market: d.market,
+            keeper: ctx.accounts.keeper.key(),
+            long_payout,
+            short_payout,
+            settle_nav: m.final_nav,
+        });
+        m.deal_closed();
+        if m.status == MarketStatus::Settled {
+            emit!(MarketStatusChanged {
+                market: m.key(),
+                status: m.status,
+                final_nav: m.final_nav,
+                open_deals: 0,
+                ts: Clock::get()?.unix_timestamp,
+            });
+        }
+        Ok(())
+    }
+}
+
+// ──────────────────────────────────────────────────────────────────────────────
+// Accounts
+// ──────────────────────────────────────────────────────────────────────────────
+
+#[account]
+pub struct Market {
+    pub authority: Pubkey,
+    pub quote_mint: Pubkey,
+    pub oracle_authority: Pubkey,
+    pub stack_id: Pubkey,
+
+    pub price_decimals: u8,
+    pub quote_decimals: u8,
+
+    pub initial_margin_bps: u16,
+    pub maintenance_margin_bps: u16,
+    pub fee_bps: u16,
+    pub liquidator_bps: u16,
+    pub price_stale_seconds: u32,
+
+    pub last_nav: u64,
+    pub last_ts: i64,
+
+    pub paused: bool, // full pause
+    pub bump: u8,
+    pub pause_flags: u8, // PAUSE_* bitmask
+
+    // New risk/admin
+    pub max_leverage_bps: u16,
+    pub max_nav_jump_bps: u16,
+    pub max_confidence_bps: u16, // 0 = disabled
+    pub circuit_breaker_until: i64,
+    pub mm_buffer_bps: u16,
+    pub close_notice_secs: u32,
+    pub circuit_breaker_secs: u32, // cool-off after a NAV jump trip
+    pub dispute_window_secs: u32, // 0 = NAV posts apply immediately
+    pub dispute_bond: u64, // quote units a disputer must post
+    pub pending_nav: PendingNav,
+    pub bonds_held: u64, // dispute bonds sitting in fee_vault
+    pub oracle_dead_multiple: u16, // x price_stale_seconds without a NAV => dead (0 = disabled)
+    pub oracle_dead: bool,
+    pub dead_nav: u64, // emergency settlement NAV fixed by declare_oracle_dead
+    pub oracle_dead_ts: i64,
+
+    // Lifecycle (shutdown freezes final_nav; Settled once open_deals reaches 0)
+    pub status: MarketStatus,
+    pub open_deals: u64,
+    pub breaker_nav: u64, // pending mark: last NAV rejected by the jump limit (0 once a post is accepted)
+    pub breaker_nav_ts: i64,
+
+    // Dated contracts (expiry_ts = 0 => perpetual)
+    pub expiry_ts: i64,
+    pub final_nav: u64,
+    pub final_nav_ts: i64,
+
+    // Funding (perpetuals)
+    pub funding_mode: FundingMode,
+    pub funding_rate_bps: i32, // fixed daily rate, positive => longs pay shorts
+    pub funding_premium_bps: i32, // last oracle-posted daily premium (Premium mode)
+    pub max_funding_rate_bps: u16, // 0 = uncapped
+    pub cumulative_funding: i128, // per-unit index, NAV units * FUNDING_INDEX_SCALE
+    pub last_funding_ts: i64,
+
+    pub admin_threshold: u8,
+    pub admins: [Pubkey; MAX_ADMINS],
+    pub proposal_count: u64, // next Proposal id
+    pub open_proposals: u32, // created but not yet executed or cancelled
+
+    // Roles (default => unassigned; the authority can always act)
+    pub guardian: Pubkey,     // pause, add pause flags, trip the breaker
+    pub risk_manager: Pubkey, // tighten margin params without timelock
+    pub fee_manager: Pubkey,  // withdraw fees
+
+    pub fee_split: FeeSplit,
+
+    // Multi-oracle aggregation (default => single oracle_authority via post_nav)
+    pub oracle_set: Pubkey,
+
+    // Mark price & NAV history (default nav_history => none recorded)
+    pub mark_mode: MarkMode,
+    pub twap_window_secs: u32,
+    pub nav_history: Pubkey,
+
+    // External price feed (used when oracle_source != Authority)
+    pub oracle_source: OracleSource,
+    pub oracle_feed: Pubkey,
+    pub stack_definition: Pubkey, // default => none
+
+    pub pending: Option<PendingParams>,
+    pub min_timelock_secs: u32, // floor for propose_market_params / UpdateParams proposal delays
+}
+
+impl Market {
+    pub const LEN: usize =
+        8 + // disc
+        32*4 + // keys
+        1 + 1 + // decimals
+        2*4 + // bps fields (im, mm, fee, liq)
+        4 + // stale secs
+        8 + 8 + // last_nav, last_ts
+        1 + 1 + 1 + // paused, bump, pause_flags
+        2 + 2 + 2 + 8 + 2 + // max_lev, max_jump, max_conf, breaker_until, mm_buffer
+        4 + // close_notice_secs
+        4 + 8 + 8 + // circuit_breaker_secs, breaker_nav, breaker_nav_ts
+        4 + 8 + PendingNav::LEN + 8 + // dispute window, bond, pending_nav, bonds_held
+        2 + 1 + 8 + 8 + // oracle_dead_multiple, oracle_dead, dead_nav, oracle_dead_ts
+        1 + 8 + // status, open_deals
+        8 + 8 + 8 + // expiry_ts, final_nav, final_nav_ts
+        1 + 4 + 4 + 2 + 16 + 8 + // funding mode, rate, premium, max rate, index, last ts
+        1 + // admin_threshold
+        (32*MAX_ADMINS) + // admins
+        8 + 4 + // proposal_count, open_proposals
+        32 * 3 + // guardian, risk_manager, fee_manager
+        FeeSplit::LEN + // fee_split
+        32 + // oracle_set
+        1 + 4 + 32 + // mark_mode, twap_window_secs, nav_history
+        1 + 32 + 32 + // oracle_source, oracle_feed, stack_definition
+        1 + PendingParams::MAX_LEN + // Option tag + pending (max)
+        4; // min_timelock_secs
+
+    pub fn admin_count(&self) -> usize {
+        self.admins.iter().filter(|k| **k != Pubkey::default()).count()
+    }
+
+    pub fn deal_opened(&mut self) -> Result<()> {
+        self.open_deals = self.open_deals.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// The last settlement of a Settling market marks it Settled.
+    pub fn deal_closed(&mut self) {
+        self.open_deals = self.open_deals.saturating_sub(1);
+        if self.status == MarketStatus::Settling && self.open_deals == 0 {
+            self.status = MarketStatus::Settled;
+        }
+    }
+}
+
+/// Latest NAV from one publisher (ts = 0 => never submitted).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct OracleSubmission {
+    pub nav: u64,
+    pub confidence: u64, // 0 = not provided
+    pub ts: i64,
+}
+impl OracleSubmission {
+    pub const LEN: usize = 8 + 8 + 8;
+}
+
+/// Publisher set for quorum/median NAV aggregation; submissions[i] belongs to publishers[i].
+#[account]
+pub struct OracleSet {
+    pub market: Pubkey,
+    pub num_publishers: u8,
+    pub quorum: u8,
+    pub publishers: [Pubkey; MAX_PUBLISHERS],
+    pub submissions: [OracleSubmission; MAX_PUBLISHERS],
+    pub bump: u8,
+}
+impl OracleSet {
+    pub const LEN: usize = 8 + 32 + 1 + 1 + 32 * MAX_PUBLISHERS + OracleSubmission::LEN * MAX_PUBLISHERS + 1;
+
+    fn set_publishers(&mut self, publishers: &[Pubkey], quorum: u8) -> Result<()> {
+        require!(
+            !publishers.is_empty() && publishers.len() <= MAX_PUBLISHERS,
+            ErrorCode::InvalidOracleSet
+        );
+        require!(quorum >= 1 && quorum as usize <= publishers.len(), ErrorCode::InvalidOracleSet);
+        for (i, p) in publishers.iter().enumerate() {
+            require!(*p != Pubkey::default(), ErrorCode::InvalidOracleSet);
+            require!(!publishers[..i].contains(p), ErrorCode::InvalidOracleSet);
+        }
+        self.publishers = [Pubkey::default(); MAX_PUBLISHERS];
+        self.publishers[..publishers.len()].copy_from_slice(publishers);
+        self.submissions = [OracleSubmission::default(); MAX_PUBLISHERS];
+        self.num_publishers = publishers.len() as u8;
+        self.quorum = quorum;
+        Ok(())
+    }
+
+    fn publisher_index(&self, key: &Pubkey) -> Option<usize> {
+        self.publishers[..self.num_publishers as usize].iter().position(|p| p == key)
+    }
+}
+
+#[zero_copy]
+pub struct NavSample {
+    pub nav: u64,
+    pub confidence: u64, // 0 = not provided
+    pub ts: i64,
+}
+
+/// Ring buffer of accepted NAVs for a market (newest at head - 1).
+#[account(zero_copy)]
+pub struct NavHistory {
+    pub market: Pubkey,
+    pub head: u32, // next write slot
+    pub count: u32,
+    pub samples: [NavSample; NAV_HISTORY_LEN],
+}
+impl NavHistory {
+    pub const LEN: usize = 8 + 32 + 4 + 4 + (8 + 8 + 8) * NAV_HISTORY_LEN;
+
+    fn push(&mut self, nav: u64, confidence: u64, ts: i64) {
+        self.samples[self.head as usize] = NavSample { nav, confidence, ts };
+        self.head = ((self.head as usize + 1) % NAV_HISTORY_LEN) as u32;
+        self.count = (self.count + 1).min(NAV_HISTORY_LEN as u32);
+    }
+
+    /// Time-weighted average over [now - window_secs, now]; each sample holds until the next one.
+    /// Falls back to the latest sample when the window is empty or zero. None if no samples.
+    fn twap(&self, now: i64, window_secs: u32) -> Option<u64> {
+        let start = now - window_secs as i64;
+        let mut end = now;
+        let mut acc: u128 = 0;
+        let mut weight: u128 = 0;
+        let mut latest = None;
+        for k in 0..self.count as usize {
+            let s = &self.samples[(self.head as usize + NAV_HISTORY_LEN - 1 - k) % NAV_HISTORY_LEN];
+            latest = latest.or(Some(s.nav));
+            let seg_start = s.ts.max(start);
+            if end > seg_start {
+                let w = (end - seg_start) as u128;
+                acc += s.nav as u128 * w;
+                weight += w;
+            }
+            if s.ts <= start {
+                break;
+            }
+            end = s.ts;
+        }
+        acc.checked_div(weight).map(|v| v as u64).or(latest)
+    }
+}
+
+/// One basket constituent: a Pyth / Switchboard feed and its units per stack unit (scaled 1e6).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct Constituent {
+    pub feed: Pubkey,
+    pub source: OracleSource,
+    pub units: u64,
+}
+impl Constituent {
+    pub const LEN: usize = 32 + 1 + 8;
+}
+
+/// On-chain basket backing the market's stack NAV.
+#[account]
+pub struct StackDefinition {
+    pub market: Pubkey,
+    pub divisor: u64, // scaled 1e6 (1_000_000 = 1.0)
+    pub num_constituents: u8,
+    pub constituents: [Constituent; MAX_CONSTITUENTS],
+    pub bump: u8,
+}
+impl StackDefinition {
+    pub const LEN: usize = 8 + 32 + 8 + 1 + Constituent::LEN * MAX_CONSTITUENTS + 1;
+
+    /// Set new units (by index; slots past num_constituents must be 0) and/or divisor.
+    fn rebalance(&mut self, units: Option<[u64; MAX_CONSTITUENTS]>, divisor: Option<u64>) -> Result<()> {
+        if let Some(u) = units {
+            let n = self.num_constituents as usize;
+            require!(u[n..].iter().all(|x| *x == 0), ErrorCode::InvalidStackDefinition);
+            require!(u[..n].iter().any(|x| *x > 0), ErrorCode::InvalidStackDefinition);
+            for (c, x) in self.constituents.iter_mut().zip(u.iter()) {
+                c.units = *x;
+            }
+        }
+        if let Some(d) = divisor {
+            require!(d > 0, ErrorCode::InvalidStackDefinition);
+            self.divisor = d;
+        }
+        Ok(())
+    }
+}
+
+/// Optimistically posted NAV awaiting its dispute window (posted_ts = 0 => none).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct PendingNav {
+    pub nav: u64,
+    pub confidence: u64,
+    pub posted_ts: i64,
+    pub disputer: Pubkey, // default = undisputed
+    pub bond: u64,
+}
+impl PendingNav {
+    pub const LEN: usize = 8 + 8 + 8 + 32 + 8;
+}
+
+/// Fee recipients are quote-mint token accounts; a zero-bps slot is ignored.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct FeeSplit {
+    pub treasury: Pubkey,
+    pub treasury_bps: u16,
+    pub insurance: Pubkey,
+    pub insurance_bps: u16,
+    pub oracle_operator: Pubkey,
+    pub oracle_operator_bps: u16,
+}
+impl FeeSplit {
+    pub const LEN: usize = 3 * (32 + 2);
+
+    pub fn is_valid(&self) -> bool {
+        let total = self.treasury_bps as u32 + self.insurance_bps as u32 + self.oracle_operator_bps as u32;
+        total == 10_000
+            && (self.treasury_bps == 0 || self.treasury != Pubkey::default())
+            && (self.insurance_bps == 0 || self.insurance != Pubkey::default())
+            && (self.oracle_operator_bps == 0 || self.oracle_operator != Pubkey::default())
+    }
+
+    /// (treasury, insurance, oracle operator) shares of `total`. Rounding dust goes to the largest
+    /// share, so it always lands in a configured (and account-constrained) recipient.
+    pub fn amounts(&self, total: u64) -> Result<(u64, u64, u64)> {
+        let shares = [self.treasury_bps, self.insurance_bps, self.oracle_operator_bps];
+        let mut amounts = [0u64; 3];
+        for (a, s) in amounts.iter_mut().zip(shares) {
+            *a = bps(total as u128, s)? as u64;
+        }
+        let dust = total - amounts.iter().sum::<u64>();
+        let largest = (0..3).max_by_key(|&i| (shares[i], std::cmp::Reverse(i))).unwrap_or(0);
+        amounts[largest] += dust;
+        Ok((amounts[0], amounts[1], amounts[2]))
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FundingMode {
+    #[default]
+    Disabled,
+    Fixed,   // market.funding_rate_bps
+    Premium, // oracle-posted funding_premium_bps
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OracleSource {
+    #[default]
+    Authority,   // post_nav / submit_nav
+    Pyth,        // oracle_feed = Pyth price account
+    Switchboard, // oracle_feed = Switchboard V2 aggregator
+    Stack,       // StackDefinition basket via compute_stack_nav
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MarketStatus {
+    #[default]
+    Active,
+    ReduceOnly, // no new deals, offers or size increases
+    Settling,   // final_nav frozen; deals settle via settle_deal_at_final_nav
+    Settled,    // no open deals; market can be closed
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MarkMode {
+    #[default]
+    Spot, // last_nav
+    Twap, // time-weighted over twap_window_secs from NavHistory
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct PendingParams {
+    pub params: MarketUpdateParams,
+    pub eta: i64,
+}
+impl PendingParams {
+    // rough upper bound for serialization (borsh)
+    pub const MAX_LEN: usize = MarketUpdateParams::MAX_LEN + 8;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Guardian,
+    RiskManager,
+    FeeManager,
+}
+
+/// Admin action executed through a multisig Proposal.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub enum ProposalAction {
+    UpdateParams { params: MarketUpdateParams },
+    Pause { paused: bool },
+    SetPauseFlags { flags: u8 },
+    RotateAuthority { new_authority: Pubkey },
+    AddAdmin { admin: Pubkey },
+    RemoveAdmin { admin: Pubkey },
+    ReplaceAdmin { old_admin: Pubkey, new_admin: Pubkey },
+    SetRole { role: Role, holder: Pubkey },
+    SetFeeSplit { split: FeeSplit },
+    WithdrawFees { amount: Option<u64> },
+    ResolveNavDispute { nav: u64, nav_valid: bool }, // `nav` must still be the pending NAV
+    ResetCircuitBreaker,
+    ReviveOracle,
+    SetReduceOnly { reduce_only: bool },
+    BeginShutdown,
+    SetOraclePublishers { publishers: Vec<Pubkey>, quorum: u8 }, // timelocked like UpdateParams
+}
+impl ProposalAction {
+    const SET_PUBLISHERS_LEN: usize = (4 + 32 * MAX_PUBLISHERS) + 1;
+    // variant tag + largest payload (UpdateParams or SetOraclePublishers)
+    pub const MAX_LEN: usize = 1 + if MarketUpdateParams::MAX_LEN > Self::SET_PUBLISHERS_LEN {
+        MarketUpdateParams::MAX_LEN
+    } else {
+        Self::SET_PUBLISHERS_LEN
+    };
+}
+
+/// Pending multisig action; admins approve in separate transactions (approvals deduplicated by key).
+#[account]
+pub struct Proposal {
+    pub market: Pubkey,
+    pub proposal_id: u64,
+    pub proposer: Pubkey,
+    pub action: ProposalAction,
+    pub approvals: [Pubkey; MAX_ADMINS],
+    pub num_approvals: u8,
+    pub eta: i64,        // earliest execution
+    pub expires_at: i64, // latest execution (eta + PROPOSAL_GRACE_SECS)
+    pub bump: u8,
+}
+impl Proposal {
+    pub const LEN: usize = 8 + 32 + 8 + 32 + ProposalAction::MAX_LEN + 32 * MAX_ADMINS + 1 + 8 + 8 + 1;
+
+    /// Record `admin`'s approval. Approvals from keys that have since left the admin set are dropped
+    /// first, so they neither count nor hold slots.
+    pub fn approve(&mut self, m: &Market, admin: Pubkey) -> Result<()> {
+        let mut current = [Pubkey::default(); MAX_ADMINS];
+        let mut n = 0;
+        for k in self.approvals[..self.num_approvals as usize].iter().filter(|k| is_admin(m, **k)) {
+            current[n] = *k;
+            n += 1;
+        }
+        self.approvals = current;
+        self.num_approvals = n as u8;
+        require!(!self.approvals[..n].contains(&admin), ErrorCode::AlreadyApproved);
+        require!(n < MAX_ADMINS, ErrorCode::AlreadyApproved);
+        self.approvals[n] = admin;
+        self.num_approvals += 1;
+        Ok(())
+    }
+
+    /// Approvals from keys that are still in the market's admin set.
+    pub fn current_approvals(&self, m: &Market) -> u8 {
+        self.approvals[..self.num_approvals as usize]
+            .iter()
+            .filter(|k| is_admin(m, **k))
+            .count() as u8
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct MarketUpdateParams {
+    pub oracle_authority: Option<Pubkey>,
+    pub initial_margin_bps: Option<u16>,
+    pub maintenance_margin_bps: Option<u16>,
+    pub fee_bps: Option<u16>,
+    pub liquidator_bps: Option<u16>,
+    pub price_stale_seconds: Option<u32>,
+
+    // new params
+    pub max_leverage_bps: Option<u16>,
+    pub max_nav_jump_bps: Option<u16>,
+    pub max_confidence_bps: Option<u16>,
+    pub mm_buffer_bps: Option<u16>,
+    pub admin_threshold: Option<u8>,
+    pub close_notice_secs: Option<u32>,
+    pub funding_mode: Option<FundingMode>,
+    pub funding_rate_bps: Option<i32>,
+    pub max_funding_rate_bps: Option<u16>,
+    pub mark_mode: Option<MarkMode>,
+    pub twap_window_secs: Option<u32>,
+    pub oracle_source: Option<OracleSource>,
+    pub oracle_feed: Option<Pubkey>,
+    pub circuit_breaker_secs: Option<u32>,
+    pub dispute_window_secs: Option<u32>,
+    pub dispute_bond: Option<u64>,
+    pub oracle_dead_multiple: Option<u16>,
+    pub min_timelock_secs: Option<u32>, // timelocked path only
+    // basket rebalance (timelocked path only); units by constituent index
+    pub stack_units: Option<[u64; MAX_CONSTITUENTS]>,
+    pub stack_divisor: Option<u64>,
+}
+impl MarketUpdateParams {
+    pub const MAX_LEN: usize =
+        (1+32) + // oracle_authority
+        (1+2)*4 + // four u16 options (im, mm, fee, liq)
+        (1+4) + // price_stale_seconds
+        (1+2)*4 + // new u16 options
+        (1+1) + // admin_threshold
+        (1+4) + // close_notice_secs
+        (1+1) + (1+4) + (1+2) + // funding mode, rate, max rate
+        (1+1) + (1+4) + // mark_mode, twap_window_secs
+        (1+1) + (1+32) + // oracle_source, oracle_feed
+        (1+4) + // circuit_breaker_secs
+        (1+4) + (1+8) + // dispute_window_secs, dispute_bond
+        (1+2) + // oracle_dead_multiple
+        (1+4) + // min_timelock_secs
+        (1+8*MAX_CONSTITUENTS) + (1+8); // stack_units, stack_divisor
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct MarketInitParams {
+    pub oracle_authority: Pubkey,
+    pub price_decimals: u8,
+    pub initial_margin_bps: u16,
+    pub maintenance_margin_bps: u16,
+    pub fee_bps: u16,
+    pub liquidator_bps: u16,
+    pub price_stale_seconds: u32,
+
+    // new
+    pub max_leverage_bps: u16,
+    pub max_nav_jump_bps: u16,
+    pub max_confidence_bps: Option<u16>,
+    pub mm_buffer_bps: Option<u16>,
+    pub admin_threshold: Option<u8>,
+    pub close_notice_secs: Option<u32>,
+    pub expiry_ts: Option<i64>, // None = perpetual
+    pub funding_mode: Option<FundingMode>,
+    pub funding_rate_bps: Option<i32>,
+    pub max_funding_rate_bps: Option<u16>,
+    pub mark_mode: Option<MarkMode>,
+    pub twap_window_secs: Option<u32>,
+    pub oracle_source: Option<OracleSource>,
+    pub oracle_feed: Option<Pubkey>,
+    pub circuit_breaker_secs: Option<u32>,
+    pub dispute_window_secs: Option<u32>,
+    pub dispute_bond: Option<u64>,
+    pub oracle_dead_multiple: Option<u16>,
+    pub min_timelock_secs: Option<u32>,
+}
+
+#[account]
+pub struct MarketVaultAuth {
+    pub market: Pubkey,
+    pub bump: u8,
+}
+impl MarketVaultAuth {
+    pub const LEN: usize = 8 + 32 + 1;
+}
+
+#[account]
+pub struct Deal {
+    pub market: Pubkey,
+    pub long: Pubkey,
+    pub short: Pubkey,
+
+    pub size: u64,
+    pub entry_nav: u64,
+    pub is_open: bool,
+
+    pub long_margin: u64,
+    pub short_margin: u64,
+
+    pub client_order_id: u64,
+    pub bump: u8,
+
+    // market.cumulative_funding at open
+    pub funding_index: i128,
+}
+impl Deal {
+    pub const LEN: usize = 8 + 32 + 32 + 32 + 8 + 8 + 1 + 8 + 8 + 8 + 1 + 16;
+}
+
+/// Caller-side slippage/deadline protection for trades that execute at `last_nav`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct TradeGuard {
+    pub min_nav: Option<u64>,
+    pub max_nav: Option<u64>,
+    pub deadline_ts: Option<i64>, // reject if executed after this time
+}
+
+/// Terms a maker signs off-chain; see `open_deal_from_offer`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SignedOffer {
+    pub market: Pubkey,
+    pub maker: Pubkey,
+    pub maker_is_long: bool,
+    pub size: u64,
+    pub min_nav: u64, // acceptable last_nav band at fill
+    pub max_nav: u64,
+    pub maker_deposit: u64,
+    pub expiry_ts: i64,
+    pub nonce: u64,
+}
+
+/// Marks a maker's signed-offer nonce as used (replay protection).
+#[account]
+pub struct OfferNonce {
+    pub maker: Pubkey,
+    pub nonce: u64,
+    pub bump: u8,
+}
+impl OfferNonce {
+    pub const LEN: usize = 8 + 32 + 8 + 1;
+}
+
+/// On-chain offer with the maker's margin held in an escrow ATA owned by this PDA.
+#[account]
+pub struct Offer {
+    pub market: Pubkey,
+    pub maker: Pubkey,
+    pub offer_id: u64,
+    pub maker_is_long: bool,
+    pub size: u64,
+    pub ref_nav: u64,  // last_nav when the offer was made
+    pub band_bps: u16, // fill allowed while last_nav is within ref_nav ± band_bps
+    pub maker_deposit: u64,
+    pub expiry_ts: i64,        // 0 = good until cancelled
+    pub allowed_taker: Pubkey, // default = open to anyone
+    pub bump: u8,
+}
+impl Offer {
+    pub const LEN: usize = 8 + 32 + 32 + 8 + 1 + 8 + 8 + 2 + 8 + 8 + 32 + 1;
+}
+
+#[account]
+pub struct DealVaultAuth {
+    pub deal: Pubkey,
+    pub bump: u8,
+}
+impl DealVaultAuth {
+    pub const LEN: usize = 8 + 32 + 1;
+}
+
+/// One outstanding close request per deal and counterparty.
+#[account]
+pub struct CloseRequest {
+    pub deal: Pubkey,
+    pub requester: Pubkey,
+    pub min_nav: Option<u64>,
+    pub max_nav: Option<u64>,
+    pub expiry_ts: i64,
+    pub reduce_size: u64, // 0 = full close
+    pub created_ts: i64,
+    pub bump: u8,
+}
+impl CloseRequest {
+    pub const LEN: usize = 8 + 32 + 32 + (1 + 8) * 2 + 8 + 8 + 8 + 1;
+}
+
+// ──────────────────────────────────────────────────────────────────────────────
+// Instruction Contexts
+// ──────────────────────────────────────────────────────────────────────────────
+
+#[derive(Accounts)]
+#[instruction(stack_id: Pubkey)]
+pub struct InitMarket<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = Market::LEN,
+        seeds = [VERSION_SEED, b"market", authority.key().as_ref(), quote_mint.key().as_ref(), stack_id.as_ref()],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = MarketVaultAuth::LEN,
+        seeds = [VERSION_SEED, b"mva", market.key().as_ref()],
+        bump
+    )]
+    pub market_vault_auth: Account<'info, MarketVaultAuth>,
+
+    #[account(
+        init,
+        payer = authority,
+        associated_token::mint = quote_mint,
+        associated_token::authority = market_vault_auth
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct CreateProposal<'info> {
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        init,
+        payer = proposer,
+        space = Proposal::LEN,
+        seeds = [VERSION_SEED, b"proposal", market.key().as_ref(), &market.proposal_count.to_le_bytes()],
+        bump
+    )]
+    pub proposal: Account<'info, Proposal>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveProposal<'info> {
+    pub admin: Signer<'info>,
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = market,
+        seeds = [VERSION_SEED, b"proposal", market.key().as_ref(), &proposal.proposal_id.to_le_bytes()],
+        bump = proposal.bump,
+    )]
+    pub proposal: Account<'info, Proposal>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteProposal<'info> {
+    pub executor: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = market,
+        has_one = proposer,
+        seeds = [VERSION_SEED, b"proposal", market.key().as_ref(), &proposal.proposal_id.to_le_bytes()],
+        bump = proposal.bump,
+    )]
+    pub proposal: Account<'info, Proposal>,
+    /// CHECK: receives the proposal rent
+    #[account(mut)]
+    pub proposer: UncheckedAccount<'info>,
+
+    // required when UpdateParams rebalances the stack basket
+    #[account(
+        mut,
+        has_one = market,
+        seeds = [VERSION_SEED, b"stack", market.key().as_ref()],
+        bump = stack_definition.bump,
+    )]
+    pub stack_definition: Option<Account<'info, StackDefinition>>,
+
+    // required for WithdrawFees
+    #[account(
+        seeds = [VERSION_SEED, b"mva", market.key().as_ref()],
+        bump = market_vault_auth.bump,
+    )]
+    pub market_vault_auth: Option<Account<'info, MarketVaultAuth>>,
+    #[account(
+        mut,
+        constraint = fee_vault.mint == market.quote_mint,
+        constraint = fee_vault.owner == market_vault_auth.as_ref().map(|a| a.key()).unwrap_or_default(),
+    )]
+    pub fee_vault: Option<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = treasury_ata.mint == market.quote_mint,
+        constraint = market.fee_split.treasury_bps == 0 || treasury_ata.key() == market.fee_split.treasury
+    )]
+    pub treasury_ata: Option<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = insurance_ata.mint == market.quote_mint,
+        constraint = market.fee_split.insurance_bps == 0 || insurance_ata.key() == market.fee_split.insurance
+    )]
+    pub insurance_ata: Option<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = oracle_operator_ata.mint == market.quote_mint,
+        constraint = market.fee_split.oracle_operator_bps == 0 || oracle_operator_ata.key() == market.fee_split.oracle_operator
+    )]
+    pub oracle_operator_ata: Option<Account<'info, TokenAccount>>,
+    pub token_program: Option<Program<'info, Token>>,
+
+    // required for ResolveNavDispute (bond refund / NavHistory markets) and a Twap BeginShutdown
+    #[account(
+        mut,
+        constraint = disputer_refund_ata.mint == market.quote_mint,
+        constraint = disputer_refund_ata.owner == market.pending_nav.disputer
+    )]
+    pub disputer_refund_ata: Option<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+
+    // required for SetOraclePublishers
+    #[account(
+        mut,
+        has_one = market,
+        seeds = [VERSION_SEED, b"oracle_set", market.key().as_ref()],
+        bump = oracle_set.bump,
+    )]
+    pub oracle_set: Option<Account<'info, OracleSet>>,
+}
+
+#[derive(Accounts)]
+pub struct CancelProposal<'info> {
+    pub signer: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = market,
+        has_one = proposer,
+        seeds = [VERSION_SEED, b"proposal", market.key().as_ref(), &proposal.proposal_id.to_le_bytes()],
+        bump = proposal.bump,
+    )]
+    pub proposal: Account<'info, Proposal>,
+    /// CHECK: receives the proposal rent
+    #[account(mut)]
+    pub proposer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AdminMarketToggle<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+}
+
+#[derive(Accounts)]
+pub struct AdminMarketParams<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+}
+
+#[derive(Accounts)]
+pub struct InitOracleSet<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        init,
+        payer = authority,
+        space = OracleSet::LEN,
+        seeds = [VERSION_SEED, b"oracle_set", market.key().as_ref()],
+        bump
+    )]
+    pub oracle_set: Account<'info, OracleSet>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AdminOracleSet<'info> {
+    pub authority: Signer<'info>,
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = market,
+        seeds = [VERSION_SEED, b"oracle_set", market.key().as_ref()],
+        bump = oracle_set.bump,
+    )]
+    pub oracle_set: Account<'info, OracleSet>,
+}
+
+#[derive(Accounts)]
+pub struct SubmitNav<'info> {
+    #[account(mut, constraint = market.oracle_set == oracle_set.key() @ ErrorCode::InvalidOracleSet)]
+    pub market: Account<'info, Market>,
+    #[account(mut, has_one = market)]
+    pub oracle_set: Account<'info, OracleSet>,
+    pub publisher: Signer<'info>,
+    // required once the market has a NavHistory
+    #[account(mut)]
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteMarketParams<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    // required when the pending params rebalance the stack basket
+    #[account(
+        mut,
+        has_one = market,
+        seeds = [VERSION_SEED, b"stack", market.key().as_ref()],
+        bump = stack_definition.bump,
+    )]
+    pub stack_definition: Option<Account<'info, StackDefinition>>,
+}
+
+#[derive(Accounts)]
+pub struct InitStackDefinition<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        init,
+        payer = authority,
+        space = StackDefinition::LEN,
+        seeds = [VERSION_SEED, b"stack", market.key().as_ref()],
+        bump
+    )]
+    pub stack_definition: Account<'info, StackDefinition>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ComputeStackNav<'info> {
+    #[account(mut, constraint = market.stack_definition == stack_definition.key() @ ErrorCode::InvalidStackDefinition)]
+    pub market: Account<'info, Market>,
+    #[account(has_one = market)]
+    pub stack_definition: Account<'info, StackDefinition>,
+    // required once the market has a NavHistory
+    #[account(mut)]
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateFunding<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFees<'info> {
+    pub authority: Signer<'info>,
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [VERSION_SEED, b"mva", market.key().as_ref()],
+        bump = market_vault_auth.bump,
+    )]
+    pub market_vault_auth: Account<'info, MarketVaultAuth>,
+
+    #[account(
+        mut,
+        constraint = fee_vault.mint == market.quote_mint,
+        constraint = fee_vault.owner == market_vault_auth.key(),
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+
+    // recipients (a zero-bps slot may be any quote-mint account; nothing is sent to it)
+    #[account(
+        mut,
+        constraint = treasury_ata.mint == market.quote_mint,
+        constraint = market.fee_split.treasury_bps == 0 || treasury_ata.key() == market.fee_split.treasury
+    )]
+    pub treasury_ata: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = insurance_ata.mint == market.quote_mint,
+        constraint = market.fee_split.insurance_bps == 0 || insurance_ata.key() == market.fee_split.insurance
+    )]
+    pub insurance_ata: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = oracle_operator_ata.mint == market.quote_mint,
+        constraint = market.fee_split.oracle_operator_bps == 0 || oracle_operator_ata.key() == market.fee_split.oracle_operator
+    )]
+    pub oracle_operator_ata: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct PostNav<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    pub oracle_authority: Signer<'info>,
+    // required once the market has a NavHistory
+    #[account(mut)]
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct RefreshNavFromFeed<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    /// CHECK: pinned by market.oracle_feed; layout parsed per market.oracle_source
+    #[account(address = market.oracle_feed @ ErrorCode::InvalidFeed)]
+    pub feed: UncheckedAccount<'info>,
+    // required once the market has a NavHistory
+    #[account(mut)]
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct DisputeNav<'info> {
+    pub disputer: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        constraint = disputer_source.mint == market.quote_mint,
+        constraint = disputer_source.owner == disputer.key()
+    )]
+    pub disputer_source: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = fee_vault.mint == market.quote_mint,
+        constraint = fee_vault.owner == market_vault_auth.key(),
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        seeds = [VERSION_SEED, b"mva", market.key().as_ref()],
+        bump = market_vault_auth.bump,
+    )]
+    pub market_vault_auth: Account<'info, MarketVaultAuth>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveNavDispute<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        constraint = fee_vault.mint == market.quote_mint,
+        constraint = fee_vault.owner == market_vault_auth.key(),
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        seeds = [VERSION_SEED, b"mva", market.key().as_ref()],
+        bump = market_vault_auth.bump,
+    )]
+    pub market_vault_auth: Account<'info, MarketVaultAuth>,
+    // required to refund a bond when the NAV is rejected
+    #[account(
+        mut,
+        constraint = disputer_refund_ata.mint == market.quote_mint,
+        constraint = disputer_refund_ata.owner == market.pending_nav.disputer
+    )]
+    pub disputer_refund_ata: Option<Account<'info, TokenAccount>>,
+    // required once the market has a NavHistory
+    #[account(mut)]
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct BeginShutdown<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    // required in Twap mark mode
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseMarket<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut, close = market_authority)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        close = market_authority,
+        seeds = [VERSION_SEED, b"mva", market.key().as_ref()],
+        bump = market_vault_auth.bump,
+    )]
+    pub market_vault_auth: Account<'info, MarketVaultAuth>,
+    #[account(
+        mut,
+        constraint = fee_vault.mint == market.quote_mint,
+        constraint = fee_vault.owner == market_vault_auth.key(),
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+
+    // attached accounts; required (and closed) when the market has them set
+    #[account(mut, close = market_authority, address = market.oracle_set)]
+    pub oracle_set: Option<Account<'info, OracleSet>>,
+    #[account(mut, close = market_authority, address = market.stack_definition)]
+    pub stack_definition: Option<Account<'info, StackDefinition>>,
+    #[account(mut, close = market_authority, address = market.nav_history)]
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+
+    /// CHECK: only used as destination for closed-account rent
+    #[account(mut, address = market.authority)]
+    pub market_authority: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct DeclareOracleDead<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    // required in Twap mark mode
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeNav<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    // required once the market has a NavHistory
+    #[account(mut)]
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct InitNavHistory<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        init,
+        payer = authority,
+        space = NavHistory::LEN,
+        seeds = [VERSION_SEED, b"nav_history", market.key().as_ref()],
+        bump
+    )]
+    pub nav_history: AccountLoader<'info, NavHistory>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(client_order_id: u64)]
+pub struct OpenDeal<'info> {
+    // parties
+    #[account(mut)]
+    pub long: Signer<'info>,
+    #[account(mut)]
+    pub short: Signer<'info>,
+
+    // market & mint
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    // users' source ATAs
+    #[account(
+        mut,
+        constraint = long_source.mint == quote_mint.key(),
+        constraint = long_source.owner == long.key()
+    )]
+    pub long_source: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = short_source.mint == quote_mint.key(),
+        constraint = short_source.owner == short.key()
+    )]
+    pub short_source: Account<'info, TokenAccount>,
+
+    // deal state
+    #[account(
+        init,
+        payer = long,
+        space = Deal::LEN,
+        seeds = [VERSION_SEED, b"deal", market.key().as_ref(), long.key().as_ref(), short.key().as_ref(), &client_order_id.to_le_bytes()],
+        bump
+    )]
+    pub deal: Account<'info, Deal>,
+
+    // deal vault authority
+    #[account(
+        init,
+        payer = long,
+        space = DealVaultAuth::LEN,
+        seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()],
+        bump
+    )]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+
+    // margin vaults (per-side PDA token accounts owned by the deal_vault_auth PDA)
+    #[account(
+        init,
+        payer = long,
+        token::mint = quote_mint,
+        token::authority = deal_vault_auth,
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = long,
+        token::mint = quote_mint,
+        token::authority = deal_vault_auth,
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    // fee vault belongs to the market vault auth
+    #[account(
+        mut,
+        constraint = fee_vault.mint == quote_mint.key(),
+        constraint = fee_vault.owner == market_vault_auth.key(),
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+
+    pub market_vault_auth: Account<'info, MarketVaultAuth>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer: SignedOffer)]
+pub struct OpenDealFromOffer<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    /// CHECK: authenticated by the ed25519 signature over `offer`
+    #[account(address = offer.maker)]
+    pub maker: UncheckedAccount<'info>,
+
+    /// CHECK: must be the maker or taker according to `offer.maker_is_long`
+    #[account(constraint = long.key() == if offer.maker_is_long { offer.maker } else { taker.key() } @ ErrorCode::OfferMismatch)]
+    pub long: UncheckedAccount<'info>,
+    /// CHECK: must be the maker or taker according to `offer.maker_is_long`
+    #[account(constraint = short.key() == if offer.maker_is_long { taker.key() } else { offer.maker } @ ErrorCode::OfferMismatch)]
+    pub short: UncheckedAccount<'info>,
+
+    // market & mint
+    #[account(mut)]
+    pub market: Box<Account<'info, Market>>,
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    // parties' source ATAs (maker's must approve market_vault_auth as delegate)
+    #[account(
+        mut,
+        constraint = long_source.mint == quote_mint.key(),
+        constraint = long_source.owner == long.key()
+    )]
+    pub long_source: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = short_source.mint == quote_mint.key(),
+        constraint = short_source.owner == short.key()
+    )]
+    pub short_source: Box<Account<'info, TokenAccount>>,
+
+    // deal state
+    #[account(
+        init,
+        payer = taker,
+        space = Deal::LEN,
+        seeds = [VERSION_SEED, b"deal", market.key().as_ref(), long.key().as_ref(), short.key().as_ref(), &offer.nonce.to_le_bytes()],
+        bump
+    )]
+    pub deal: Box<Account<'info, Deal>>,
+
+    #[account(
+        init,
+        payer = taker,
+        space = DealVaultAuth::LEN,
+        seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()],
+        bump
+    )]
+    pub deal_vault_auth: Box<Account<'info, DealVaultAuth>>,
+
+    // margin vaults (per-side PDA token accounts owned by the deal_vault_auth PDA)
+    #[account(
+        init,
+        payer = taker,
+        token::mint = quote_mint,
+        token::authority = deal_vault_auth,
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = taker,
+        token::mint = quote_mint,
+        token::authority = deal_vault_auth,
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Box<Account<'info, TokenAccount>>,
+
+    // replay protection
+    #[account(
+        init,
+        payer = taker,
+        space = OfferNonce::LEN,
+        seeds = [VERSION_SEED, b"offer_nonce", offer.maker.as_ref(), &offer.nonce.to_le_bytes()],
+        bump
+    )]
+    pub offer_nonce: Box<Account<'info, OfferNonce>>,
+
+    // fee vault belongs to the market vault auth
+    #[account(
+        mut,
+        constraint = fee_vault.mint == quote_mint.key(),
+        constraint = fee_vault.owner == market_vault_auth.key(),
+    )]
+    pub fee_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        seeds = [VERSION_SEED, b"mva", market.key().as_ref()],
+        bump = market_vault_auth.bump,
+    )]
+    pub market_vault_auth: Box<Account<'info, MarketVaultAuth>>,
+
+    /// CHECK: instructions sysvar, read for the ed25519 verify instruction
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions_sysvar: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(offer_id: u64)]
+pub struct MakeOffer<'info> {
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    pub market: Box<Account<'info, Market>>,
+    #[account(address = market.quote_mint)]
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = Offer::LEN,
+        seeds = [VERSION_SEED, b"offer", market.key().as_ref(), maker.key().as_ref(), &offer_id.to_le_bytes()],
+        bump
+    )]
+    pub offer: Box<Account<'info, Offer>>,
+
+    // escrow (owned by the offer PDA)
+    #[account(
+        init,
+        payer = maker,
+        associated_token::mint = quote_mint,
+        associated_token::authority = offer
+    )]
+    pub escrow: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = maker_source.mint == quote_mint.key(),
+        constraint = maker_source.owner == maker.key()
+    )]
+    pub maker_source: Box<Account<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct TakeOffer<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    /// CHECK: offer maker; receives escrow and offer rent
+    #[account(mut, address = offer.maker)]
+    pub maker: UncheckedAccount<'info>,
+
+    /// CHECK: must be the maker or taker according to `offer.maker_is_long`
+    #[account(constraint = long.key() == if offer.maker_is_long { offer.maker } else { taker.key() } @ ErrorCode::OfferMismatch)]
+    pub long: UncheckedAccount<'info>,
+    /// CHECK: must be the maker or taker according to `offer.maker_is_long`
+    #[account(constraint = short.key() == if offer.maker_is_long { taker.key() } else { offer.maker } @ ErrorCode::OfferMismatch)]
+    pub short: UncheckedAccount<'info>,
+
+    // market & mint
+    #[account(mut, address = offer.market @ ErrorCode::OfferMismatch)]
+    pub market: Box<Account<'info, Market>>,
+    #[account(address = market.quote_mint)]
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        close = maker,
+        seeds = [VERSION_SEED, b"offer", offer.market.as_ref(), offer.maker.as_ref(), &offer.offer_id.to_le_bytes()],
+        bump = offer.bump,
+    )]
+    pub offer: Box<Account<'info, Offer>>,
+    #[account(
+        mut,
+        associated_token::mint = quote_mint,
+        associated_token::authority = offer
+    )]
+    pub escrow: Box<Account<'info, TokenAccount>>,
+
+    // taker's source token account (the maker's side comes from escrow)
+    #[account(
+        mut,
+        constraint = taker_source.mint == quote_mint.key(),
+        constraint = taker_source.owner == taker.key()
+    )]
+    pub taker_source: Box<Account<'info, TokenAccount>>,
+
+    // deal state
+    #[account(
+        init,
+        payer = taker,
+        space = Deal::LEN,
+        seeds = [VERSION_SEED, b"deal", market.key().as_ref(), long.key().as_ref(), short.key().as_ref(), &offer.offer_id.to_le_bytes()],
+        bump
+    )]
+    pub deal: Box<Account<'info, Deal>>,
+
+    #[account(
+        init,
+        payer = taker,
+        space = DealVaultAuth::LEN,
+        seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()],
+        bump
+    )]
+    pub deal_vault_auth: Box<Account<'info, DealVaultAuth>>,
+
+    // margin vaults (per-side PDA token accounts owned by the deal_vault_auth PDA)
+    #[account(
+        init,
+        payer = taker,
+        token::mint = quote_mint,
+        token::authority = deal_vault_auth,
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = taker,
+        token::mint = quote_mint,
+        token::authority = deal_vault_auth,
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Box<Account<'info, TokenAccount>>,
+
+    // fee vault belongs to the market vault auth
+    #[account(
+        mut,
+        constraint = fee_vault.mint == quote_mint.key(),
+        constraint = fee_vault.owner == market_vault_auth.key(),
+    )]
+    pub fee_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        seeds = [VERSION_SEED, b"mva", market.key().as_ref()],
+        bump = market_vault_auth.bump,
+    )]
+    pub market_vault_auth: Box<Account<'info, MarketVaultAuth>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOffer<'info> {
+    #[account(mut)]
+    pub maker: Signer<'info>,
+
+    #[account(
+        mut,
+        close = maker,
+        has_one = maker,
+        seeds = [VERSION_SEED, b"offer", offer.market.as_ref(), offer.maker.as_ref(), &offer.offer_id.to_le_bytes()],
+        bump = offer.bump,
+    )]
+    pub offer: Account<'info, Offer>,
+    #[account(
+        mut,
+        constraint = escrow.owner == offer.key(),
+    )]
+    pub escrow: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_destination.mint == escrow.mint,
+        constraint = maker_destination.owner == maker.key()
+    )]
+    pub maker_destination: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct AddMarginLong<'info> {
+    #[account(mut)]
+    pub long: Signer<'info>,
+    #[account(mut, has_one = long, has_one = market)]
+    pub deal: Account<'info, Deal>,
+    pub market: Account<'info, Market>,
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = long_source.mint == quote_mint.key(),
+        constraint = long_source.owner == long.key()
+    )]
+    pub long_source: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct AddMarginShort<'info> {
+    #[account(mut)]
+    pub short: Signer<'info>,
+    #[account(mut, has_one = short, has_one = market)]
+    pub deal: Account<'info, Deal>,
+    pub market: Account<'info, Market>,
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = short_source.mint == quote_mint.key(),
+        constraint = short_source.owner == short.key()
+    )]
+    pub short_source: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawMarginLong<'info> {
+    pub long: Signer<'info>,
+    #[account(mut, has_one = long, has_one = market)]
+    pub deal: Account<'info, Deal>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+    // counterparty vault (read-only) for the pool leverage check
+    #[account(
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = long_destination.mint == quote_mint.key(),
+        constraint = long_destination.owner == long.key()
+    )]
+    pub long_destination: Account<'info, TokenAccount>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+
+    // required when market.mark_mode = Twap
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawMarginShort<'info> {
+    pub short: Signer<'info>,
+    #[account(mut, has_one = short, has_one = market)]
+    pub deal: Account<'info, Deal>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+    // counterparty vault (read-only) for the pool leverage check
+    #[account(
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = short_destination.mint == quote_mint.key(),
+        constraint = short_destination.owner == short.key()
+    )]
+    pub short_destination: Account<'info, TokenAccount>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+
+    // required when market.mark_mode = Twap
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseDeal<'info> {
+    #[account(mut)]
+    pub long: Signer<'info>,
+    #[account(mut)]
+    pub short: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(mut, has_one = market)]
+    pub deal: Account<'info, Deal>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    // vaults
+    #[account(
+        mut,
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    // payouts
+    #[account(mut, constraint = long_payout_ata.mint == quote_mint.key(), constraint = long_payout_ata.owner == long.key())]
+    pub long_payout_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = short_payout_ata.mint == quote_mint.key(), constraint = short_payout_ata.owner == short.key())]
+    pub short_payout_ata: Account<'info, TokenAccount>,
+
+    /// CHECK: only used as destination for close_account rent
+    #[account(mut, address = market.authority)]
+    pub market_authority: UncheckedAccount<'info>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+
+    // required when market.mark_mode = Twap
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct IncreaseDeal<'info> {
+    pub long: Signer<'info>,
+    pub short: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(mut, has_one = market, has_one = long, has_one = short)]
+    pub deal: Account<'info, Deal>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    // users' source ATAs
+    #[account(
+        mut,
+        constraint = long_source.mint == quote_mint.key(),
+        constraint = long_source.owner == long.key()
+    )]
+    pub long_source: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = short_source.mint == quote_mint.key(),
+        constraint = short_source.owner == short.key()
+    )]
+    pub short_source: Account<'info, TokenAccount>,
+
+    // vaults
+    #[account(
+        mut,
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    // fee vault belongs to the market vault auth
+    #[account(
+        mut,
+        constraint = fee_vault.mint == quote_mint.key(),
+        constraint = fee_vault.owner == market_vault_auth.key(),
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        seeds = [VERSION_SEED, b"mva", market.key().as_ref()],
+        bump = market_vault_auth.bump,
+    )]
+    pub market_vault_auth: Account<'info, MarketVaultAuth>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+
+    // required when market.mark_mode = Twap
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct ReduceDeal<'info> {
+    pub long: Signer<'info>,
+    pub short: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(mut, has_one = market, has_one = long, has_one = short)]
+    pub deal: Account<'info, Deal>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    // vaults
+    #[account(
+        mut,
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    // released margin destinations
+    #[account(mut, constraint = long_payout_ata.mint == quote_mint.key(), constraint = long_payout_ata.owner == long.key())]
+    pub long_payout_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = short_payout_ata.mint == quote_mint.key(), constraint = short_payout_ata.owner == short.key())]
+    pub short_payout_ata: Account<'info, TokenAccount>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+
+    // required when market.mark_mode = Twap
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct RequestClose<'info> {
+    #[account(mut)]
+    pub requester: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+    #[account(has_one = market)]
+    pub deal: Account<'info, Deal>,
+
+    #[account(
+        init,
+        payer = requester,
+        space = CloseRequest::LEN,
+        seeds = [VERSION_SEED, b"close_req", deal.key().as_ref(), requester.key().as_ref()],
+        bump
+    )]
+    pub close_request: Account<'info, CloseRequest>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelCloseRequest<'info> {
+    #[account(mut)]
+    pub requester: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = requester,
+        close = requester,
+        seeds = [VERSION_SEED, b"close_req", close_request.deal.as_ref(), requester.key().as_ref()],
+        bump = close_request.bump
+    )]
+    pub close_request: Account<'info, CloseRequest>,
+}
+
+#[derive(Accounts)]
+pub struct SettleCloseRequest<'info> {
+    // counterparty (accept_close) or requester (force_close)
+    pub signer: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(mut, has_one = market)]
+    pub deal: Account<'info, Deal>,
+
+    #[account(
+        mut,
+        has_one = deal,
+        close = requester,
+        seeds = [VERSION_SEED, b"close_req", deal.key().as_ref(), close_request.requester.as_ref()],
+        bump = close_request.bump
+    )]
+    pub close_request: Account<'info, CloseRequest>,
+
+    /// CHECK: only receives the close request rent
+    #[account(mut, address = close_request.requester)]
+    pub requester: UncheckedAccount<'info>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    // vaults
+    #[account(
+        mut,
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    // payouts
+    #[account(mut, constraint = long_payout_ata.mint == quote_mint.key(), constraint = long_payout_ata.owner == deal.long)]
+    pub long_payout_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = short_payout_ata.mint == quote_mint.key(), constraint = short_payout_ata.owner == deal.short)]
+    pub short_payout_ata: Account<'info, TokenAccount>,
+
+    /// CHECK: only used as destination for close_account rent
+    #[account(mut, address = market.authority)]
+    pub market_authority: UncheckedAccount<'info>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+
+    // required when market.mark_mode = Twap
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct SettleExpiredDeal<'info> {
+    pub keeper: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(mut, has_one = market)]
+    pub deal: Account<'info, Deal>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    // vaults
+    #[account(
+        mut,
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    // payouts
+    #[account(mut, constraint = long_payout_ata.mint == quote_mint.key(), constraint = long_payout_ata.owner == deal.long)]
+    pub long_payout_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = short_payout_ata.mint == quote_mint.key(), constraint = short_payout_ata.owner == deal.short)]
+    pub short_payout_ata: Account<'info, TokenAccount>,
+
+    /// CHECK: only used as destination for close_account rent
+    #[account(mut, address = market.authority)]
+    pub market_authority: UncheckedAccount<'info>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SettleDealAtFinalNav<'info> {
+    pub keeper: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(mut, has_one = market)]
+    pub deal: Account<'info, Deal>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    // vaults
+    #[account(
+        mut,
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    // payouts
+    #[account(mut, constraint = long_payout_ata.mint == quote_mint.key(), constraint = long_payout_ata.owner == deal.long)]
+    pub long_payout_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = short_payout_ata.mint == quote_mint.key(), constraint = short_payout_ata.owner == deal.short)]
+    pub short_payout_ata: Account<'info, TokenAccount>,
+
+    /// CHECK: only used as destination for close_account rent
+    #[account(mut, address = market.authority)]
+    pub market_authority: UncheckedAccount<'info>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct EmergencySettleDeal<'info> {
+    pub keeper: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(mut, has_one = market)]
+    pub deal: Account<'info, Deal>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    // vaults
+    #[account(
+        mut,
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    // payouts
+    #[account(mut, constraint = long_payout_ata.mint == quote_mint.key(), constraint = long_payout_ata.owner == deal.long)]
+    pub long_payout_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = short_payout_ata.mint == quote_mint.key(), constraint = short_payout_ata.owner == deal.short)]
+    pub short_payout_ata: Account<'info, TokenAccount>,
+
+    /// CHECK: only used as destination for close_account rent
+    #[account(mut, address = market.authority)]
+    pub market_authority: UncheckedAccount<'info>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Liquidate<'info> {
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(mut, has_one = market)]
+    pub deal: Account<'info, Deal>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    // vaults
+    #[account(
+        mut,
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    // payouts
+    #[account(mut, constraint = long_payout_ata.mint == quote_mint.key(), constraint = long_payout_ata.owner == deal.long)]
+    pub long_payout_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = short_payout_ata.mint == quote_mint.key(), constraint = short_payout_ata.owner == deal.short)]
+    pub short_payout_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = liquidator_ata.mint == quote_mint.key(), constraint = liquidator_ata.owner == liquidator.key())]
+    pub liquidator_ata: Account<'info, TokenAccount>,
+
+    /// CHECK: only used as destination for close_account rent
+    #[account(mut, address = market.authority)]
+    pub market_authority: UncheckedAccount<'info>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+
+    // required when market.mark_mode = Twap
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct PartialLiquidate<'info> {
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(mut, has_one = market)]
+    pub deal: Account<'info, Deal>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = long_margin_vault.mint == quote_mint.key(),
+        constraint = long_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"long_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub long_margin_vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = short_margin_vault.mint == quote_mint.key(),
+        constraint = short_margin_vault.owner == deal_vault_auth.key(),
+        seeds = [VERSION_SEED, b"short_vault", deal.key().as_ref()],
+        bump
+    )]
+    pub short_margin_vault: Account<'info, TokenAccount>,
+
+    #[account(mut, constraint = long_payout_ata.mint == quote_mint.key(), constraint = long_payout_ata.owner == deal.long)]
+    pub long_payout_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = short_payout_ata.mint == quote_mint.key(), constraint = short_payout_ata.owner == deal.short)]
+    pub short_payout_ata: Account<'info, TokenAccount>,
+    #[account(mut, constraint = liquidator_ata.mint == quote_mint.key(), constraint = liquidator_ata.owner == liquidator.key())]
+    pub liquidator_ata: Account<'info, TokenAccount>,
+
+    /// CHECK: only used as destination for close_account rent
+    #[account(mut, address = market.authority)]
+    pub market_authority: UncheckedAccount<'info>,
+
+    #[account(seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()], bump = deal_vault_auth.bump)]
+    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+    pub token_program: Program<'info, Token>,
+
+    // required when market.mark_mode = Twap
+    pub nav_history: Option<AccountLoader<'info, NavHistory>>,
+}
+
+// ──────────────────────────────────────────────────────────────────────────────
+// Events
+// ──────────────────────────────────────────────────────────────────────────────
+
+#[event]
+pub struct MarketInitialized {
+    pub market: Pubkey,
+    pub quote_mint: Pubkey,
+    pub stack_id: Pubkey,
+    pub im_bps: u16,
+    pub mm_bps: u16,
+    pub fee_bps: u16,
+    pub liq_bps: u16,
     pub price_decimals: u8,
     pub quote_decimals: u8,
+}
 
-    pub initial_margin_bps: u16,
-    pub maintenance_margin_bps: u16,
-    pub fee_bps: u16,
-    pub liquidator_bps: u16,
-    pub price_stale_seconds: u32,
+#[event]
+pub struct NavPosted {
+    pub market: Pubkey,
+    pub nav: u64,
+    pub ts: i64,
+}
 
-    pub last_nav: u64,
-    pub last_ts: i64,
+#[event]
+pub struct RoleSet {
+    pub market: Pubkey,
+    pub role: Role,
+    pub holder: Pubkey, // default => cleared
+}
 
-    pub paused: bool,
-    pub bump: u8,
+#[event]
+pub struct AdminSetChanged {
+    pub market: Pubkey,
+    pub added: Pubkey,   // default => removal
+    pub removed: Pubkey, // default => addition
+    pub admin_count: u8,
+    pub admin_threshold: u8,
+}
 
-    // New risk/admin
-    pub max_leverage_bps: u16,
-    pub max_nav_jump_bps: u16,
-    pub max_confidence_bps: u16, // 0 = disabled
-    pub circuit_breaker_until: i64,
-    pub mm_buffer_bps: u16,
+#[event]
+pub struct ParamsProposed {
+    pub market: Pubkey,
+    pub params: MarketUpdateParams,
+    pub eta: i64,
+    pub expires_ts: i64, // no longer executable after this
+}
 
-    pub admin_threshold: u8,
-    pub admins: [Pubkey; MAX_ADMINS],
+#[event]
+pub struct ParamsExecuted {
+    pub market: Pubkey,
+    pub eta: i64,
+    pub ts: i64,
+}
+
+#[event]
+pub struct ParamsCancelled {
+    pub market: Pubkey,
+    pub eta: i64,
+    pub ts: i64,
+}
+
+#[event]
+pub struct ProposalCreated {
+    pub market: Pubkey,
+    pub proposal: Pubkey,
+    pub proposal_id: u64,
+    pub proposer: Pubkey,
+    pub eta: i64,
+}
+
+#[event]
+pub struct ProposalApproved {
+    pub proposal: Pubkey,
+    pub admin: Pubkey,
+    pub approvals: u8,
+}
+
+#[event]
+pub struct ProposalExecuted {
+    pub market: Pubkey,
+    pub proposal: Pubkey,
+    pub proposal_id: u64,
+}
+
+#[event]
+pub struct ProposalCancelled {
+    pub market: Pubkey,
+    pub proposal: Pubkey,
+    pub proposal_id: u64,
+}
+
+#[event]
+pub struct CircuitBreakerTripped {
+    pub market: Pubkey,
+    pub until: i64,
+    pub rejected_nav: u64, // 0 = manual trip
+    pub last_nav: u64,
+    pub ts: i64,
+}
+
+#[event]
+pub struct BreakerNavConfirmed {
+    pub market: Pubkey,
+    pub nav: u64,
+    pub breaker_nav: u64,
+    pub previous_nav: u64,
+    pub ts: i64,
+}
 
-    pub pending: Option<PendingParams>,
+#[event]
+pub struct CircuitBreakerReset {
+    pub market: Pubkey,
+    pub ts: i64,
 }
 
-impl Market {
-    pub const LEN: usize =
-        8 + // disc
-        32*4 + // keys
-        1 + 1 + // decimals
-        2*4 + // bps fields (im, mm, fee, liq)
-        4 + // stale secs
-        8 + 8 + // last_nav, last_ts
-        1 + 1 + // paused, bump
-        2 + 2 + 2 + 8 + 2 + // max_lev, max_jump, max_conf, breaker_until, mm_buffer
-        1 + // admin_threshold
-        (32*MAX_ADMINS) + // admins
-        1 + PendingParams::MAX_LEN; // Option tag + pending (max)
+#[event]
+pub struct MarketStatusChanged {
+    pub market: Pubkey,
+    pub status: MarketStatus,
+    pub final_nav: u64,
+    pub open_deals: u64,
+    pub ts: i64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
-pub struct PendingParams {
-    pub params: MarketUpdateParams,
-    pub eta: i64,
+#[event]
+pub struct MarketClosed {
+    pub market: Pubkey,
+    pub ts: i64,
 }
-impl PendingParams {
-    // rough upper bound for serialization (borsh)
-    pub const MAX_LEN: usize = MarketUpdateParams::MAX_LEN + 8;
+
+#[event]
+pub struct OracleDeclaredDead {
+    pub market: Pubkey,
+    pub settle_nav: u64,
+    pub last_nav_ts: i64,
+    pub ts: i64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
-pub struct MarketUpdateParams {
-    pub oracle_authority: Option<Pubkey>,
-    pub initial_margin_bps: Option<u16>,
-    pub maintenance_margin_bps: Option<u16>,
-    pub fee_bps: Option<u16>,
-    pub liquidator_bps: Option<u16>,
-    pub price_stale_seconds: Option<u32>,
+#[event]
+pub struct OracleRevived {
+    pub market: Pubkey,
+    pub ts: i64,
+}
 
-    // new params
-    pub max_leverage_bps: Option<u16>,
-    pub max_nav_jump_bps: Option<u16>,
-    pub max_confidence_bps: Option<u16>,
-    pub mm_buffer_bps: Option<u16>,
-    pub admin_threshold: Option<u8>,
+#[event]
+pub struct NavProposed {
+    pub market: Pubkey,
+    pub nav: u64,
+    pub ts: i64,
+    pub finalizes_at: i64,
 }
-impl MarketUpdateParams {
-    pub const MAX_LEN: usize =
-        (1+32) + // oracle_authority
-        (1+2)*4 + // four u16 options (im, mm, fee, liq)
-        (1+4) + // price_stale_seconds
-        (1+2)*4 + // new u16 options
-        (1+1); // admin_threshold
+
+#[event]
+pub struct NavDisputeRaised {
+    pub market: Pubkey,
+    pub nav: u64,
+    pub disputer: Pubkey,
+    pub bond: u64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct MarketInitParams {
-    pub oracle_authority: Pubkey,
-    pub price_decimals: u8,
-    pub initial_margin_bps: u16,
-    pub maintenance_margin_bps: u16,
-    pub fee_bps: u16,
-    pub liquidator_bps: u16,
-    pub price_stale_seconds: u32,
+#[event]
+pub struct NavDisputeResolved {
+    pub market: Pubkey,
+    pub nav: u64,
+    pub nav_valid: bool, // true => applied, bond slashed; false => discarded, bond refunded
+    pub disputer: Pubkey,
+    pub bond: u64,
+}
 
-    // new
-    pub max_leverage_bps: u16,
-    pub max_nav_jump_bps: u16,
-    pub max_confidence_bps: Option<u16>,
-    pub mm_buffer_bps: Option<u16>,
-    pub admin_threshold: Option<u8>,
+#[event]
+pub struct NavSubmitted {
+    pub market: Pubkey,
+    pub publisher: Pubkey,
+    pub nav: u64,
+    pub ts: i64,
 }
 
-#[account]
-pub struct MarketVaultAuth {
+#[event]
+pub struct StackRebalanced {
     pub market: Pubkey,
-    pub bump: u8,
+    pub divisor: u64,
+    pub units: [u64; MAX_CONSTITUENTS],
 }
-impl MarketVaultAuth {
-    pub const LEN: usize = 8 + 32 + 1;
+
+#[event]
+pub struct FundingUpdated {
+    pub market: Pubkey,
+    pub cumulative_funding: i128,
+    pub rate_bps: i32,
+    pub ts: i64,
 }
 
-#[account]
-pub struct Deal {
+#[event]
+pub struct DealOpened {
+    pub deal: Pubkey,
     pub market: Pubkey,
     pub long: Pubkey,
     pub short: Pubkey,
-
     pub size: u64,
     pub entry_nav: u64,
-    pub is_open: bool,
-
-    pub long_margin: u64,
-    pub short_margin: u64,
-
-    pub client_order_id: u64,
-    pub bump: u8,
-}
-impl Deal {
-    pub const LEN: usize = 8 + 32 + 32 + 32 + 8 + 8 + 1 + 8 + 8 + 8 + 1;
+    pub notional_quote: u64,
+    pub long_deposit: u64,
+    pub short_deposit: u64,
+    pub open_fee_each: u64,
 }
 
-#[account]
-pub struct DealVaultAuth {
+#[event]
+pub struct SignedOfferFilled {
     pub deal: Pubkey,
-    pub bump: u8,
-}
-impl DealVaultAuth {
-    pub const LEN: usize = 8 + 32 + 1;
+    pub maker: Pubkey,
+    pub taker: Pubkey,
+    pub nonce: u64,
 }
 
-// ──────────────────────────────────────────────────────────────────────────────
-// Instruction Contexts
-// ──────────────────────────────────────────────────────────────────────────────
-
-#[derive(Accounts)]
-#[instruction(stack_id: Pubkey)]
-pub struct InitMarket<'info> {
-    #[account(mut)]
-    pub authority: Signer<'info>,
-
-    pub quote_mint: Box<Account<'info, Mint>>,
-
-    #[account(
-        init,
-        payer = authority,
-        space = Market::LEN,
-        seeds = [VERSION_SEED, b"market", authority.key().as_ref(), quote_mint.key().as_ref(), stack_id.as_ref()],
-        bump
-    )]
-    pub market: Account<'info, Market>,
-
-    #[account(
-        init,
-        payer = authority,
-        space = MarketVaultAuth::LEN,
-        seeds = [VERSION_SEED, b"mva", market.key().as_ref()],
-        bump
-    )]
-    pub market_vault_auth: Account<'info, MarketVaultAuth>,
+#[event]
+pub struct OfferMade {
+    pub offer: Pubkey,
+    pub market: Pubkey,
+    pub maker: Pubkey,
+    pub maker_is_long: bool,
+    pub size: u64,
+    pub ref_nav: u64,
+    pub band_bps: u16,
+    pub maker_deposit: u64,
+    pub expiry_ts: i64,
+    pub allowed_taker: Pubkey,
+}
 
-    #[account(
-        init,
-        payer = authority,
-        associated_token::mint = quote_mint,
-        associated_token::authority = market_vault_auth
-    )]
-    pub fee_vault: Account<'info, TokenAccount>,
+#[event]
+pub struct OfferTaken {
+    pub offer: Pubkey,
+    pub deal: Pubkey,
+    pub maker: Pubkey,
+    pub taker: Pubkey,
+}
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub rent: Sysvar<'info, Rent>,
+#[event]
+pub struct OfferCancelled {
+    pub offer: Pubkey,
+    pub maker: Pubkey,
 }
 
-#[derive(Accounts)]
-pub struct AdminMarketToggle<'info> {
-    pub authority: Signer<'info>,
-    #[account(mut)]
-    pub market: Account<'info, Market>,
+#[event]
+pub struct MarginWithdrawn {
+    pub deal: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub remaining_margin: u64,
 }
 
-#[derive(Accounts)]
-pub struct AdminMarketParams<'info> {
-    pub authority: Signer<'info>,
-    #[account(mut)]
-    pub market: Account<'info, Market>,
+#[event]
+pub struct DealIncreased {
+    pub deal: Pubkey,
+    pub market: Pubkey,
+    pub added_size: u64,
+    pub new_size: u64,
+    pub fill_nav: u64,
+    pub new_entry_nav: u64,
+    pub long_deposit: u64,
+    pub short_deposit: u64,
+    pub fee_each: u64,
 }
 
-#[derive(Accounts)]
-pub struct PostNav<'info> {
-    #[account(mut)]
-    pub market: Account<'info, Market>,
-    pub oracle_authority: Signer<'info>,
+#[event]
+pub struct DealReduced {
+    pub deal: Pubkey,
+    pub market: Pubkey,
+    pub reduced_by: u64,
+    pub remaining_size: u64,
+    pub realized_pnl_long: i64, // moved between vaults; + = short paid long
+    pub nav: u64,
+    pub long_released: u64,
+    pub short_released: u64,
 }
 
-#[derive(Accounts)]
-#[instruction(client_order_id: u64)]
-pub struct OpenDeal<'info> {
-    // parties
-    #[account(mut)]
-    pub long: Signer<'info>,
-    #[account(mut)]
-    pub short: Signer<'info>,
+#[event]
+pub struct DealClosed {
+    pub deal: Pubkey,
+    pub market: Pubkey,
+    pub long_payout: u64,
+    pub short_payout: u64,
+    pub close_nav: u64,
+}
 
-    // market & mint
-    pub market: Account<'info, Market>,
-    pub quote_mint: Box<Account<'info, Mint>>,
+#[event]
+pub struct FinalNavSet {
+    pub market: Pubkey,
+    pub final_nav: u64,
+    pub ts: i64,
+}
 
-    // users' source ATAs
-    #[account(
-        mut,
-        constraint = long_source.mint == quote_mint.key(),
-        constraint = long_source.owner == long.key()
-    )]
-    pub long_source: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        constraint = short_source.mint == quote_mint.key(),
-        constraint = short_source.owner == short.key()
-    )]
-    pub short_source: Account<'info, TokenAccount>,
+#[event]
+pub struct DealSettled {
+    pub deal: Pubkey,
+    pub market: Pubkey,
+    pub keeper: Pubkey,
+    pub long_payout: u64,
+    pub short_payout: u64,
+    pub settle_nav: u64,
+}
 
-    // deal state
-    #[account(
-        init,
-        payer = long,
-        space = Deal::LEN,
-        seeds = [VERSION_SEED, b"deal", market.key().as_ref(), long.key().as_ref(), short.key().as_ref(), &client_order_id.to_le_bytes()],
-        bump
-    )]
-    pub deal: Account<'info, Deal>,
+#[event]
+pub struct DealLiquidated {
+    pub deal: Pubkey,
+    pub market: Pubkey,
+    pub bounty_paid: u64,
+    pub close_nav: u64,
+}
 
-    // deal vault authority
-    #[account(
-        init,
-        payer = long,
-        space = DealVaultAuth::LEN,
-        seeds = [VERSION_SEED, b"deal_vault_auth", deal.key().as_ref()],
-        bump
-    )]
-    pub deal_vault_auth: Account<'info, DealVaultAuth>,
+#[event]
+pub struct CloseRequested {
+    pub deal: Pubkey,
+    pub market: Pubkey,
+    pub requester: Pubkey,
+    pub min_nav: Option<u64>,
+    pub max_nav: Option<u64>,
+    pub expiry_ts: i64,
+    pub reduce_size: u64,
+    pub unilateral_after: i64,
+}
 
-    // margin vaults (owned by the deal_vault_auth PDA)
-    #[account(
-        init,
-        payer = long,
-        associated_token::mint = quote_mint,
-        associated_token::authority = deal_vault_auth
-    )]
-    pub long_margin_vault: Account<'info, TokenAccount>,
-    #[account(
-        init,
-        payer = long,
-        associated_token::mint = quote_mint,
-        associated_token::authority = deal_vault_auth
-    )]
-    pub short_margin_vault: Account<'info, TokenAccount>,
+#[event]
+pub struct CloseRequestCancelled {
+    pub deal: Pubkey,
+    pub requester: Pubkey,
+}
 
-    // fee vault belongs to the market vault auth
-    #[account(
-        mut,
-        constraint = fee_vault.mint == quote_mint.key(),
-        constraint = fee_vault.owner == market_vault_auth.key(),
-    )]
-    pub fee_vault: Account<'info, TokenAccount>,
+#[event]
+pub struct FeesWithdrawn {
+    pub market: Pubkey,
+    pub total: u64,
+    pub treasury_amount: u64,
+    pub insurance_amount: u64,
+    pub oracle_operator_amount: u64,
+}
 
-    pub market_vault_auth: Account<'info, MarketVaultAuth>,
+// ──────────────────────────────────────────────────────────────────────────────
+// Helpers & Admin Utilities
+// ──────────────────────────────────────────────────────────────────────────────
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub rent: Sysvar<'info, Rent>,
+fn apply_market_updates(m: &mut Market, p: &MarketUpdateParams) {
+    if let Some(x) = p.oracle_authority       { m.oracle_authority = x; }
+    if let Some(x) = p.initial_margin_bps     { m.initial_margin_bps = x; }
+    if let Some(x) = p.maintenance_margin_bps { m.maintenance_margin_bps = x; }
+    if let Some(x) = p.fee_bps                { m.fee_bps = x; }
+    if let Some(x) = p.liquidator_bps         { m.liquidator_bps = x; }
+    if let Some(x) = p.price_stale_seconds    { m.price_stale_seconds = x; }
+
+    if let Some(x) = p.max_leverage_bps       { m.max_leverage_bps = x; }
+    if let Some(x) = p.max_nav_jump_bps       { m.max_nav_jump_bps = x; }
+    if let Some(x) = p.max_confidence_bps     { m.max_confidence_bps = x; }
+    if let Some(x) = p.mm_buffer_bps          { m.mm_buffer_bps = x; }
+    if let Some(x) = p.admin_threshold        { m.admin_threshold = x; }
+    if let Some(x) = p.close_notice_secs      { m.close_notice_secs = x; }
+    if let Some(x) = p.funding_mode           { m.funding_mode = x; }
+    if let Some(x) = p.funding_rate_bps       { m.funding_rate_bps = x; }
+    if let Some(x) = p.max_funding_rate_bps   { m.max_funding_rate_bps = x; }
+    if let Some(x) = p.mark_mode              { m.mark_mode = x; }
+    if let Some(x) = p.twap_window_secs       { m.twap_window_secs = x; }
+    if let Some(x) = p.oracle_source          { m.oracle_source = x; }
+    if let Some(x) = p.oracle_feed            { m.oracle_feed = x; }
+    if let Some(x) = p.circuit_breaker_secs   { m.circuit_breaker_secs = x; }
+    if let Some(x) = p.dispute_window_secs    { m.dispute_window_secs = x; }
+    if let Some(x) = p.dispute_bond           { m.dispute_bond = x; }
+    if let Some(x) = p.oracle_dead_multiple   { m.oracle_dead_multiple = x; }
+    if let Some(x) = p.min_timelock_secs      { m.min_timelock_secs = x; }
 }
 
-#[derive(Accounts)]
-pub struct AddMarginLong<'info> {
-    #[account(mut)]
-    pub long: Signer<'info>,
-    #[account(mut, has_one = long)]
-    pub deal: Account<'info, Deal>,
-    pub market: Account<'info, Market>,
-    pub quote_mint: Box<Account<'info, Mint>>,
+/// Shared settlement for accept_close / force_close: checks expiry and NAV band, then either
+/// reduces the deal (partial request) or pays out and closes the vaults.
+fn settle_close_request(ctx: Context<SettleCloseRequest>, guard: Option<TradeGuard>) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let deal = &mut ctx.accounts.deal;
+    accrue_funding(market, Clock::get()?.unix_timestamp)?;
+    let req = &ctx.accounts.close_request;
+    require!(deal.is_open, ErrorCode::NotOpen);
+    ensure_not_paused(market, PAUSE_CLOSE)?;
+    ensure_not_settling(market)?;
+    ensure_not_expired(market)?;
+    ensure_price_fresh(market)?;
 
-    #[account(
-        mut,
-        constraint = long_source.mint == quote_mint.key(),
-        constraint = long_source.owner == long.key()
-    )]
-    pub long_source: Account<'info, TokenAccount>,
+    let now = Clock::get()?.unix_timestamp;
+    require!(now <= req.expiry_ts, ErrorCode::CloseRequestExpired);
+    let nav = mark_nav(market, &ctx.accounts.nav_history)?;
+    if let Some(lo) = req.min_nav {
+        require!(nav >= lo, ErrorCode::NavOutOfRange);
+    }
+    if let Some(hi) = req.max_nav {
+        require!(nav <= hi, ErrorCode::NavOutOfRange);
+    }
+    check_trade_guard(&guard, nav)?;
 
-    #[account(
-        mut,
-        constraint = long_margin_vault.mint == quote_mint.key(),
-        constraint = long_margin_vault.owner == deal_vault_auth.key()
-    )]
-    pub long_margin_vault: Account<'info, TokenAccount>,
+    // Partial request: realize PnL on the slice and keep the deal open
+    if req.reduce_size > 0 {
+        require!(req.reduce_size < deal.size, ErrorCode::ReduceExceedsSize);
+        let realized = deal_pnl_long_for_size(deal, market, nav, req.reduce_size)?;
+        let moved = transfer_realized_pnl(
+            &ctx.accounts.token_program,
+            &ctx.accounts.long_margin_vault,
+            &ctx.accounts.short_margin_vault,
+            &ctx.accounts.deal_vault_auth,
+            deal,
+            realized,
+        )?;
+        deal.size -= req.reduce_size;
+        ctx.accounts.long_margin_vault.reload()?;
+        ctx.accounts.short_margin_vault.reload()?;
+        deal.long_margin = ctx.accounts.long_margin_vault.amount;
+        deal.short_margin = ctx.accounts.short_margin_vault.amount;
 
-    pub deal_vault_auth: Account<'info, DealVaultAuth>,
-    pub token_program: Program<'info, Token>,
+        emit!(DealReduced {
+            deal: deal.key(),
+            market: deal.market,
+            reduced_by: req.reduce_size,
+            remaining_size: deal.size,
+            realized_pnl_long: moved as i64,
+            nav,
+            long_released: 0,
+            short_released: 0,
+        });
+        return Ok(());
+    }
+
+    let pnl_long = deal_pnl_long(deal, market, nav)?;
+    let (long_payout, short_payout) = Settlement {
+        token_program: &ctx.accounts.token_program,
+        long_margin_vault: &mut ctx.accounts.long_margin_vault,
+        short_margin_vault: &mut ctx.accounts.short_margin_vault,
+        long_payout_ata: &ctx.accounts.long_payout_ata,
+        short_payout_ata: &ctx.accounts.short_payout_ata,
+        rent_destination: &ctx.accounts.market_authority,
+        deal_vault_auth: &ctx.accounts.deal_vault_auth,
+    }
+    .settle(deal, pnl_long)?;
+
+    deal.is_open = false;
+    market.deal_closed();
+
+    emit!(DealClosed {
+        deal: deal.key(),
+        market: deal.market,
+        long_payout,
+        short_payout,
+        close_nav: nav,
+    });
+    Ok(())
 }
 
-#[derive(Accounts)]
-pub struct AddMarginShort<'info> {
-    #[account(mut)]
-    pub short: Signer<'info>,
-    #[account(mut, has_one = short)]
-    pub deal: Account<'info, Deal>,
-    pub market: Account<'info, Market>,
-    pub quote_mint: Box<Account<'info, Mint>>,
+/// Gate and store a new market NAV: circuit breaker window, optional confidence, jump limit (a jump
+/// trips the breaker and is recorded, not applied);
+/// records it in the NavHistory (if configured); the first NAV at/after expiry also fixes final_nav
+/// (TWAP when mark_mode = Twap).
+/// `nav_ts` is when the NAV was observed (the post time of an optimistic NAV); it becomes `last_ts`
+/// and the history sample time, while `now` drives the circuit breaker.
+fn apply_nav<'info>(
+    market: &mut Market,
+    market_key: Pubkey,
+    nav: u64,
+    nav_confidence: Option<u64>,
+    nav_ts: i64,
+    now: i64,
+    history: &Option<AccountLoader<'info, NavHistory>>,
+) -> Result<()> {
+    require!(!market.oracle_dead, ErrorCode::OracleDead);
 
-    #[account(
-        mut,
-        constraint = short_source.mint == quote_mint.key(),
-        constraint = short_source.owner == short.key()
-    )]
-    pub short_source: Account<'info, TokenAccount>,
+    // Circuit breaker window check
+    if now < market.circuit_breaker_until {
+        return err!(ErrorCode::CircuitBreaker);
+    }
 
-    #[account(
-        mut,
-        constraint = short_margin_vault.mint == quote_mint.key(),
-        constraint = short_margin_vault.owner == deal_vault_auth.key()
-    )]
-    pub short_margin_vault: Account<'info, TokenAccount>,
+    // Confidence (if configured and provided)
+    if market.max_confidence_bps > 0 {
+        if let Some(conf) = nav_confidence {
+            let conf_bps = ratio_bps_u128(conf as u128, (nav as u128).max(1))? as u16;
+            require!(conf_bps <= market.max_confidence_bps, ErrorCode::OracleConfidenceTooWide);
+        }
+    }
 
-    pub deal_vault_auth: Account<'info, DealVaultAuth>,
-    pub token_program: Program<'info, Token>,
-}
+    // Jump limit check. Once the cool-off has passed, a post within the limit of the pending
+    // breaker_nav confirms the move instead of re-tripping against the stale last_nav.
+    if market.last_nav != 0 {
+        let jump_bps = nav_jump_bps(market.last_nav, nav)?;
+        let confirms_breaker =
+            market.breaker_nav != 0 && nav_jump_bps(market.breaker_nav, nav)? <= market.max_nav_jump_bps;
+        if jump_bps > market.max_nav_jump_bps && !confirms_breaker {
+            // Trip circuit breaker for the configured cool-off and keep the offending NAV as the pending
+            // mark. Returns Ok so the trip persists (an error would roll it back); last_nav is unchanged.
+            market.circuit_breaker_until = now + market.circuit_breaker_secs as i64;
+            market.breaker_nav = nav;
+            market.breaker_nav_ts = now;
+            emit!(CircuitBreakerTripped {
+                market: market_key,
+                until: market.circuit_breaker_until,
+                rejected_nav: nav,
+                last_nav: market.last_nav,
+                ts: now,
+            });
+            return Ok(());
+        }
+        if jump_bps > market.max_nav_jump_bps {
+            emit!(BreakerNavConfirmed {
+                market: market_key,
+                nav,
+                breaker_nav: market.breaker_nav,
+                previous_nav: market.last_nav,
+                ts: now,
+            });
+        }
+    }
+    // Accepted: any pending mark is either confirmed or superseded by a post near last_nav
+    market.breaker_nav = 0;
+    market.breaker_nav_ts = 0;
+
+    if market.nav_history != Pubkey::default() {
+        let h = history.as_ref().ok_or(ErrorCode::InvalidNavHistory)?;
+        require_keys_eq!(h.key(), market.nav_history, ErrorCode::InvalidNavHistory);
+        h.load_mut()?.push(nav, nav_confidence.unwrap_or(0), nav_ts);
+    }
 
-#[derive(Accounts)]
-pub struct CloseDeal<'info> {
-    #[account(mut)]
-    pub long: Signer<'info>,
-    #[account(mut)]
-    pub short: Signer<'info>,
+    market.last_nav = nav;
+    market.last_ts = nav_ts;
 
-    #[account(mut)]
-    pub market: Account<'info, Market>,
-    #[account(mut, has_one = market)]
-    pub deal: Account<'info, Deal>,
+    emit!(NavPosted { market: market_key, nav, ts: market.last_ts });
 
-    pub quote_mint: Box<Account<'info, Mint>>,
+    // First post at/after expiry fixes the final settlement NAV
+    if market.expiry_ts != 0 && nav_ts >= market.expiry_ts && market.final_nav == 0 {
+        let final_nav = mark_nav(market, history)?;
+        market.final_nav = final_nav;
+        market.final_nav_ts = nav_ts;
+        emit!(FinalNavSet { market: market_key, final_nav, ts: nav_ts });
+    }
+    Ok(())
+}
 
-    // vaults
-    #[account(
-        mut,
-        constraint = long_margin_vault.mint == quote_mint.key(),
-        constraint = long_margin_vault.owner == deal_vault_auth.key()
-    )]
-    pub long_margin_vault: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        constraint = short_margin_vault.mint == quote_mint.key(),
-        constraint = short_margin_vault.owner == deal_vault_auth.key()
-    )]
-    pub short_margin_vault: Account<'info, TokenAccount>,
+/// Mark price for close / liquidation / expiry: last_nav (Spot) or the NavHistory TWAP (Twap).
+fn mark_nav<'info>(m: &Market, history: &Option<AccountLoader<'info, NavHistory>>) -> Result<u64> {
+    match m.mark_mode {
+        MarkMode::Spot => Ok(m.last_nav),
+        MarkMode::Twap => {
+            let h = history.as_ref().ok_or(ErrorCode::InvalidNavHistory)?;
+            require_keys_eq!(h.key(), m.nav_history, ErrorCode::InvalidNavHistory);
+            let now = Clock::get()?.unix_timestamp;
+            let twap = h.load()?.twap(now, m.twap_window_secs);
+            Ok(twap.ok_or(ErrorCode::InvalidNavHistory)?)
+        }
+    }
+}
 
-    // payouts
-    #[account(mut, constraint = long_payout_ata.mint == quote_mint.key(), constraint = long_payout_ata.owner == long.key())]
-    pub long_payout_ata: Account<'info, TokenAccount>,
-    #[account(mut, constraint = short_payout_ata.mint == quote_mint.key(), constraint = short_payout_ata.owner == short.key())]
-    pub short_payout_ata: Account<'info, TokenAccount>,
+/// Price read from an external feed: `price` and `conf` carry `decimals` decimals.
+struct FeedPrice {
+    price: u128,
+    conf: u128,
+    decimals: u32,
+    publish_ts: i64,
+}
 
-    /// CHECK: only used as destination for close_account rent
-    #[account(mut, address = market.authority)]
-    pub market_authority: UncheckedAccount<'info>,
+fn read_bytes<const N: usize>(data: &[u8], off: usize) -> Result<[u8; N]> {
+    let b = data.get(off..off + N).ok_or(ErrorCode::InvalidFeed)?;
+    Ok(b.try_into().unwrap())
+}
 
-    pub deal_vault_auth: Account<'info, DealVaultAuth>,
-    pub token_program: Program<'info, Token>,
+/// Pyth v2 price account: magic/atype header, expo @20, timestamp @96, aggregate price info @208
+/// (price i64, conf u64, status u32). Only a Trading aggregate with a positive price is accepted.
+fn parse_pyth_price(data: &[u8]) -> Result<FeedPrice> {
+    require!(u32::from_le_bytes(read_bytes(data, 0)?) == PYTH_MAGIC, ErrorCode::InvalidFeed);
+    require!(u32::from_le_bytes(read_bytes(data, 8)?) == PYTH_ATYPE_PRICE, ErrorCode::InvalidFeed);
+    let expo = i32::from_le_bytes(read_bytes(data, 20)?);
+    let publish_ts = i64::from_le_bytes(read_bytes(data, 96)?);
+    let price = i64::from_le_bytes(read_bytes(data, 208)?);
+    let conf = u64::from_le_bytes(read_bytes(data, 216)?);
+    let status = u32::from_le_bytes(read_bytes(data, 224)?);
+    require!(status == PYTH_STATUS_TRADING, ErrorCode::InvalidFeed);
+    require!(price > 0 && expo <= 0, ErrorCode::InvalidFeed);
+    let decimals = expo.unsigned_abs();
+    require!(decimals <= MAX_FEED_DECIMALS, ErrorCode::InvalidFeed);
+    Ok(FeedPrice { price: price as u128, conf: conf as u128, decimals, publish_ts })
 }
 
-#[derive(Accounts)]
-pub struct Liquidate<'info> {
-    #[account(mut)]
-    pub liquidator: Signer<'info>,
+/// Switchboard V2 aggregator: latest_confirmed_round @341 — round_open_timestamp i64 @+17,
+/// result {mantissa i128, scale u32} @+25, std_deviation {mantissa, scale} @+45.
+fn parse_switchboard_price(data: &[u8]) -> Result<FeedPrice> {
+    let r = SWITCHBOARD_ROUND_OFFSET;
+    let publish_ts = i64::from_le_bytes(read_bytes(data, r + 17)?);
+    let mantissa = i128::from_le_bytes(read_bytes(data, r + 25)?);
+    let scale = u32::from_le_bytes(read_bytes(data, r + 41)?);
+    let std_mantissa = i128::from_le_bytes(read_bytes(data, r + 45)?);
+    let std_scale = u32::from_le_bytes(read_bytes(data, r + 61)?);
+    require!(mantissa > 0 && std_mantissa >= 0, ErrorCode::InvalidFeed);
+    require!(scale <= MAX_FEED_DECIMALS && std_scale <= MAX_FEED_DECIMALS, ErrorCode::InvalidFeed);
+    let conf = scale_amount(std_mantissa as u128, std_scale, scale)?;
+    Ok(FeedPrice { price: mantissa as u128, conf, decimals: scale, publish_ts })
+}
 
-    #[account(mut)]
-    pub market: Account<'info, Market>,
-    #[account(mut, has_one = market)]
-    pub deal: Account<'info, Deal>,
+/// Median (mean of the two middle values for an even count). `v` must be non-empty.
+fn median_u64(mut v: Vec<u64>) -> u64 {
+    v.sort_unstable();
+    let n = v.len();
+    if n % 2 == 1 {
+        v[n / 2]
+    } else {
+        ((v[n / 2 - 1] as u128 + v[n / 2] as u128) / 2) as u64
+    }
+}
 
-    pub quote_mint: Box<Account<'info, Mint>>,
+fn ensure_price_fresh(m: &Market) -> Result<()> {
+    require!(!m.oracle_dead, ErrorCode::OracleDead);
+    let now = Clock::get()?.unix_timestamp;
+    if now < m.circuit_breaker_until {
+        return err!(ErrorCode::CircuitBreaker);
+    }
+    require!(m.last_nav > 0, ErrorCode::PriceNotSet);
+    let age = now.saturating_sub(m.last_ts);
+    require!(age >= 0, ErrorCode::ClockWentBackwards);
+    require!((age as u64) <= m.price_stale_seconds as u64, ErrorCode::PriceStale);
+    Ok(())
+}
 
-    // vaults
-    #[account(
-        mut,
-        constraint = long_margin_vault.mint == quote_mint.key(),
-        constraint = long_margin_vault.owner == deal_vault_auth.key()
-    )]
-    pub long_margin_vault: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        constraint = short_margin_vault.mint == quote_mint.key(),
-        constraint = short_margin_vault.owner == deal_vault_auth.key()
-    )]
-    pub short_margin_vault: Account<'info, TokenAccount>,
+/// Daily funding rate in bps for the current mode, clamped to ±max_funding_rate_bps when set.
+fn effective_funding_rate_bps(m: &Market) -> i32 {
+    let raw = match m.funding_mode {
+        FundingMode::Disabled => 0,
+        FundingMode::Fixed => m.funding_rate_bps,
+        FundingMode::Premium => m.funding_premium_bps,
+    };
+    if m.max_funding_rate_bps == 0 {
+        return raw;
+    }
+    let cap = m.max_funding_rate_bps as i32;
+    raw.clamp(-cap, cap)
+}
 
-    // payouts
-    #[account(mut, constraint = long_payout_ata.mint == quote_mint.key(), constraint = long_payout_ata.owner == deal.long)]
-    pub long_payout_ata: Account<'info, TokenAccount>,
-    #[account(mut, constraint = short_payout_ata.mint == quote_mint.key(), constraint = short_payout_ata.owner == deal.short)]
-    pub short_payout_ata: Account<'info, TokenAccount>,
-    #[account(mut, constraint = liquidator_ata.mint == quote_mint.key(), constraint = liquidator_ata.owner == liquidator.key())]
-    pub liquidator_ata: Account<'info, TokenAccount>,
+/// Accrue the cumulative funding index from last_funding_ts to `now` at the current rate and NAV.
+fn accrue_funding(m: &mut Market, now: i64) -> Result<()> {
+    // Funding stops at expiry, once shutdown begins and while the oracle is dead
+    if m.oracle_dead || matches!(m.status, MarketStatus::Settling | MarketStatus::Settled) {
+        return Ok(());
+    }
+    let now = if m.expiry_ts != 0 { now.min(m.expiry_ts) } else { now };
+    let dt = now.saturating_sub(m.last_funding_ts);
+    if dt <= 0 {
+        return Ok(());
+    }
+    let rate = effective_funding_rate_bps(m) as i128;
+    if rate != 0 && m.last_nav != 0 {
+        // index delta = nav * rate/1e4 * dt/day, carried at FUNDING_INDEX_SCALE precision
+        let delta = (m.last_nav as i128)
+            .checked_mul(rate)
+            .and_then(|x| x.checked_mul(dt as i128))
+            .and_then(|x| x.checked_mul(FUNDING_INDEX_SCALE as i128))
+            .and_then(|x| x.checked_div(10_000 * SECONDS_PER_DAY as i128))
+            .ok_or(ErrorCode::MathOverflow)?;
+        m.cumulative_funding = m.cumulative_funding.checked_add(delta).ok_or(ErrorCode::MathOverflow)?;
+    }
+    m.last_funding_ts = now;
+    Ok(())
+}
 
-    /// CHECK: only used as destination for close_account rent
-    #[account(mut, address = market.authority)]
-    pub market_authority: UncheckedAccount<'info>,
+/// Funding owed by LONG (quote units, signed) for `size_units` over an index delta.
+fn funding_quote(size_units: u64, index_delta: i128, price_decimals: u8, quote_decimals: u8) -> Result<i128> {
+    let mag = index_delta
+        .unsigned_abs()
+        .checked_mul(size_units as u128)
+        .and_then(|x| x.checked_div(FUNDING_INDEX_SCALE))
+        .ok_or(ErrorCode::MathOverflow)?;
+    let scaled = scale_amount(mag, (UNIT_DECIMALS as u32) + (price_decimals as u32), quote_decimals as u32)?;
+    Ok(if index_delta >= 0 { scaled as i128 } else { -(scaled as i128) })
+}
 
-    pub deal_vault_auth: Account<'info, DealVaultAuth>,
-    pub token_program: Program<'info, Token>,
+/// LONG PnL at `nav` net of funding accrued since the deal recorded its index.
+fn deal_pnl_long(d: &Deal, m: &Market, nav: u64) -> Result<i128> {
+    deal_pnl_long_for_size(d, m, nav, d.size)
 }
 
-#[derive(Accounts)]
-pub struct PartialLiquidate<'info> {
-    #[account(mut)]
-    pub liquidator: Signer<'info>,
+/// LONG PnL (net of funding) on `size_units` of the deal.
+fn deal_pnl_long_for_size(d: &Deal, m: &Market, nav: u64, size_units: u64) -> Result<i128> {
+    let pnl = pnl_quote(size_units, d.entry_nav, nav, m.price_decimals, m.quote_decimals)?;
+    let funding = funding_quote(size_units, m.cumulative_funding.saturating_sub(d.funding_index), m.price_decimals, m.quote_decimals)?;
+    Ok(pnl - funding)
+}
 
-    #[account(mut)]
-    pub market: Account<'info, Market>,
-    #[account(mut, has_one = market)]
-    pub deal: Account<'info, Deal>,
+/// Largest amount a side can release while its equity stays >= IM + mm buffer on `notional_q`.
+fn free_margin(m: &Market, notional_q: u128, own_vault: u64, own_pnl: i128) -> Result<u64> {
+    let required = bps(notional_q, m.initial_margin_bps.saturating_add(m.mm_buffer_bps))?;
+    let excess = (own_vault as i128) + own_pnl - (required as i128);
+    Ok(clamp_i128(excess, 0, own_vault as i128) as u64)
+}
 
-    pub quote_mint: Box<Account<'info, Mint>>,
+/// Smallest combined margin pool that keeps `notional_q` within max_leverage_bps.
+fn min_pool_for_leverage(m: &Market, notional_q: u128) -> Result<u128> {
+    require!(m.max_leverage_bps > 0, ErrorCode::LeverageTooHigh);
+    let lev = m.max_leverage_bps as u128;
+    notional_q
+        .checked_mul(10_000)
+        .and_then(|x| x.checked_add(lev - 1))
+        .map(|x| x / lev)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
 
-    #[account(
-        mut,
-        constraint = long_margin_vault.mint == quote_mint.key(),
-        constraint = long_margin_vault.owner == deal_vault_auth.key()
-    )]
-    pub long_margin_vault: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        constraint = short_margin_vault.mint == quote_mint.key(),
-        constraint = short_margin_vault.owner == deal_vault_auth.key()
-    )]
-    pub short_margin_vault: Account<'info, TokenAccount>,
+/// Entry terms for a new deal at current NAV.
+struct OpenQuote {
+    entry_nav: u64,
+    notional_q: u128,
+    open_fee_each: u128,
+}
 
-    #[account(mut, constraint = long_payout_ata.mint == quote_mint.key(), constraint = long_payout_ata.owner == deal.long)]
-    pub long_payout_ata: Account<'info, TokenAccount>,
-    #[account(mut, constraint = short_payout_ata.mint == quote_mint.key(), constraint = short_payout_ata.owner == deal.short)]
-    pub short_payout_ata: Account<'info, TokenAccount>,
-    #[account(mut, constraint = liquidator_ata.mint == quote_mint.key(), constraint = liquidator_ata.owner == liquidator.key())]
-    pub liquidator_ata: Account<'info, TokenAccount>,
+/// Price a new deal at last_nav and enforce IM (+ fee) per side and the leverage cap at open.
+fn quote_open(m: &Market, size: u64, long_deposit: u64, short_deposit: u64) -> Result<OpenQuote> {
+    // Entry price and notional (in quote mint decimals)
+    let entry_nav = m.last_nav;
+    let notional_q = notional_quote(size, entry_nav, m.price_decimals, m.quote_decimals)?;
+
+    // Fees & margin requirements
+    let open_fee_total = bps(notional_q, m.fee_bps)?;
+    let open_fee_each = open_fee_total / 2;
+    let im_required_each = bps(notional_q, m.initial_margin_bps)?;
+
+    require!(long_deposit as u128 >= im_required_each + open_fee_each, ErrorCode::InsufficientMargin);
+    require!(short_deposit as u128 >= im_required_each + open_fee_each, ErrorCode::InsufficientMargin);
+
+    // Leverage cap at open: based on total effective margin after fees
+    let effective_total_margin = (long_deposit as u128)
+        .saturating_add(short_deposit as u128)
+        .saturating_sub(open_fee_total);
+    require!(effective_total_margin > 0, ErrorCode::InsufficientMargin);
+    let lev_bps = ratio_bps_u128(notional_q, effective_total_margin)?;
+    require!(lev_bps <= m.max_leverage_bps as u128, ErrorCode::LeverageTooHigh);
+
+    Ok(OpenQuote { entry_nav, notional_q, open_fee_each })
+}
 
-    /// CHECK: only used as destination for close_account rent
-    #[account(mut, address = market.authority)]
-    pub market_authority: UncheckedAccount<'info>,
+/// Enforce an optional TradeGuard against the NAV the trade executes at.
+fn check_trade_guard(guard: &Option<TradeGuard>, nav: u64) -> Result<()> {
+    if let Some(g) = guard {
+        if let Some(t) = g.deadline_ts {
+            require!(Clock::get()?.unix_timestamp <= t, ErrorCode::DeadlineExceeded);
+        }
+        if let Some(lo) = g.min_nav {
+            require!(nav >= lo, ErrorCode::NavBelowMin);
+        }
+        if let Some(hi) = g.max_nav {
+            require!(nav <= hi, ErrorCode::NavAboveMax);
+        }
+    }
+    Ok(())
+}
 
-    pub deal_vault_auth: Account<'info, DealVaultAuth>,
-    pub token_program: Program<'info, Token>,
+/// |nav - ref_nav| <= band_bps of ref_nav.
+fn nav_within_band(nav: u64, ref_nav: u64, band_bps: u16) -> Result<bool> {
+    let diff = (nav as i128 - ref_nav as i128).unsigned_abs();
+    Ok(diff <= bps(ref_nav as u128, band_bps)?)
 }
 
-// ──────────────────────────────────────────────────────────────────────────────
-// Events
-// ──────────────────────────────────────────────────────────────────────────────
+/// Bytes the maker signs for a SignedOffer.
+fn offer_message(offer: &SignedOffer) -> Result<Vec<u8>> {
+    let mut msg = OFFER_DOMAIN.to_vec();
+    offer.serialize(&mut msg).map_err(|_| ErrorCode::MathOverflow)?;
+    Ok(msg)
+}
 
-#[event]
-pub struct MarketInitialized {
-    pub market: Pubkey,
-    pub quote_mint: Pubkey,
-    pub stack_id: Pubkey,
-    pub im_bps: u16,
-    pub mm_bps: u16,
-    pub fee_bps: u16,
-    pub liq_bps: u16,
-    pub price_decimals: u8,
-    pub quote_decimals: u8,
+/// Require that the instruction right before this one is an ed25519 verify of `msg` by `signer`,
+/// with signature, key and message all embedded in that instruction's own data.
+fn verify_ed25519_ix(ix_sysvar: &AccountInfo, signer: &Pubkey, msg: &[u8]) -> Result<()> {
+    let current = load_current_index_checked(ix_sysvar)?;
+    require!(current > 0, ErrorCode::InvalidOfferSignature);
+    let ix = load_instruction_at_checked((current - 1) as usize, ix_sysvar)?;
+    require_keys_eq!(ix.program_id, ed25519_program::ID, ErrorCode::InvalidOfferSignature);
+
+    // Header: num_signatures (u8), padding (u8), then seven u16 offsets for the single signature
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, ErrorCode::InvalidOfferSignature);
+    let read_u16 = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+    let sig_ix = read_u16(4);
+    let pk_offset = read_u16(6) as usize;
+    let pk_ix = read_u16(8);
+    let msg_offset = read_u16(10) as usize;
+    let msg_len = read_u16(12) as usize;
+    let msg_ix = read_u16(14);
+    require!(
+        sig_ix == u16::MAX && pk_ix == u16::MAX && msg_ix == u16::MAX,
+        ErrorCode::InvalidOfferSignature
+    );
+
+    let pk = data.get(pk_offset..pk_offset + 32).ok_or(ErrorCode::InvalidOfferSignature)?;
+    require!(pk == signer.as_ref(), ErrorCode::InvalidOfferSignature);
+    let signed = data.get(msg_offset..msg_offset + msg_len).ok_or(ErrorCode::InvalidOfferSignature)?;
+    require!(signed == msg, ErrorCode::InvalidOfferSignature);
+    Ok(())
 }
 
-#[event]
-pub struct NavPosted {
-    pub market: Pubkey,
-    pub nav: u64,
-    pub ts: i64,
+/// Withdrawal must leave the side's equity at or above IM + mm buffer, and the pool within the leverage cap.
+fn check_withdrawal(
+    m: &Market,
+    notional_q: u128,
+    own_vault: u64,
+    other_vault: u64,
+    own_pnl: i128,
+    amount: u64,
+) -> Result<()> {
+    require!(amount <= own_vault, ErrorCode::InsufficientMargin);
+
+    let required = bps(notional_q, m.initial_margin_bps.saturating_add(m.mm_buffer_bps))?;
+    let equity_after = (own_vault as i128) - (amount as i128) + own_pnl;
+    require!(equity_after >= required as i128, ErrorCode::InsufficientMargin);
+
+    let pool_after = (own_vault as u128 + other_vault as u128).saturating_sub(amount as u128);
+    require!(pool_after > 0, ErrorCode::InsufficientMargin);
+    let lev_bps = ratio_bps_u128(notional_q, pool_after)?;
+    require!(lev_bps <= m.max_leverage_bps as u128, ErrorCode::LeverageTooHigh);
+    Ok(())
 }
 
-#[event]
-pub struct DealOpened {
-    pub deal: Pubkey,
-    pub market: Pubkey,
-    pub long: Pubkey,
-    pub short: Pubkey,
-    pub size: u64,
-    pub entry_nav: u64,
-    pub notional_quote: u64,
-    pub long_deposit: u64,
-    pub short_deposit: u64,
-    pub open_fee_each: u64,
+/// `paused` blocks everything; `pause_flags` blocks the given operation group.
+fn ensure_not_paused(m: &Market, flag: u8) -> Result<()> {
+    require!(!m.paused && m.pause_flags & flag == 0, ErrorCode::MarketPaused);
+    Ok(())
 }
 
-#[event]
-pub struct DealClosed {
-    pub deal: Pubkey,
-    pub market: Pubkey,
-    pub long_payout: u64,
-    pub short_payout: u64,
-    pub close_nav: u64,
+/// New exposure (open, offer, increase) only while Active.
+/// Oracle / basket setup instructions take effect immediately, so they are limited to markets that
+/// have not accepted a NAV yet; afterwards changes go through the timelock.
+fn ensure_market_setup(m: &Market) -> Result<()> {
+    require!(m.last_nav == 0 && m.open_deals == 0, ErrorCode::MarketAlreadyLive);
+    Ok(())
 }
 
-#[event]
-pub struct DealLiquidated {
-    pub deal: Pubkey,
-    pub market: Pubkey,
-    pub bounty_paid: u64,
-    pub close_nav: u64,
+/// Optimistic NAV needs a bond, otherwise anyone can stall every post with free disputes.
+/// Margin and breaker limits that keep the market tradable: MM <= IM <= 100%, a non-zero leverage
+/// cap and a non-zero NAV jump limit (0 would trip the breaker on every post). A zero staleness
+/// window would make every NAV stale and the oracle declarable dead right after a post.
+fn ensure_risk_bounds(m: &Market) -> Result<()> {
+    require!(
+        m.maintenance_margin_bps <= m.initial_margin_bps && m.initial_margin_bps <= 10_000,
+        ErrorCode::InvalidRiskParams
+    );
+    require!(m.max_leverage_bps > 0 && m.max_nav_jump_bps > 0, ErrorCode::InvalidRiskParams);
+    require!(m.price_stale_seconds > 0, ErrorCode::InvalidRiskParams);
+    Ok(())
 }
 
-// ──────────────────────────────────────────────────────────────────────────────
-// Helpers & Admin Utilities
-// ──────────────────────────────────────────────────────────────────────────────
+fn ensure_dispute_bond(m: &Market) -> Result<()> {
+    require!(m.dispute_window_secs == 0 || m.dispute_bond > 0, ErrorCode::DisputeBondRequired);
+    Ok(())
+}
 
-fn apply_market_updates(m: &mut Market, p: &MarketUpdateParams) {
-    if let Some(x) = p.oracle_authority       { m.oracle_authority = x; }
-    if let Some(x) = p.initial_margin_bps     { m.initial_margin_bps = x; }
-    if let Some(x) = p.maintenance_margin_bps { m.maintenance_margin_bps = x; }
-    if let Some(x) = p.fee_bps                { m.fee_bps = x; }
-    if let Some(x) = p.liquidator_bps         { m.liquidator_bps = x; }
-    if let Some(x) = p.price_stale_seconds    { m.price_stale_seconds = x; }
+fn ensure_active(m: &Market) -> Result<()> {
+    require!(m.status == MarketStatus::Active, ErrorCode::MarketNotActive);
+    Ok(())
+}
 
-    if let Some(x) = p.max_leverage_bps       { m.max_leverage_bps = x; }
-    if let Some(x) = p.max_nav_jump_bps       { m.max_nav_jump_bps = x; }
-    if let Some(x) = p.max_confidence_bps     { m.max_confidence_bps = x; }
-    if let Some(x) = p.mm_buffer_bps          { m.mm_buffer_bps = x; }
-    if let Some(x) = p.admin_threshold        { m.admin_threshold = x; }
+/// Trading at market NAV stops once shutdown has begun; deals then settle at final_nav.
+fn ensure_not_settling(m: &Market) -> Result<()> {
+    require!(
+        matches!(m.status, MarketStatus::Active | MarketStatus::ReduceOnly),
+        ErrorCode::MarketShuttingDown
+    );
+    Ok(())
 }
 
-fn ensure_price_fresh(m: &Market) -> Result<()> {
-    let now = Clock::get()?.unix_timestamp;
-    if now < m.circuit_breaker_until {
-        return err!(ErrorCode::CircuitBreaker);
+fn ensure_not_expired(m: &Market) -> Result<()> {
+    if m.expiry_ts != 0 {
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < m.expiry_ts, ErrorCode::MarketExpired);
     }
-    require!(m.last_nav > 0, ErrorCode::PriceNotSet);
-    let age = now.saturating_sub(m.last_ts);
-    require!(age >= 0, ErrorCode::ClockWentBackwards);
-    require!((age as u64) <= m.price_stale_seconds as u64, ErrorCode::PriceStale);
     Ok(())
 }
 
@@ -1126,6 +4995,12 @@ fn bps(amount: u128, bps: u16) -> Result<u128> {
         .ok_or(ErrorCode::MathOverflow.into())
 }
 
+/// |new - old| / old in bps, saturating at u16::MAX.
+fn nav_jump_bps(old: u64, new: u64) -> Result<u16> {
+    let bps = ratio_bps_u128(old.abs_diff(new) as u128, (old as u128).max(1))?;
+    Ok(u16::try_from(bps).unwrap_or(u16::MAX))
+}
+
 fn ratio_bps_u128(num: u128, denom: u128) -> Result<u128> {
     num.checked_mul(10_000)
         .and_then(|x| x.checked_div(denom))
@@ -1177,13 +5052,259 @@ fn scale_amount(amount: u128, from_dec: u32, to_dec: u32) -> Result<u128> {
 }
 
 fn pow10_u128(p: u32) -> Option<u128> {
-    Some(10u128.pow(p))
+    10u128.checked_pow(p)
 }
 
 fn clamp_i128(x: i128, lo: i128, hi: i128) -> i128 {
     if x < lo { lo } else if x > hi { hi } else { x }
 }
 
+/// Split the combined pool at `pnl_long`: long gets its margin + PnL clamped to [0, pool], short the rest.
+fn split_pool(long_amt: u64, short_amt: u64, pnl_long: i128) -> (u64, u64) {
+    let total_pool = (long_amt as u128) + (short_amt as u128);
+    let desired_long = (long_amt as i128) + pnl_long;
+    let long_payout = clamp_i128(desired_long, 0, total_pool as i128) as u128;
+    let short_payout = total_pool.saturating_sub(long_payout);
+    (long_payout as u64, short_payout as u64)
+}
+
+/// Move realized LONG PnL between the margin vaults, capped at the paying vault's balance.
+/// Returns the signed amount moved to LONG.
+fn transfer_realized_pnl<'info>(
+    token_program: &Program<'info, Token>,
+    long_vault: &Account<'info, TokenAccount>,
+    short_vault: &Account<'info, TokenAccount>,
+    deal_vault_auth: &Account<'info, DealVaultAuth>,
+    deal: &Account<'info, Deal>,
+    realized_long: i128,
+) -> Result<i128> {
+    if realized_long >= 0 {
+        let amt = (realized_long as u128).min(short_vault.amount as u128) as u64;
+        drain_to(token_program, short_vault, long_vault, deal_vault_auth, deal, amt)?;
+        Ok(amt as i128)
+    } else {
+        let amt = realized_long.unsigned_abs().min(long_vault.amount as u128) as u64;
+        drain_to(token_program, long_vault, short_vault, deal_vault_auth, deal, amt)?;
+        Ok(-(amt as i128))
+    }
+}
+
+/// Apply a params update (timelocked pending params or an executed proposal): rebalance the
+/// basket if requested, settle funding at the old rate, then write the new fields.
+fn execute_params_update<'info>(
+    market: &mut Market,
+    params: &MarketUpdateParams,
+    stack_definition: Option<&mut Account<'info, StackDefinition>>,
+    now: i64,
+) -> Result<()> {
+    if params.stack_units.is_some() || params.stack_divisor.is_some() {
+        let def = stack_definition.ok_or(ErrorCode::InvalidStackDefinition)?;
+        def.rebalance(params.stack_units, params.stack_divisor)?;
+        emit!(StackRebalanced {
+            market: def.market,
+            divisor: def.divisor,
+            units: def.constituents.map(|c| c.units),
+        });
+    }
+    accrue_funding(market, now)?;
+    apply_market_updates(market, params);
+    ensure_admin_threshold(market)?;
+    ensure_risk_bounds(market)?;
+    ensure_dispute_bond(market)
+}
+
+fn reset_market_breaker(market: &mut Account<'_, Market>) -> Result<()> {
+    market.circuit_breaker_until = 0;
+    emit!(CircuitBreakerReset { market: market.key(), ts: Clock::get()?.unix_timestamp });
+    Ok(())
+}
+
+fn revive_market_oracle(market: &mut Account<'_, Market>) -> Result<()> {
+    require!(market.oracle_dead, ErrorCode::OracleAlive);
+    let now = Clock::get()?.unix_timestamp;
+    market.oracle_dead = false;
+    market.dead_nav = 0;
+    market.oracle_dead_ts = 0;
+    market.last_funding_ts = now; // no funding for the dead period
+    emit!(OracleRevived { market: market.key(), ts: now });
+    Ok(())
+}
+
+fn set_market_reduce_only(market: &mut Account<'_, Market>, reduce_only: bool) -> Result<()> {
+    ensure_not_settling(market)?;
+    market.status = if reduce_only { MarketStatus::ReduceOnly } else { MarketStatus::Active };
+    emit!(MarketStatusChanged {
+        market: market.key(),
+        status: market.status,
+        final_nav: market.final_nav,
+        open_deals: market.open_deals,
+        ts: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
+
+/// Freeze the final NAV and move to Settling (Settled with no open deals); see `begin_shutdown`.
+fn begin_market_shutdown<'info>(
+    market: &mut Account<'info, Market>,
+    history: &Option<AccountLoader<'info, NavHistory>>,
+) -> Result<()> {
+    ensure_not_settling(market)?;
+    let now = Clock::get()?.unix_timestamp;
+
+    // Deals may already have settled at a fixed expiry NAV; never replace it
+    if market.final_nav == 0 {
+        market.final_nav = if market.oracle_dead {
+            market.dead_nav
+        } else {
+            ensure_price_fresh(market)?;
+            mark_nav(market, history)?
+        };
+        market.final_nav_ts = now;
+    }
+    require!(market.final_nav > 0, ErrorCode::FinalNavNotSet);
+
+    accrue_funding(market, now)?; // last accrual; funding stops once Settling
+    market.status = if market.open_deals == 0 { MarketStatus::Settled } else { MarketStatus::Settling };
+    emit!(MarketStatusChanged {
+        market: market.key(),
+        status: market.status,
+        final_nav: market.final_nav,
+        open_deals: market.open_deals,
+        ts: now,
+    });
+    Ok(())
+}
+
+/// Accounts for ruling on the pending NAV (resolve_nav_dispute or a ResolveNavDispute proposal).
+/// The token accounts are only needed to refund the bond of a rejected NAV.
+struct NavDisputeResolution<'a, 'info> {
+    token_program: Option<&'a Program<'info, Token>>,
+    fee_vault: Option<&'a Account<'info, TokenAccount>>,
+    market_vault_auth: Option<&'a Account<'info, MarketVaultAuth>>,
+    disputer_refund_ata: Option<&'a Account<'info, TokenAccount>>,
+    nav_history: &'a Option<AccountLoader<'info, NavHistory>>,
+}
+
+impl<'a, 'info> NavDisputeResolution<'a, 'info> {
+    /// `nav_valid = true` applies the pending NAV and slashes any bond into fees; `false` discards
+    /// it and refunds the bond to the disputer.
+    fn resolve(self, market: &mut Account<'info, Market>, nav_valid: bool) -> Result<()> {
+        let p = market.pending_nav;
+        require!(p.posted_ts != 0, ErrorCode::NoPendingNav);
+        let market_key = market.key();
+
+        market.pending_nav = PendingNav::default();
+        market.bonds_held = market.bonds_held.saturating_sub(p.bond);
+        if !nav_valid && p.bond > 0 {
+            transfer_signed_mva(
+                self.token_program.ok_or(ErrorCode::MissingProposalAccounts)?,
+                self.fee_vault.ok_or(ErrorCode::MissingProposalAccounts)?,
+                self.disputer_refund_ata.ok_or(ErrorCode::MissingDisputerAccount)?,
+                self.market_vault_auth.ok_or(ErrorCode::MissingProposalAccounts)?,
+                &market_key,
+                p.bond,
+            )?;
+        }
+
+        emit!(NavDisputeResolved {
+            market: market_key,
+            nav: p.nav,
+            nav_valid,
+            disputer: p.disputer,
+            bond: p.bond,
+        });
+
+        if nav_valid {
+            let now = Clock::get()?.unix_timestamp;
+            apply_nav(market, market_key, p.nav, Some(p.confidence), p.posted_ts, now, self.nav_history)?;
+        }
+        Ok(())
+    }
+}
+
+/// Accounts needed to split the fee vault between the configured fee recipients.
+struct FeeDistribution<'a, 'info> {
+    token_program: &'a Program<'info, Token>,
+    fee_vault: &'a Account<'info, TokenAccount>,
+    market_vault_auth: &'a Account<'info, MarketVaultAuth>,
+    treasury_ata: &'a Account<'info, TokenAccount>,
+    insurance_ata: &'a Account<'info, TokenAccount>,
+    oracle_operator_ata: &'a Account<'info, TokenAccount>,
+}
+
+impl<'a, 'info> FeeDistribution<'a, 'info> {
+    /// Send `amount` (default: all available fees) to the recipients per `market.fee_split`.
+    fn distribute(self, market: &Account<'info, Market>, amount: Option<u64>) -> Result<()> {
+        let split = market.fee_split;
+        require!(split.is_valid(), ErrorCode::InvalidFeeSplit);
+
+        // Dispute bonds held in fee_vault are not fees
+        let available = self.fee_vault.amount.saturating_sub(market.bonds_held);
+        let total = amount.unwrap_or(available);
+        require!(total <= available, ErrorCode::InsufficientFees);
+
+        let (treasury_amount, insurance_amount, oracle_operator_amount) = split.amounts(total)?;
+
+        let market_key = market.key();
+        for (to, amt) in [
+            (self.treasury_ata, treasury_amount),
+            (self.insurance_ata, insurance_amount),
+            (self.oracle_operator_ata, oracle_operator_amount),
+        ] {
+            transfer_signed_mva(self.token_program, self.fee_vault, to, self.market_vault_auth, &market_key, amt)?;
+        }
+
+        emit!(FeesWithdrawn {
+            market: market_key,
+            total,
+            treasury_amount,
+            insurance_amount,
+            oracle_operator_amount,
+        });
+        Ok(())
+    }
+}
+
+/// Accounts needed to pay out both sides of a deal and close its margin vaults.
+struct Settlement<'a, 'info> {
+    token_program: &'a Program<'info, Token>,
+    long_margin_vault: &'a mut Account<'info, TokenAccount>,
+    short_margin_vault: &'a mut Account<'info, TokenAccount>,
+    long_payout_ata: &'a Account<'info, TokenAccount>,
+    short_payout_ata: &'a Account<'info, TokenAccount>,
+    rent_destination: &'a UncheckedAccount<'info>,
+    deal_vault_auth: &'a Account<'info, DealVaultAuth>,
+}
+
+impl<'a, 'info> Settlement<'a, 'info> {
+    /// Drain both vaults to the payout ATAs at `pnl_long`, then close them. Returns (long_payout, short_payout).
+    fn settle(self, deal: &Account<'info, Deal>, pnl_long: i128) -> Result<(u64, u64)> {
+        let long_amt = self.long_margin_vault.amount;
+        let short_amt = self.short_margin_vault.amount;
+        let (long_payout, short_payout) = split_pool(long_amt, short_amt, pnl_long);
+
+        // Each side is paid from its own vault first; the winner tops up from the other vault
+        let long_from_long = long_payout.min(long_amt);
+        let long_from_short = long_payout - long_from_long;
+        let short_from_short = short_amt - long_from_short;
+        let short_from_long = long_amt - long_from_long;
+
+        let dva = self.deal_vault_auth;
+        drain_to(self.token_program, self.long_margin_vault, self.long_payout_ata, dva, deal, long_from_long)?;
+        drain_to(self.token_program, self.short_margin_vault, self.long_payout_ata, dva, deal, long_from_short)?;
+        drain_to(self.token_program, self.short_margin_vault, self.short_payout_ata, dva, deal, short_from_short)?;
+        drain_to(self.token_program, self.long_margin_vault, self.short_payout_ata, dva, deal, short_from_long)?;
+
+        // Refresh balances so the empty-vault check in close sees post-transfer amounts
+        self.long_margin_vault.reload()?;
+        self.short_margin_vault.reload()?;
+        close_signed_token_account(self.token_program, self.long_margin_vault, self.rent_destination, dva, deal)?;
+        close_signed_token_account(self.token_program, self.short_margin_vault, self.rent_destination, dva, deal)?;
+
+        Ok((long_payout, short_payout))
+    }
+}
+
 // CPI helpers (lifetime-safe)
 
 fn transfer_from_user<'info>(
@@ -1248,6 +5369,62 @@ fn drain_to<'info>(
     )
 }
 
+/// Transfer signed by the market vault authority PDA (fee vault owner, or an approved delegate).
+fn transfer_signed_mva<'info>(
+    token_program: &Program<'info, Token>,
+    from_vault: &Account<'info, TokenAccount>,
+    to_account: &Account<'info, TokenAccount>,
+    market_vault_auth: &Account<'info, MarketVaultAuth>,
+    market_key: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    let seeds: [&[u8]; 4] = [VERSION_SEED, b"mva", market_key.as_ref(), &[market_vault_auth.bump]];
+    transfer_signed(
+        token_program,
+        from_vault,
+        to_account,
+        market_vault_auth.to_account_info(),
+        &seeds[..],
+        amount,
+    )
+}
+
+/// Move the whole offer escrow to `to`, then close the escrow ATA (rent to `rent_destination`).
+fn release_offer_escrow<'info>(
+    token_program: &Program<'info, Token>,
+    offer: &Account<'info, Offer>,
+    escrow: &Account<'info, TokenAccount>,
+    to: &Account<'info, TokenAccount>,
+    rent_destination: &AccountInfo<'info>,
+) -> Result<()> {
+    let id_bytes = offer.offer_id.to_le_bytes();
+    let seeds: [&[u8]; 6] = [
+        VERSION_SEED,
+        b"offer",
+        offer.market.as_ref(),
+        offer.maker.as_ref(),
+        &id_bytes,
+        &[offer.bump],
+    ];
+    if escrow.amount > 0 {
+        transfer_signed(token_program, escrow, to, offer.to_account_info(), &seeds[..], escrow.amount)?;
+    }
+    let signer_groups = [&seeds[..]];
+    let cpi = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        CloseAccount {
+            account: escrow.to_account_info(),
+            destination: rent_destination.clone(),
+            authority: offer.to_account_info(),
+        },
+        &signer_groups,
+    );
+    token::close_account(cpi)
+}
+
 fn close_signed_token_account<'info>(
     token_program: &Program<'info, Token>,
     token_acc: &Account<'info, TokenAccount>,
@@ -1277,27 +5454,132 @@ fn close_signed_token_account<'info>(
 /* Admin Multisig Helpers (lifetime-decoupled) */
 // ──────────────────────────────────────────────────────────────────────────────
 
-fn require_admin_or_multisig<'a>(
-    m: &Market,
-    authority_key: Pubkey,
-    remaining: &[AccountInfo<'a>],
-) -> Result<()> {
-    if authority_key == m.authority {
+/// Direct owner path: the market authority, on single-sig markets only. Once `admin_threshold > 1`
+/// the owner is the multisig, and owner actions go through `Proposal` accounts.
+fn require_authority(m: &Market, authority_key: Pubkey) -> Result<()> {
+    require_keys_eq!(authority_key, m.authority, ErrorCode::Unauthorized);
+    require!(m.admin_threshold <= 1, ErrorCode::ProposalRequired);
+    Ok(())
+}
+
+/// The current holder of `role`, or the owner via the direct path (see `require_authority`).
+fn require_authority_or_role(m: &Market, key: Pubkey, role: Role) -> Result<()> {
+    let holder = match role {
+        Role::Guardian => m.guardian,
+        Role::RiskManager => m.risk_manager,
+        Role::FeeManager => m.fee_manager,
+    };
+    if holder != Pubkey::default() && key == holder {
         return Ok(());
     }
-    require_multisig(m, remaining)
+    require_authority(m, key)
 }
 
-fn require_multisig<'a>(m: &Market, infos: &[AccountInfo<'a>]) -> Result<()> {
-    let mut hits: u8 = 0;
-    for ai in infos {
-        if !ai.is_signer { continue; }
-        let k = ai.key();
-        if m.admins.iter().any(|a| *a != Pubkey::default() && *a == k) {
-            hits = hits.saturating_add(1);
-        }
+fn set_market_role(m: &mut Account<'_, Market>, role: Role, holder: Pubkey) {
+    match role {
+        Role::Guardian => m.guardian = holder,
+        Role::RiskManager => m.risk_manager = holder,
+        Role::FeeManager => m.fee_manager = holder,
+    }
+    emit!(RoleSet { market: m.key(), role, holder });
+}
+
+/// Risk-manager updates: margin fields only, each may only get stricter.
+fn require_margin_tightening(m: &Market, p: &MarketUpdateParams) -> Result<()> {
+    let margin_only = MarketUpdateParams {
+        initial_margin_bps: p.initial_margin_bps,
+        maintenance_margin_bps: p.maintenance_margin_bps,
+        max_leverage_bps: p.max_leverage_bps,
+        mm_buffer_bps: p.mm_buffer_bps,
+        ..Default::default()
+    };
+    require!(p.try_to_vec()? == margin_only.try_to_vec()?, ErrorCode::NotRiskReducing);
+    require_risk_reducing(m, p)
+}
+
+/// Instant (untimelocked) updates. Only fields with a clear safe direction may change, and only
+/// that way: margins / buffer / breaker cool-off up; leverage, NAV jump and confidence limits down
+/// (confidence may not go back to 0 = disabled). Each step is capped (margins by
+/// MAX_INSTANT_MARGIN_STEP_BPS, leverage and NAV jump to half the current value); larger moves go
+/// through the timelock. Oracle (incl. staleness, which also drives
+/// declare_oracle_dead), fee, funding, mark, dispute, governance and basket fields are
+/// risk-increasing or ambiguous and need the timelock.
+fn require_risk_reducing(m: &Market, p: &MarketUpdateParams) -> Result<()> {
+    let instant = MarketUpdateParams {
+        initial_margin_bps: p.initial_margin_bps,
+        maintenance_margin_bps: p.maintenance_margin_bps,
+        max_leverage_bps: p.max_leverage_bps,
+        max_nav_jump_bps: p.max_nav_jump_bps,
+        max_confidence_bps: p.max_confidence_bps,
+        mm_buffer_bps: p.mm_buffer_bps,
+        circuit_breaker_secs: p.circuit_breaker_secs,
+        ..Default::default()
+    };
+    require!(p.try_to_vec()? == instant.try_to_vec()?, ErrorCode::ParamNeedsTimelock);
+    if let Some(x) = p.initial_margin_bps     { require_margin_raise(m.initial_margin_bps, x)?; }
+    if let Some(x) = p.maintenance_margin_bps { require_margin_raise(m.maintenance_margin_bps, x)?; }
+    if let Some(x) = p.mm_buffer_bps          { require_margin_raise(m.mm_buffer_bps, x)?; }
+    if let Some(x) = p.circuit_breaker_secs   { require!(x >= m.circuit_breaker_secs, ErrorCode::NotRiskReducing); }
+    if let Some(x) = p.max_leverage_bps       { require_limit_cut(m.max_leverage_bps, x)?; }
+    if let Some(x) = p.max_nav_jump_bps       { require_limit_cut(m.max_nav_jump_bps, x)?; }
+    if let Some(x) = p.max_confidence_bps {
+        require!(x != 0 && (m.max_confidence_bps == 0 || x <= m.max_confidence_bps), ErrorCode::NotRiskReducing);
     }
-    require!((hits as u8) >= m.admin_threshold, ErrorCode::NotEnoughSigners);
+    Ok(())
+}
+
+fn require_margin_raise(current: u16, new: u16) -> Result<()> {
+    require!(new >= current, ErrorCode::NotRiskReducing);
+    require!(new - current <= MAX_INSTANT_MARGIN_STEP_BPS, ErrorCode::ParamNeedsTimelock);
+    Ok(())
+}
+
+fn require_limit_cut(current: u16, new: u16) -> Result<()> {
+    require!(new <= current, ErrorCode::NotRiskReducing);
+    require!(new >= current / 2, ErrorCode::ParamNeedsTimelock);
+    Ok(())
+}
+
+/// 1 <= admin_threshold <= number of admins; 0 would let proposals execute without approvals.
+fn ensure_admin_threshold(m: &Market) -> Result<()> {
+    require!(
+        m.admin_threshold >= 1 && m.admin_threshold as usize <= m.admin_count(),
+        ErrorCode::InvalidAdminSet
+    );
+    Ok(())
+}
+
+fn is_admin(m: &Market, key: Pubkey) -> bool {
+    key != Pubkey::default() && m.admins.contains(&key)
+}
+
+/// Replace `old` with `new` in the admin set: `old = default` adds to a free slot, `new = default`
+/// removes. Rejects duplicates and unknown keys, and keeps 1 <= admin_threshold <= number of admins.
+fn change_admin(m: &mut Account<'_, Market>, old: Pubkey, new: Pubkey) -> Result<()> {
+    require!(old != new, ErrorCode::InvalidAdminSet);
+    require!(new == Pubkey::default() || !m.admins.contains(&new), ErrorCode::InvalidAdminSet);
+    let slot = m.admins.iter().position(|k| *k == old).ok_or(ErrorCode::InvalidAdminSet)?;
+    m.admins[slot] = new;
+    ensure_admin_threshold(m)?;
+
+    emit!(AdminSetChanged {
+        market: m.key(),
+        added: new,
+        removed: old,
+        admin_count: m.admin_count() as u8,
+        admin_threshold: m.admin_threshold,
+    });
+    Ok(())
+}
+
+/// Hand the owner role to `new`. Its admin slot is the old authority's (replace semantics, so a
+/// key that is already an admin is rejected); if the old authority was removed as an admin, `new`
+/// takes a free slot instead.
+fn rotate_market_authority(m: &mut Account<'_, Market>, new: Pubkey) -> Result<()> {
+    require!(new != Pubkey::default(), ErrorCode::InvalidAdminSet);
+    let old = if is_admin(m, m.authority) { m.authority } else { Pubkey::default() };
+    change_admin(m, old, new)?;
+    m.authority = new;
     Ok(())
 }
 
@@ -1345,5 +5627,217 @@ pub enum ErrorCode {
     TimelockNotExpired,
     #[msg("Not enough admin signers")]
     NotEnoughSigners,
+    #[msg("Fee split must sum to 10000 bps with a recipient for every non-zero share")]
+    InvalidFeeSplit,
+    #[msg("Insufficient fee balance")]
+    InsufficientFees,
+    #[msg("NAV outside acceptable range")]
+    NavOutOfRange,
+    #[msg("Invalid NAV band: min above max")]
+    InvalidNavBand,
+    #[msg("Close request expired")]
+    CloseRequestExpired,
+    #[msg("Close request expiry too far in the future")]
+    CloseRequestExpiryTooFar,
+    #[msg("Close notice period has not elapsed")]
+    CloseNoticeNotElapsed,
+    #[msg("Expiry must be in the future")]
+    InvalidExpiry,
+    #[msg("Market has expired; use settle_expired_deal")]
+    MarketExpired,
+    #[msg("Market has not expired")]
+    NotExpired,
+    #[msg("Final settlement NAV not yet posted")]
+    FinalNavNotSet,
+    #[msg("Reduce amount must be below deal size; use close_deal for a full close")]
+    ReduceExceedsSize,
+    #[msg("Offer does not match market or parties")]
+    OfferMismatch,
+    #[msg("Offer expired")]
+    OfferExpired,
+    #[msg("Missing or invalid ed25519 offer signature")]
+    InvalidOfferSignature,
+    #[msg("Offer is restricted to a different taker")]
+    TakerNotAllowed,
+    #[msg("NAV below caller's min_nav")]
+    NavBelowMin,
+    #[msg("NAV above caller's max_nav")]
+    NavAboveMax,
+    #[msg("Transaction deadline exceeded")]
+    DeadlineExceeded,
+    #[msg("Invalid oracle set (publishers, quorum or account)")]
+    InvalidOracleSet,
+    #[msg("Market NAV comes from its oracle set; use submit_nav")]
+    OracleSetActive,
+    #[msg("Missing, wrong or empty NAV history account")]
+    InvalidNavHistory,
+    #[msg("Instruction not available for this market's oracle source")]
+    WrongOracleSource,
+    #[msg("Invalid or unusable price feed account")]
+    InvalidFeed,
+    #[msg("Invalid stack definition, constituents or feed accounts")]
+    InvalidStackDefinition,
+    #[msg("Stack rebalances must go through propose/execute_market_params")]
+    RebalanceNeedsTimelock,
+    #[msg("Parameter can only change through the timelock")]
+    ParamNeedsTimelock,
+    #[msg("Timelock delay below min_timelock_secs")]
+    TimelockTooShort,
+    #[msg("A pending params update already exists; cancel it first")]
+    PendingParamsExist,
+    #[msg("Pending params expired")]
+    PendingParamsExpired,
+    #[msg("No pending NAV")]
+    NoPendingNav,
+    #[msg("Pending NAV is under dispute")]
+    NavDisputed,
+    #[msg("Dispute window has closed")]
+    DisputeWindowClosed,
+    #[msg("Dispute window still open")]
+    DisputeWindowOpen,
+    #[msg("Disputer refund account required")]
+    MissingDisputerAccount,
+    #[msg("Oracle is dead; use emergency_settle_deal")]
+    OracleDead,
+    #[msg("Oracle is not dead")]
+    OracleAlive,
+    #[msg("Market is not accepting new positions")]
+    MarketNotActive,
+    #[msg("Market is shutting down; use settle_deal_at_final_nav")]
+    MarketShuttingDown,
+    #[msg("Market is not settling")]
+    MarketNotSettling,
+    #[msg("Market is not settled")]
+    MarketNotSettled,
+    #[msg("Fee vault not empty; withdraw fees first")]
+    FeeVaultNotEmpty,
+    #[msg("Unknown pause flag bits")]
+    InvalidPauseFlags,
+    #[msg("Invalid admin set change (duplicate or unknown key, no free slot, or threshold above admin count)")]
+    InvalidAdminSet,
+    #[msg("Change is not risk-reducing")]
+    NotRiskReducing,
+    #[msg("Admin already approved this proposal")]
+    AlreadyApproved,
+    #[msg("Accounts required by the proposal action are missing")]
+    MissingProposalAccounts,
+    #[msg("A dispute window requires a non-zero dispute bond")]
+    DisputeBondRequired,
+    #[msg("The pending NAV is not the one this proposal resolves")]
+    PendingNavChanged,
+    #[msg("Multisig market: owner actions go through a proposal")]
+    ProposalRequired,
+    #[msg("Setup-only instruction: the market already has a NAV")]
+    MarketAlreadyLive,
+    #[msg("Circuit breaker duration must be positive")]
+    InvalidBreakerDuration,
+    #[msg("Risk parameters out of bounds")]
+    InvalidRiskParams,
+    #[msg("Execute or cancel the market's open proposals first")]
+    ProposalsOutstanding,
+    #[msg("Proposal expired; cancel and re-propose it")]
+    ProposalExpired,
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_code(r: Result<FeedPrice>) -> u32 {
+        match r {
+            Err(Error::AnchorError(e)) => e.error_code_number,
+            other => panic!("expected an anchor error, got {:?}", other.map(|f| f.price)),
+        }
+    }
+
+    fn invalid_feed() -> u32 {
+        ErrorCode::InvalidFeed as u32 + anchor_lang::error::ERROR_CODE_OFFSET
+    }
+
+    fn pyth_fixture(expo: i32, price: i64, conf: u64, status: u32, publish_ts: i64) -> Vec<u8> {
+        let mut d = vec![0u8; 240];
+        d[0..4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+        d[8..12].copy_from_slice(&PYTH_ATYPE_PRICE.to_le_bytes());
+        d[20..24].copy_from_slice(&expo.to_le_bytes());
+        d[96..104].copy_from_slice(&publish_ts.to_le_bytes());
+        d[208..216].copy_from_slice(&price.to_le_bytes());
+        d[216..224].copy_from_slice(&conf.to_le_bytes());
+        d[224..228].copy_from_slice(&status.to_le_bytes());
+        d
+    }
+
+    fn switchboard_fixture(mantissa: i128, scale: u32, std_mantissa: i128, std_scale: u32, publish_ts: i64) -> Vec<u8> {
+        let r = SWITCHBOARD_ROUND_OFFSET;
+        let mut d = vec![0u8; r + 128];
+        d[r + 17..r + 25].copy_from_slice(&publish_ts.to_le_bytes());
+        d[r + 25..r + 41].copy_from_slice(&mantissa.to_le_bytes());
+        d[r + 41..r + 45].copy_from_slice(&scale.to_le_bytes());
+        d[r + 45..r + 61].copy_from_slice(&std_mantissa.to_le_bytes());
+        d[r + 61..r + 65].copy_from_slice(&std_scale.to_le_bytes());
+        d
+    }
+
+    #[test]
+    fn pyth_fixture_parses() {
+        let f = parse_pyth_price(&pyth_fixture(-8, 12_345_000_000, 2_500_000, PYTH_STATUS_TRADING, 1_700_000_000)).unwrap();
+        assert_eq!(f.price, 12_345_000_000);
+        assert_eq!(f.conf, 2_500_000);
+        assert_eq!(f.decimals, 8);
+        assert_eq!(f.publish_ts, 1_700_000_000);
+        assert_eq!(scale_amount(f.price, f.decimals, 6).unwrap(), 123_450_000);
+    }
+
+    #[test]
+    fn pyth_fixture_rejects_bad_header_status_and_price() {
+        let mut bad_magic = pyth_fixture(-8, 1, 0, PYTH_STATUS_TRADING, 0);
+        bad_magic[0] ^= 0xff;
+        assert_eq!(error_code(parse_pyth_price(&bad_magic)), invalid_feed());
+        assert_eq!(error_code(parse_pyth_price(&pyth_fixture(-8, 1, 0, 0, 0))), invalid_feed());
+        assert_eq!(error_code(parse_pyth_price(&pyth_fixture(-8, 0, 0, PYTH_STATUS_TRADING, 0))), invalid_feed());
+        assert_eq!(error_code(parse_pyth_price(&pyth_fixture(2, 1, 0, PYTH_STATUS_TRADING, 0))), invalid_feed());
+        assert_eq!(error_code(parse_pyth_price(&[0u8; 100])), invalid_feed());
+    }
+
+    #[test]
+    fn pyth_fixture_with_untrusted_exponent_errors_instead_of_panicking() {
+        for expo in [-39, -1_000, i32::MIN] {
+            let d = pyth_fixture(expo, 1, 0, PYTH_STATUS_TRADING, 0);
+            assert_eq!(error_code(parse_pyth_price(&d)), invalid_feed());
+        }
+    }
+
+    #[test]
+    fn switchboard_fixture_parses_and_rescales_std_dev() {
+        let f = parse_switchboard_price(&switchboard_fixture(98_765_432, 6, 15, 3, 1_700_000_123)).unwrap();
+        assert_eq!(f.price, 98_765_432);
+        assert_eq!(f.decimals, 6);
+        assert_eq!(f.conf, 15_000);
+        assert_eq!(f.publish_ts, 1_700_000_123);
+    }
+
+    #[test]
+    fn switchboard_fixture_with_untrusted_scale_errors_instead_of_panicking() {
+        assert_eq!(error_code(parse_switchboard_price(&switchboard_fixture(1, u32::MAX, 0, 0, 0))), invalid_feed());
+        assert_eq!(error_code(parse_switchboard_price(&switchboard_fixture(1, 0, 1, 1_000, 0))), invalid_feed());
+        assert_eq!(error_code(parse_switchboard_price(&switchboard_fixture(0, 6, 0, 6, 0))), invalid_feed());
+        assert_eq!(error_code(parse_switchboard_price(&[0u8; 64])), invalid_feed());
+    }
+
+    #[test]
+    fn scale_amount_overflow_is_an_error() {
+        assert!(scale_amount(1, 0, 39).is_err());
+        assert!(scale_amount(1, u32::MAX, 0).is_err());
+        assert_eq!(scale_amount(1, 0, 38).unwrap(), 10u128.pow(38));
+    }
+
+    #[test]
+    fn fee_split_dust_goes_to_the_largest_configured_share() {
+        let split = FeeSplit { treasury_bps: 0, insurance_bps: 6_667, oracle_operator_bps: 3_333, ..Default::default() };
+        assert_eq!(split.amounts(10).unwrap(), (0, 7, 3));
+        assert_eq!(split.amounts(1).unwrap(), (0, 1, 0));
+
+        let split = FeeSplit { treasury_bps: 5_000, insurance_bps: 5_000, oracle_operator_bps: 0, ..Default::default() };
+        assert_eq!(split.amounts(3).unwrap(), (2, 1, 0));
+    }
+}
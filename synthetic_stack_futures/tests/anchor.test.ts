@@ -3,50 +3,331 @@
 
 
 describe("Synthetic Stack Futures – minimal test (no SPL helpers)", () => {
-  it("initMarket + postNav + pause toggle", async () => {
-    const wallet = pg.wallet; // has publicKey & signTransaction
+  // --- constants for SPL Token Program (classic token; not token-2022) ---
+  const TOKEN_PROGRAM_ID = new web3.PublicKey(
+    "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"
+  );
+  const ASSOCIATED_TOKEN_PROGRAM_ID = new web3.PublicKey(
+    "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"
+  );
+  // Size of a Mint account (classic token = 82 bytes)
+  const MINT_SIZE = 82;
+
+  // Helper: build InitializeMint instruction data (tag = 0)
+  // layout: u8(tag=0) | u8(decimals) | [32]mintAuthority | u8(hasFreezeAuth) | [32]?freezeAuthority
+  function createInitializeMintIx(
+    mintPubkey,
+    decimals,
+    mintAuthority,
+    freezeAuthority
+  ) {
+    const tag = 0; // InitializeMint
+    const hasFreeze = freezeAuthority ? 1 : 0;
+
+    const data = Buffer.alloc(1 + 1 + 32 + 1 + (hasFreeze ? 32 : 0));
+    let o = 0;
+    data.writeUInt8(tag, o); o += 1;
+    data.writeUInt8(decimals, o); o += 1;
+    Buffer.from(mintAuthority.toBuffer()).copy(data, o); o += 32;
+    data.writeUInt8(hasFreeze, o); o += 1;
+    if (hasFreeze) {
+      Buffer.from(freezeAuthority.toBuffer()).copy(data, o); o += 32;
+    }
+
+    return new web3.TransactionInstruction({
+      programId: TOKEN_PROGRAM_ID,
+      keys: [
+        { pubkey: mintPubkey, isSigner: false, isWritable: true },
+        { pubkey: web3.SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false },
+      ],
+      data,
+    });
+  }
+
+  const PROGRAM_ID = pg.program.programId;
+  const VERSION_SEED = Buffer.from("v1");
+
+  function deriveAta(owner, mint) {
+    return web3.PublicKey.findProgramAddressSync(
+      [owner.toBuffer(), TOKEN_PROGRAM_ID.toBuffer(), mint.toBuffer()],
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    )[0];
+  }
+
+  // AssociatedTokenAccount Create (empty data)
+  function createAtaIx(payer, ata, owner, mint) {
+    return new web3.TransactionInstruction({
+      programId: ASSOCIATED_TOKEN_PROGRAM_ID,
+      keys: [
+        { pubkey: payer, isSigner: true, isWritable: true },
+        { pubkey: ata, isSigner: false, isWritable: true },
+        { pubkey: owner, isSigner: false, isWritable: false },
+        { pubkey: mint, isSigner: false, isWritable: false },
+        { pubkey: web3.SystemProgram.programId, isSigner: false, isWritable: false },
+        { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+      ],
+      data: Buffer.alloc(0),
+    });
+  }
+
+  // Token MintTo: u8(tag=7) | u64(amount)
+  function mintToIx(mint, dest, authority, amount) {
+    const data = Buffer.alloc(9);
+    data.writeUInt8(7, 0);
+    data.writeBigUInt64LE(BigInt(amount), 1);
+    return new web3.TransactionInstruction({
+      programId: TOKEN_PROGRAM_ID,
+      keys: [
+        { pubkey: mint, isSigner: false, isWritable: true },
+        { pubkey: dest, isSigner: false, isWritable: true },
+        { pubkey: authority, isSigner: true, isWritable: false },
+      ],
+      data,
+    });
+  }
 
-    // --- constants for SPL Token Program (classic token; not token-2022) ---
-    const TOKEN_PROGRAM_ID = new web3.PublicKey(
-      "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"
+  // Token Approve: u8(tag=4) | u64(amount)
+  function approveIx(source, delegate, owner, amount) {
+    const data = Buffer.alloc(9);
+    data.writeUInt8(4, 0);
+    data.writeBigUInt64LE(BigInt(amount), 1);
+    return new web3.TransactionInstruction({
+      programId: TOKEN_PROGRAM_ID,
+      keys: [
+        { pubkey: source, isSigner: false, isWritable: true },
+        { pubkey: delegate, isSigner: false, isWritable: false },
+        { pubkey: owner, isSigner: true, isWritable: false },
+      ],
+      data,
+    });
+  }
+
+  // Bytes a maker signs for open_deal_from_offer: OFFER_DOMAIN || borsh(SignedOffer)
+  const OFFER_DOMAIN = Buffer.from("synthetic_stack_futures:offer:v1");
+  function signedOfferMessage(o) {
+    const u64 = (v) => new BN(v).toArrayLike(Buffer, "le", 8);
+    return Buffer.concat([
+      OFFER_DOMAIN,
+      o.market.toBuffer(),
+      o.maker.toBuffer(),
+      Buffer.from([o.makerIsLong ? 1 : 0]),
+      ...[o.size, o.minNav, o.maxNav, o.makerDeposit, o.expiryTs, o.nonce].map(u64),
+    ]);
+  }
+
+  const wallet = pg.wallet; // has publicKey & signTransaction
+
+  async function sendTx(ixs, signers = []) {
+    const tx = new web3.Transaction().add(...ixs);
+    tx.feePayer = wallet.publicKey;
+    tx.recentBlockhash = (await pg.connection.getLatestBlockhash()).blockhash;
+    if (signers.length) tx.partialSign(...signers);
+    const signed = await wallet.signTransaction(tx);
+    const sig = await pg.connection.sendRawTransaction(signed.serialize());
+    await pg.connection.confirmTransaction(sig, "confirmed");
+    return sig;
+  }
+
+  async function tokenBalance(ata) {
+    return new BN((await pg.connection.getTokenAccountBalance(ata)).value.amount);
+  }
+
+  async function expectError(label, code, fn) {
+    let failed = false;
+    try {
+      await fn();
+    } catch (e) {
+      failed = true;
+      const text = [String(e), ...(e.logs ?? [])].join("\n"); // runtime errors (e.g. "already in use") are in the logs
+      assert.include(text, code, `${label} failed for another reason: ${e}`);
+    }
+    assert.isTrue(failed, `${label} should fail with ${code}`);
+  }
+
+  // Poll until the cluster clock (block time) has passed `ts`
+  async function waitForChainTime(ts) {
+    for (;;) {
+      const now = await pg.connection.getBlockTime(await pg.connection.getSlot("confirmed"));
+      if (now !== null && now > ts) return;
+      await new Promise((r) => setTimeout(r, 500));
+    }
+  }
+
+  function proposalPda(marketPda, proposalId) {
+    return web3.PublicKey.findProgramAddressSync(
+      [VERSION_SEED, Buffer.from("proposal"), marketPda.toBuffer(), new BN(proposalId).toArrayLike(Buffer, "le", 8)],
+      PROGRAM_ID
+    )[0];
+  }
+
+  // Create a proposal as the wallet (admin[0]); returns its id
+  async function createProposal(marketPda, action, delaySecs = 0) {
+    const { proposalCount } = await pg.program.account.market.fetch(marketPda);
+    await pg.program.methods
+      .createProposal(action, delaySecs)
+      .accounts({
+        proposer: wallet.publicKey,
+        market: marketPda,
+        proposal: proposalPda(marketPda, proposalCount),
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+    return proposalCount;
+  }
+
+  async function approveProposal(marketPda, proposalId, adminKp) {
+    return pg.program.methods
+      .approveProposal()
+      .accounts({ admin: adminKp.publicKey, market: marketPda, proposal: proposalPda(marketPda, proposalId) })
+      .signers([adminKp])
+      .rpc();
+  }
+
+  async function executeProposal(marketPda, proposalId, extra = {}) {
+    const proposal = proposalPda(marketPda, proposalId);
+    const { proposer } = await pg.program.account.proposal.fetch(proposal);
+    return pg.program.methods
+      .executeProposal()
+      .accounts({
+        executor: wallet.publicKey,
+        market: marketPda,
+        proposal,
+        proposer,
+        stackDefinition: null,
+        marketVaultAuth: null,
+        feeVault: null,
+        treasuryAta: null,
+        insuranceAta: null,
+        oracleOperatorAta: null,
+        tokenProgram: null,
+        disputerRefundAta: null,
+        navHistory: null,
+        oracleSet: null,
+        ...extra,
+      })
+      .rpc();
+  }
+
+  // Fresh quote mint + market (wallet = authority, oracle and long), a funded short keypair and NAV 1.0
+  // (unless `initialNav` is false, leaving the market in setup)
+  async function setupMarket(overrides = {}, { initialNav = true } = {}) {
+    const mintKp = web3.Keypair.generate();
+    const mintRent = await pg.connection.getMinimumBalanceForRentExemption(MINT_SIZE);
+    await sendTx(
+      [
+        web3.SystemProgram.createAccount({
+          fromPubkey: wallet.publicKey,
+          newAccountPubkey: mintKp.publicKey,
+          lamports: mintRent,
+          space: MINT_SIZE,
+          programId: TOKEN_PROGRAM_ID,
+        }),
+        createInitializeMintIx(mintKp.publicKey, 6, wallet.publicKey, null),
+      ],
+      [mintKp]
     );
-    const ASSOCIATED_TOKEN_PROGRAM_ID = new web3.PublicKey(
-      "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"
+    const mint = mintKp.publicKey;
+
+    const stackId = web3.Keypair.generate().publicKey;
+    const [marketPda] = web3.PublicKey.findProgramAddressSync(
+      [VERSION_SEED, Buffer.from("market"), wallet.publicKey.toBuffer(), mint.toBuffer(), stackId.toBuffer()],
+      PROGRAM_ID
     );
-    // Size of a Mint account (classic token = 82 bytes)
-    const MINT_SIZE = 82;
+    const [mvaPda] = web3.PublicKey.findProgramAddressSync([VERSION_SEED, Buffer.from("mva"), marketPda.toBuffer()], PROGRAM_ID);
+    const feeVaultAta = deriveAta(mvaPda, mint);
 
-    // Helper: build InitializeMint instruction data (tag = 0)
-    // layout: u8(tag=0) | u8(decimals) | [32]mintAuthority | u8(hasFreezeAuth) | [32]?freezeAuthority
-    function createInitializeMintIx(
-      mintPubkey,
-      decimals,
-      mintAuthority,
-      freezeAuthority
-    ) {
-      const tag = 0; // InitializeMint
-      const hasFreeze = freezeAuthority ? 1 : 0;
-
-      const data = Buffer.alloc(1 + 1 + 32 + 1 + (hasFreeze ? 32 : 0));
-      let o = 0;
-      data.writeUInt8(tag, o); o += 1;
-      data.writeUInt8(decimals, o); o += 1;
-      Buffer.from(mintAuthority.toBuffer()).copy(data, o); o += 32;
-      data.writeUInt8(hasFreeze, o); o += 1;
-      if (hasFreeze) {
-        Buffer.from(freezeAuthority.toBuffer()).copy(data, o); o += 32;
-      }
+    const params = {
+      oracleAuthority: wallet.publicKey,
+      priceDecimals: 6,
+      initialMarginBps: 1000,
+      maintenanceMarginBps: 500,
+      feeBps: 10,
+      liquidatorBps: 50,
+      priceStaleSeconds: 300,
+      maxLeverageBps: 10_000,
+      maxNavJumpBps: 5_000,
+      maxConfidenceBps: 0,
+      mmBufferBps: 100,
+      adminThreshold: 1,
+      closeNoticeSecs: 86_400,
+      expiryTs: null,
+      ...overrides,
+    };
+    await pg.program.methods
+      .initMarket(stackId, params)
+      .accounts({
+        authority: wallet.publicKey,
+        quoteMint: mint,
+        market: marketPda,
+        marketVaultAuth: mvaPda,
+        feeVault: feeVaultAta,
+        systemProgram: web3.SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
 
-      return new web3.TransactionInstruction({
-        programId: TOKEN_PROGRAM_ID,
-        keys: [
-          { pubkey: mintPubkey, isSigner: false, isWritable: true },
-          { pubkey: web3.SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false },
-        ],
-        data,
-      });
+    const shortKp = web3.Keypair.generate();
+    const longAta = deriveAta(wallet.publicKey, mint);
+    const shortAta = deriveAta(shortKp.publicKey, mint);
+    await sendTx([
+      createAtaIx(wallet.publicKey, longAta, wallet.publicKey, mint),
+      createAtaIx(wallet.publicKey, shortAta, shortKp.publicKey, mint),
+      mintToIx(mint, longAta, wallet.publicKey, 10_000_000),
+      mintToIx(mint, shortAta, wallet.publicKey, 10_000_000),
+    ]);
+
+    const nav = new BN(1_000_000);
+    const postNav = (value) =>
+      pg.program.methods
+        .postNav(value, null)
+        .accounts({ market: marketPda, oracleAuthority: wallet.publicKey, navHistory: null })
+        .rpc();
+    if (initialNav) await postNav(nav);
+
+    function dealAccounts(clientOrderId, long = wallet.publicKey, short = shortKp.publicKey) {
+      const idLe = new BN(clientOrderId).toArrayLike(Buffer, "le", 8);
+      const [dealPda] = web3.PublicKey.findProgramAddressSync(
+        [VERSION_SEED, Buffer.from("deal"), marketPda.toBuffer(), long.toBuffer(), short.toBuffer(), idLe],
+        PROGRAM_ID
+      );
+      const seed = (label) =>
+        web3.PublicKey.findProgramAddressSync([VERSION_SEED, Buffer.from(label), dealPda.toBuffer()], PROGRAM_ID)[0];
+      return { dealPda, dvaPda: seed("deal_vault_auth"), longVault: seed("long_vault"), shortVault: seed("short_vault") };
     }
 
+    // size 1.0 unit at NAV 1.0 => notional 1_000_000 quote units
+    function openDeal(clientOrderId, deposit = 1_000_000) {
+      const a = dealAccounts(clientOrderId);
+      return pg.program.methods
+        .openDeal(new BN(clientOrderId), new BN(1_000_000), new BN(deposit), new BN(deposit), null)
+        .accounts({
+          long: wallet.publicKey,
+          short: shortKp.publicKey,
+          market: marketPda,
+          quoteMint: mint,
+          longSource: longAta,
+          shortSource: shortAta,
+          deal: a.dealPda,
+          dealVaultAuth: a.dvaPda,
+          longMarginVault: a.longVault,
+          shortMarginVault: a.shortVault,
+          feeVault: feeVaultAta,
+          marketVaultAuth: mvaPda,
+          systemProgram: web3.SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([shortKp])
+        .rpc();
+    }
+
+    return { mint, marketPda, mvaPda, feeVaultAta, shortKp, longAta, shortAta, nav, postNav, dealAccounts, openDeal };
+  }
+
+  it("initMarket + postNav + pause toggle", async () => {
     // --- (optional) airdrop for fees on local validator ---
     try {
       const sig = await pg.connection.requestAirdrop(wallet.publicKey, 1_000_000_000);
@@ -94,8 +375,6 @@ describe("Synthetic Stack Futures – minimal test (no SPL helpers)", () => {
     // Seeds:
     // - market: [b"v1", b"market", authority, quote_mint, stack_id]
     // - mva:    [b"v1", b"mva", market]
-    const PROGRAM_ID = pg.program.programId;
-    const VERSION_SEED = Buffer.from("v1");
     const stackId = web3.Keypair.generate().publicKey;
 
     const [marketPda] = await web3.PublicKey.findProgramAddress(
@@ -143,6 +422,8 @@ describe("Synthetic Stack Futures – minimal test (no SPL helpers)", () => {
       maxConfidenceBps: 0,          // disable confidence gate
       mmBufferBps: 100,             // +1% buffer
       adminThreshold: 1,            // single-sig admin
+      closeNoticeSecs: 86_400,      // 1 day before unilateral close
+      expiryTs: null,               // perpetual
     };
 
     // --- 4) Debug accounts before calling initMarket ---
@@ -246,6 +527,7 @@ describe("Synthetic Stack Futures – minimal test (no SPL helpers)", () => {
       .accounts({
         market: marketPda,
         oracleAuthority: wallet.publicKey,
+        navHistory: null, // no NavHistory configured
       })
       .rpc();
     await pg.connection.confirmTransaction(txNav, "confirmed");
@@ -289,6 +571,825 @@ describe("Synthetic Stack Futures – minimal test (no SPL helpers)", () => {
 
     console.log("🎉 All tests passed - minimal flow OK!");
   });
+
+  it("circuit breaker: a NAV jump trips the breaker and blocks open_deal and liquidate", async () => {
+    const m = await setupMarket();
+    await m.openDeal(1);
+    const deal1 = m.dealAccounts(1);
+    assert.equal((await pg.program.account.deal.fetch(deal1.dealPda)).isOpen, true);
+
+    // +100% is beyond max_nav_jump_bps (50%)
+    const jumpNav = m.nav.muln(2);
+    await m.postNav(jumpNav);
+    const tripped = await pg.program.account.market.fetch(m.marketPda);
+    assert.equal(tripped.lastNav.toString(), m.nav.toString()); // jump not applied
+    assert.equal(tripped.breakerNav.toString(), jumpNav.toString()); // kept as pending mark
+    assert.isAbove(tripped.circuitBreakerUntil.toNumber(), tripped.lastTs.toNumber()); // cool-off in the future
+
+    await expectError("open_deal", "CircuitBreaker", () => m.openDeal(2));
+    await expectError("liquidate", "CircuitBreaker", () =>
+      pg.program.methods
+        .liquidate()
+        .accounts({
+          liquidator: wallet.publicKey,
+          market: m.marketPda,
+          deal: deal1.dealPda,
+          quoteMint: m.mint,
+          longMarginVault: deal1.longVault,
+          shortMarginVault: deal1.shortVault,
+          longPayoutAta: m.longAta,
+          shortPayoutAta: m.shortAta,
+          liquidatorAta: m.longAta,
+          marketAuthority: wallet.publicKey,
+          dealVaultAuth: deal1.dvaPda,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          navHistory: null,
+        })
+        .rpc()
+    );
+  });
+
+  it("withdraw_margin: per-side vaults and IM + buffer floor", async () => {
+    const m = await setupMarket();
+    await m.openDeal(1);
+    const d = m.dealAccounts(1);
+
+    const withdrawLong = (amount, vaults = d) =>
+      pg.program.methods
+        .withdrawMarginLong(new BN(amount))
+        .accounts({
+          long: wallet.publicKey,
+          deal: d.dealPda,
+          market: m.marketPda,
+          quoteMint: m.mint,
+          longMarginVault: vaults.longVault,
+          shortMarginVault: vaults.shortVault,
+          longDestination: m.longAta,
+          dealVaultAuth: d.dvaPda,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          navHistory: null,
+        })
+        .rpc();
+
+    // Passing the short's vault as the long vault would drain the counterparty's collateral
+    await expectError("withdraw from swapped vaults", "ConstraintSeeds", () =>
+      withdrawLong(1, { longVault: d.shortVault, shortVault: d.longVault })
+    );
+
+    const own = (await tokenBalance(d.longVault)).toNumber();
+    const required = 110_000; // (IM 10% + buffer 1%) of the 1_000_000 notional
+    await expectError("withdraw below IM + buffer", "InsufficientMargin", () => withdrawLong(own - required + 1_000));
+    await expectError("withdraw below MM", "InsufficientMargin", () => withdrawLong(own));
+
+    await withdrawLong(own - required - 1_000);
+    assert.equal((await tokenBalance(d.longVault)).toNumber(), required + 1_000);
+    const dealAcc = await pg.program.account.deal.fetch(d.dealPda);
+    assert.equal(dealAcc.longMargin.toNumber(), required + 1_000);
+  });
+
+  it("accept_close: the counterparty's trade guard bounds the settlement NAV", async () => {
+    const m = await setupMarket();
+    await m.openDeal(1);
+    const d = m.dealAccounts(1);
+    const [closeRequest] = web3.PublicKey.findProgramAddressSync(
+      [VERSION_SEED, Buffer.from("close_req"), d.dealPda.toBuffer(), m.shortKp.publicKey.toBuffer()],
+      PROGRAM_ID
+    );
+    const now = Math.floor(Date.now() / 1000);
+    await pg.program.methods
+      .requestClose(null, null, new BN(now + 3600), null)
+      .accounts({
+        requester: m.shortKp.publicKey,
+        market: m.marketPda,
+        deal: d.dealPda,
+        closeRequest,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .signers([m.shortKp])
+      .rpc();
+
+    const accept = (guard) =>
+      pg.program.methods
+        .acceptClose(guard)
+        .accounts({
+          signer: wallet.publicKey,
+          market: m.marketPda,
+          deal: d.dealPda,
+          closeRequest,
+          requester: m.shortKp.publicKey,
+          quoteMint: m.mint,
+          longMarginVault: d.longVault,
+          shortMarginVault: d.shortVault,
+          longPayoutAta: m.longAta,
+          shortPayoutAta: m.shortAta,
+          marketAuthority: wallet.publicKey,
+          dealVaultAuth: d.dvaPda,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          navHistory: null,
+        })
+        .rpc();
+    await expectError("NAV above the accepter's max", "NavAboveMax", () =>
+      accept({ minNav: null, maxNav: new BN(999_999), deadlineTs: null })
+    );
+    await expectError("accepted after the deadline", "DeadlineExceeded", () =>
+      accept({ minNav: null, maxNav: null, deadlineTs: new BN(now - 60) })
+    );
+
+    await accept({ minNav: new BN(990_000), maxNav: new BN(1_010_000), deadlineTs: new BN(now + 600) });
+    assert.isFalse((await pg.program.account.deal.fetch(d.dealPda)).isOpen);
+    assert.isNull(await pg.connection.getAccountInfo(closeRequest));
+  });
+
+  it("open_deal_from_offer: ed25519-signed maker offer, bad signatures and nonce replay", async () => {
+    const m = await setupMarket();
+    const maker = m.shortKp; // signs off-chain and funds through a delegate approval; the taker goes long
+    const now = Math.floor(Date.now() / 1000);
+    const offer = {
+      market: m.marketPda,
+      maker: maker.publicKey,
+      makerIsLong: false,
+      size: new BN(1_000_000),
+      minNav: new BN(900_000),
+      maxNav: new BN(1_100_000),
+      makerDeposit: new BN(200_000),
+      expiryTs: new BN(now + 3600),
+      nonce: new BN(77),
+    };
+    await sendTx([approveIx(m.shortAta, m.mvaPda, maker.publicKey, 1_000_000)], [maker]);
+
+    const sign = (o, signerKp = maker) =>
+      web3.Ed25519Program.createInstructionWithPrivateKey({
+        privateKey: signerKp.secretKey,
+        message: signedOfferMessage(o),
+      });
+    const fill = (o, ed25519Ix, taker = null, takerAta = m.longAta) => {
+      const long = taker ? taker.publicKey : wallet.publicKey;
+      const d = m.dealAccounts(o.nonce, long, maker.publicKey);
+      const [offerNonce] = web3.PublicKey.findProgramAddressSync(
+        [VERSION_SEED, Buffer.from("offer_nonce"), maker.publicKey.toBuffer(), o.nonce.toArrayLike(Buffer, "le", 8)],
+        PROGRAM_ID
+      );
+      return pg.program.methods
+        .openDealFromOffer(o, new BN(200_000))
+        .accounts({
+          taker: long,
+          maker: maker.publicKey,
+          long,
+          short: maker.publicKey,
+          market: m.marketPda,
+          quoteMint: m.mint,
+          longSource: takerAta,
+          shortSource: m.shortAta,
+          deal: d.dealPda,
+          dealVaultAuth: d.dvaPda,
+          longMarginVault: d.longVault,
+          shortMarginVault: d.shortVault,
+          offerNonce,
+          feeVault: m.feeVaultAta,
+          marketVaultAuth: m.mvaPda,
+          instructionsSysvar: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          systemProgram: web3.SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        })
+        .preInstructions(ed25519Ix ? [ed25519Ix] : [])
+        .signers(taker ? [taker] : [])
+        .rpc();
+    };
+
+    await expectError("offer without an ed25519 instruction", "InvalidOfferSignature", () => fill(offer, null));
+    const impostor = web3.Keypair.generate();
+    await expectError("offer signed by another key", "InvalidOfferSignature", () =>
+      fill(offer, sign(offer, impostor))
+    );
+    const inflated = { ...offer, size: new BN(2_000_000) };
+    await expectError("terms changed after signing", "InvalidOfferSignature", () => fill(inflated, sign(offer)));
+
+    await fill(offer, sign(offer));
+    const deal = await pg.program.account.deal.fetch(m.dealAccounts(77).dealPda);
+    assert.isTrue(deal.isOpen);
+    assert.equal(deal.short.toBase58(), maker.publicKey.toBase58());
+    assert.equal(deal.clientOrderId.toNumber(), 77);
+
+    // A second taker (a different deal PDA) cannot reuse the maker's nonce
+    const taker2 = web3.Keypair.generate();
+    const taker2Ata = deriveAta(taker2.publicKey, m.mint);
+    await sendTx([
+      web3.SystemProgram.transfer({ fromPubkey: wallet.publicKey, toPubkey: taker2.publicKey, lamports: 50_000_000 }),
+      createAtaIx(wallet.publicKey, taker2Ata, taker2.publicKey, m.mint),
+      mintToIx(m.mint, taker2Ata, wallet.publicKey, 1_000_000),
+    ]);
+    await expectError("replayed offer nonce", "already in use", () => fill(offer, sign(offer), taker2, taker2Ata));
+  });
+
+  it("make_offer / take_offer / cancel_offer: escrowed maker margin, allowed taker, maker-only cancel", async () => {
+    const m = await setupMarket();
+    const maker = m.shortKp; // maker goes short; the taker goes long
+    const offerPda = (offerId) =>
+      web3.PublicKey.findProgramAddressSync(
+        [VERSION_SEED, Buffer.from("offer"), m.marketPda.toBuffer(), maker.publicKey.toBuffer(), new BN(offerId).toArrayLike(Buffer, "le", 8)],
+        PROGRAM_ID
+      )[0];
+    const makeOffer = async (offerId, allowedTaker = null) => {
+      const offer = offerPda(offerId);
+      await pg.program.methods
+        .makeOffer(new BN(offerId), false, new BN(1_000_000), 500, new BN(200_000), null, allowedTaker)
+        .accounts({
+          maker: maker.publicKey,
+          market: m.marketPda,
+          quoteMint: m.mint,
+          offer,
+          escrow: deriveAta(offer, m.mint),
+          makerSource: m.shortAta,
+          systemProgram: web3.SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([maker])
+        .rpc();
+      return offer;
+    };
+    const takeOffer = (offerId, taker = null, takerAta = m.longAta) => {
+      const offer = offerPda(offerId);
+      const long = taker ? taker.publicKey : wallet.publicKey;
+      const d = m.dealAccounts(offerId, long, maker.publicKey);
+      return pg.program.methods
+        .takeOffer(new BN(200_000))
+        .accounts({
+          taker: long,
+          maker: maker.publicKey,
+          long,
+          short: maker.publicKey,
+          market: m.marketPda,
+          quoteMint: m.mint,
+          offer,
+          escrow: deriveAta(offer, m.mint),
+          takerSource: takerAta,
+          deal: d.dealPda,
+          dealVaultAuth: d.dvaPda,
+          longMarginVault: d.longVault,
+          shortMarginVault: d.shortVault,
+          feeVault: m.feeVaultAta,
+          marketVaultAuth: m.mvaPda,
+          systemProgram: web3.SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers(taker ? [taker] : [])
+        .rpc();
+    };
+    const cancelOffer = (offerId, signerKp = maker, destination = m.shortAta) => {
+      const offer = offerPda(offerId);
+      return pg.program.methods
+        .cancelOffer()
+        .accounts({
+          maker: signerKp.publicKey,
+          offer,
+          escrow: deriveAta(offer, m.mint),
+          makerDestination: destination,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([signerKp])
+        .rpc();
+    };
+
+    const stranger = web3.Keypair.generate();
+    const strangerAta = deriveAta(stranger.publicKey, m.mint);
+    await sendTx([
+      web3.SystemProgram.transfer({ fromPubkey: wallet.publicKey, toPubkey: stranger.publicKey, lamports: 50_000_000 }),
+      createAtaIx(wallet.publicKey, strangerAta, stranger.publicKey, m.mint),
+      mintToIx(m.mint, strangerAta, wallet.publicKey, 1_000_000),
+    ]);
+
+    // Offer 5 is reserved for the wallet: the maker's deposit sits in escrow until it is taken
+    const makerBefore = (await tokenBalance(m.shortAta)).toNumber();
+    const offer5 = await makeOffer(5, wallet.publicKey);
+    assert.equal((await tokenBalance(deriveAta(offer5, m.mint))).toNumber(), 200_000);
+    assert.equal((await tokenBalance(m.shortAta)).toNumber(), makerBefore - 200_000);
+
+    await expectError("take by a taker the offer is not reserved for", "TakerNotAllowed", () =>
+      takeOffer(5, stranger, strangerAta)
+    );
+
+    await takeOffer(5);
+    const deal = await pg.program.account.deal.fetch(m.dealAccounts(5).dealPda);
+    assert.isTrue(deal.isOpen);
+    assert.equal(deal.short.toBase58(), maker.publicKey.toBase58());
+    assert.equal(deal.clientOrderId.toNumber(), 5);
+    assert.equal(deal.shortMargin.toNumber(), 200_000 - 1_000); // escrow minus the 10 bps open fee
+    assert.isNull(await pg.connection.getAccountInfo(offer5), "offer closed on fill");
+    assert.isNull(await pg.connection.getAccountInfo(deriveAta(offer5, m.mint)), "escrow closed on fill");
+
+    // Offer 6 is open to anyone; only the maker can withdraw it, and it cannot be taken afterwards
+    const offer6 = await makeOffer(6);
+    await expectError("cancel by someone other than the maker", "ConstraintHasOne", () =>
+      cancelOffer(6, stranger, strangerAta)
+    );
+    const makerBeforeCancel = (await tokenBalance(m.shortAta)).toNumber();
+    await cancelOffer(6);
+    assert.equal((await tokenBalance(m.shortAta)).toNumber(), makerBeforeCancel + 200_000);
+    assert.isNull(await pg.connection.getAccountInfo(offer6), "offer closed on cancel");
+    await expectError("take after cancel", "AccountNotInitialized", () => takeOffer(6));
+  });
+
+  it("circuit breaker: a confirmed move is applied after the cool-off instead of re-tripping", async () => {
+    const m = await setupMarket({ circuitBreakerSecs: 2 });
+    const fetchMarket = () => pg.program.account.market.fetch(m.marketPda);
+
+    // +100% trips the breaker and parks the NAV as the pending mark
+    await m.postNav(new BN(2_000_000));
+    let acc = await fetchMarket();
+    assert.equal(acc.lastNav.toNumber(), 1_000_000);
+    assert.equal(acc.breakerNav.toNumber(), 2_000_000);
+    await expectError("post during cool-off", "CircuitBreaker", () => m.postNav(new BN(2_000_000)));
+
+    // After the cool-off a post near breaker_nav confirms the move (it is still +110% vs last_nav)
+    await waitForChainTime(acc.circuitBreakerUntil.toNumber());
+    await m.postNav(new BN(2_100_000));
+    acc = await fetchMarket();
+    assert.equal(acc.lastNav.toNumber(), 2_100_000);
+    assert.equal(acc.breakerNav.toNumber(), 0);
+    assert.isAtMost(acc.circuitBreakerUntil.toNumber(), acc.lastTs.toNumber()); // not re-tripped
+
+    // A spike that reverts: the post near last_nav is applied and the pending mark is dropped,
+    // so a later jump to the old spike level trips again rather than riding the stale mark
+    await m.postNav(new BN(10_000_000));
+    acc = await fetchMarket();
+    assert.equal(acc.breakerNav.toNumber(), 10_000_000);
+    await waitForChainTime(acc.circuitBreakerUntil.toNumber());
+    await m.postNav(new BN(2_200_000));
+    acc = await fetchMarket();
+    assert.equal(acc.lastNav.toNumber(), 2_200_000);
+    assert.equal(acc.breakerNav.toNumber(), 0);
+
+    await m.postNav(new BN(10_000_000));
+    acc = await fetchMarket();
+    assert.equal(acc.lastNav.toNumber(), 2_200_000);
+    assert.equal(acc.breakerNav.toNumber(), 10_000_000);
+  });
+
+  it("trip_circuit_breaker: the guardian can extend a trip but not shorten or clear it", async () => {
+    const m = await setupMarket();
+    const guardian = web3.Keypair.generate();
+    await pg.program.methods
+      .setRole({ guardian: {} }, guardian.publicKey)
+      .accounts({ authority: wallet.publicKey, market: m.marketPda })
+      .rpc();
+    const trip = (duration) =>
+      pg.program.methods
+        .tripCircuitBreaker(duration)
+        .accounts({ authority: guardian.publicKey, market: m.marketPda })
+        .signers([guardian])
+        .rpc();
+    const breakerUntil = async () =>
+      (await pg.program.account.market.fetch(m.marketPda)).circuitBreakerUntil.toNumber();
+
+    await trip(600);
+    const until = await breakerUntil();
+    await expectError("zero-length trip", "InvalidBreakerDuration", () => trip(0));
+    await trip(1);
+    assert.equal(await breakerUntil(), until, "a shorter trip does not cut the active one");
+    await trip(3600);
+    assert.isAbove(await breakerUntil(), until);
+  });
+
+  it("update_market_params: instant changes are bounded and step-capped", async () => {
+    await expectError("init with a zero NAV jump limit", "InvalidRiskParams", () => setupMarket({ maxNavJumpBps: 0 }));
+    await expectError("init with a zero staleness window", "InvalidRiskParams", () => setupMarket({ priceStaleSeconds: 0 }));
+
+    const m = await setupMarket();
+    const update = (params) =>
+      pg.program.methods.updateMarketParams(params).accounts({ authority: wallet.publicKey, market: m.marketPda }).rpc();
+
+    await expectError("MM to u16::MAX", "ParamNeedsTimelock", () => update({ maintenanceMarginBps: 65_535 }));
+    await expectError("leverage cap to 0", "ParamNeedsTimelock", () => update({ maxLeverageBps: 0 }));
+    await expectError("NAV jump limit to 0", "ParamNeedsTimelock", () => update({ maxNavJumpBps: 0 }));
+
+    // Steps within the cap apply; the absolute bounds still hold (MM may not pass IM = 1000)
+    await update({ maintenanceMarginBps: 1_000, maxLeverageBps: 5_000 });
+    await expectError("MM above IM", "InvalidRiskParams", () => update({ maintenanceMarginBps: 1_200 }));
+    const acc = await pg.program.account.market.fetch(m.marketPda);
+    assert.equal(acc.maintenanceMarginBps, 1_000);
+    assert.equal(acc.maxLeverageBps, 5_000);
+  });
+
+  it("price_stale_seconds: zero is rejected through the timelocked update too", async () => {
+    const m = await setupMarket({ minTimelockSecs: 0 });
+    const id = await createProposal(m.marketPda, { updateParams: { params: { priceStaleSeconds: 0 } } });
+    await expectError("UpdateParams proposal with a zero staleness window", "InvalidRiskParams", () =>
+      executeProposal(m.marketPda, id)
+    );
+    assert.equal((await pg.program.account.market.fetch(m.marketPda)).priceStaleSeconds, 300);
+  });
+
+  it("tighten_margin_params: the risk manager cannot set extreme values", async () => {
+    const m = await setupMarket();
+    const riskManager = web3.Keypair.generate();
+    await pg.program.methods
+      .setRole({ riskManager: {} }, riskManager.publicKey)
+      .accounts({ authority: wallet.publicKey, market: m.marketPda })
+      .rpc();
+    const tighten = (params) =>
+      pg.program.methods
+        .tightenMarginParams(params)
+        .accounts({ authority: riskManager.publicKey, market: m.marketPda })
+        .signers([riskManager])
+        .rpc();
+
+    await expectError("MM to u16::MAX", "ParamNeedsTimelock", () => tighten({ maintenanceMarginBps: 65_535 }));
+    await expectError("leverage cap to 0", "ParamNeedsTimelock", () => tighten({ maxLeverageBps: 0 }));
+    await expectError("MM above IM", "InvalidRiskParams", () => tighten({ maintenanceMarginBps: 1_001 }));
+
+    await tighten({ initialMarginBps: 1_500, maintenanceMarginBps: 800 });
+    const acc = await pg.program.account.market.fetch(m.marketPda);
+    assert.equal(acc.initialMarginBps, 1_500);
+    assert.equal(acc.maintenanceMarginBps, 800);
+  });
+
+  it("optimistic NAV: bond required with a dispute window, multisig resolution refunds the disputer", async () => {
+    await expectError("dispute window without a bond", "DisputeBondRequired", () =>
+      setupMarket({ disputeWindowSecs: 600, disputeBond: new BN(0) })
+    );
+
+    // setupMarket's NAV post is only proposed under a dispute window
+    const bond = 50_000;
+    const m = await setupMarket({ disputeWindowSecs: 600, disputeBond: new BN(bond) });
+    const before = (await tokenBalance(m.shortAta)).toNumber();
+    await pg.program.methods
+      .disputeNav()
+      .accounts({
+        disputer: m.shortKp.publicKey,
+        market: m.marketPda,
+        disputerSource: m.shortAta,
+        feeVault: m.feeVaultAta,
+        marketVaultAuth: m.mvaPda,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([m.shortKp])
+      .rpc();
+    assert.equal((await tokenBalance(m.shortAta)).toNumber(), before - bond);
+
+    const feeAccounts = {
+      marketVaultAuth: m.mvaPda,
+      feeVault: m.feeVaultAta,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      disputerRefundAta: m.shortAta,
+    };
+    // A proposal naming another NAV does not resolve the pending one
+    const stale = await createProposal(m.marketPda, { resolveNavDispute: { nav: new BN(999_999), navValid: false } });
+    await expectError("resolve a NAV that is not pending", "PendingNavChanged", () =>
+      executeProposal(m.marketPda, stale, feeAccounts)
+    );
+
+    const id = await createProposal(m.marketPda, { resolveNavDispute: { nav: m.nav, navValid: false } });
+    await executeProposal(m.marketPda, id, feeAccounts);
+    const acc = await pg.program.account.market.fetch(m.marketPda);
+    assert.equal(acc.pendingNav.postedTs.toNumber(), 0);
+    assert.equal(acc.bondsHeld.toNumber(), 0);
+    assert.equal(acc.lastNav.toNumber(), 0); // rejected NAV not applied
+    assert.equal((await tokenBalance(m.shortAta)).toNumber(), before);
+  });
+
+  it("optimistic NAV: a late finalize keeps the post time, so the mark is already stale", async () => {
+    const m = await setupMarket({ disputeWindowSecs: 1, disputeBond: new BN(50_000), priceStaleSeconds: 2 });
+    const { pendingNav } = await pg.program.account.market.fetch(m.marketPda);
+    const postedTs = pendingNav.postedTs.toNumber();
+
+    // Nobody finalizes until well after the NAV would have gone stale
+    await waitForChainTime(postedTs + 1 + 2 + 1);
+    await pg.program.methods.finalizeNav().accounts({ market: m.marketPda, navHistory: null }).rpc();
+    const acc = await pg.program.account.market.fetch(m.marketPda);
+    assert.equal(acc.lastNav.toNumber(), m.nav.toNumber());
+    assert.equal(acc.lastTs.toNumber(), postedTs);
+    await expectError("open on a late-finalized NAV", "PriceStale", () => m.openDeal(1));
+  });
+
+  it("socialized loss guard: a depleting liquidation sets PAUSE_OPEN instead of the full pause", async () => {
+    const m = await setupMarket({ maxNavJumpBps: 30_000 });
+    await m.openDeal(1, 150_000);
+    const d = m.dealAccounts(1);
+
+    // +30%: the short's 150k margin cannot cover the 300k loss
+    await m.postNav(new BN(1_300_000));
+    await pg.program.methods
+      .liquidate()
+      .accounts({
+        liquidator: wallet.publicKey,
+        market: m.marketPda,
+        deal: d.dealPda,
+        quoteMint: m.mint,
+        longMarginVault: d.longVault,
+        shortMarginVault: d.shortVault,
+        longPayoutAta: m.longAta,
+        shortPayoutAta: m.shortAta,
+        liquidatorAta: m.longAta,
+        marketAuthority: wallet.publicKey,
+        dealVaultAuth: d.dvaPda,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        navHistory: null,
+      })
+      .rpc();
+
+    const acc = await pg.program.account.market.fetch(m.marketPda);
+    assert.isFalse(acc.paused);
+    assert.equal(acc.pauseFlags & 1, 1); // PAUSE_OPEN
+    await expectError("open after socialized loss", "MarketPaused", () => m.openDeal(2, 150_000));
+  });
+
+  it("begin_shutdown keeps a fixed expiry NAV when the oracle is dead", async () => {
+    const now = Math.floor(Date.now() / 1000);
+    const m = await setupMarket({ expiryTs: new BN(now + 5), priceStaleSeconds: 2, oracleDeadMultiple: 1 });
+    const fetchMarket = () => pg.program.account.market.fetch(m.marketPda);
+
+    // First post after expiry fixes final_nav; a later post only moves last_nav (and so dead_nav)
+    await waitForChainTime(now + 5);
+    await m.postNav(new BN(1_050_000));
+    await m.postNav(new BN(1_100_000));
+    let acc = await fetchMarket();
+    assert.equal(acc.finalNav.toNumber(), 1_050_000);
+
+    await waitForChainTime(acc.lastTs.toNumber() + 2);
+    await pg.program.methods.declareOracleDead().accounts({ market: m.marketPda, navHistory: null }).rpc();
+    await pg.program.methods
+      .beginShutdown()
+      .accounts({ authority: wallet.publicKey, market: m.marketPda, navHistory: null })
+      .rpc();
+    acc = await fetchMarket();
+    assert.isTrue(acc.oracleDead);
+    assert.equal(acc.deadNav.toNumber(), 1_100_000);
+    assert.equal(acc.finalNav.toNumber(), 1_050_000);
+  });
+
+  it("close_market requires attached accounts and no open proposals", async () => {
+    const m = await setupMarket({ minTimelockSecs: 0 });
+    const [navHistory] = web3.PublicKey.findProgramAddressSync(
+      [VERSION_SEED, Buffer.from("nav_history"), m.marketPda.toBuffer()],
+      PROGRAM_ID
+    );
+    await pg.program.methods
+      .initNavHistory()
+      .accounts({
+        authority: wallet.publicKey,
+        market: m.marketPda,
+        navHistory,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+    const pending = await createProposal(m.marketPda, { pause: { paused: true } });
+    await pg.program.methods
+      .beginShutdown()
+      .accounts({ authority: wallet.publicKey, market: m.marketPda, navHistory: null })
+      .rpc();
+    assert.deepEqual((await pg.program.account.market.fetch(m.marketPda)).status, { settled: {} });
+
+    const close = (attachedNavHistory) =>
+      pg.program.methods
+        .closeMarket()
+        .accounts({
+          authority: wallet.publicKey,
+          market: m.marketPda,
+          marketVaultAuth: m.mvaPda,
+          feeVault: m.feeVaultAta,
+          oracleSet: null,
+          stackDefinition: null,
+          navHistory: attachedNavHistory,
+          marketAuthority: wallet.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+    await expectError("close with an open proposal", "ProposalsOutstanding", () => close(navHistory));
+    await pg.program.methods
+      .cancelProposal()
+      .accounts({
+        signer: wallet.publicKey,
+        market: m.marketPda,
+        proposal: proposalPda(m.marketPda, pending),
+        proposer: wallet.publicKey,
+      })
+      .rpc();
+    await expectError("close leaving out the NAV history", "InvalidNavHistory", () => close(null));
+    await close(navHistory);
+    assert.isNull(await pg.connection.getAccountInfo(m.marketPda));
+    assert.isNull(await pg.connection.getAccountInfo(navHistory));
+  });
+
+  it("rotate_authority replaces the old authority's admin slot", async () => {
+    const m = await setupMarket();
+    const admin = (method, ...args) =>
+      pg.program.methods[method](...args).accounts({ authority: wallet.publicKey, market: m.marketPda }).rpc();
+    const coAdmin = web3.Keypair.generate().publicKey;
+    const next = web3.Keypair.generate().publicKey;
+
+    await admin("addAdmin", coAdmin);
+    await expectError("rotate to an existing admin", "InvalidAdminSet", () => admin("rotateAuthority", coAdmin));
+
+    await admin("rotateAuthority", next);
+    const acc = await pg.program.account.market.fetch(m.marketPda);
+    const admins = acc.admins.map((k) => k.toBase58());
+    assert.equal(acc.authority.toBase58(), next.toBase58());
+    assert.include(admins, next.toBase58());
+    assert.include(admins, coAdmin.toBase58());
+    assert.notInclude(admins, wallet.publicKey.toBase58());
+    await expectError("old authority after rotation", "Unauthorized", () => admin("setReduceOnly", true));
+  });
+
+  it("admin_threshold: zero is rejected at init and by proposal", async () => {
+    await expectError("init with threshold 0", "InvalidAdminSet", () => setupMarket({ adminThreshold: 0 }));
+    const m = await setupMarket({ minTimelockSecs: 0 });
+    const id = await createProposal(m.marketPda, { updateParams: { params: { adminThreshold: 0 } } });
+    await expectError("proposal lowering threshold to 0", "InvalidAdminSet", () => executeProposal(m.marketPda, id));
+    assert.equal((await pg.program.account.market.fetch(m.marketPda)).adminThreshold, 1);
+  });
+
+  it("multisig owner: direct owner calls need a proposal once admin_threshold > 1", async () => {
+    const m = await setupMarket({ minTimelockSecs: 0 });
+    const coAdmin = web3.Keypair.generate();
+    const direct = (method, ...args) =>
+      pg.program.methods[method](...args).accounts({ authority: wallet.publicKey, market: m.marketPda }).rpc();
+
+    // Single-sig: the authority adds a co-admin directly and raises the threshold by proposal
+    await direct("addAdmin", coAdmin.publicKey);
+    const raise = await createProposal(m.marketPda, { updateParams: { params: { adminThreshold: 2 } } });
+    await executeProposal(m.marketPda, raise);
+    assert.equal((await pg.program.account.market.fetch(m.marketPda)).adminThreshold, 2);
+
+    // The authority key no longer bypasses the multisig
+    await expectError("direct set_reduce_only", "ProposalRequired", () => direct("setReduceOnly", true));
+    await expectError("direct unpause", "ProposalRequired", () => direct("pauseMarket", false));
+
+    const id = await createProposal(m.marketPda, { setReduceOnly: { reduceOnly: true } });
+    await expectError("execute with one of two approvals", "NotEnoughSigners", () => executeProposal(m.marketPda, id));
+    await approveProposal(m.marketPda, id, coAdmin);
+    await executeProposal(m.marketPda, id);
+    const acc = await pg.program.account.market.fetch(m.marketPda);
+    assert.deepEqual(acc.status, { reduceOnly: {} });
+  });
+
+  it("proposals: 2-of-3 create/approve/execute, admin-only, single approval per key, cancel", async () => {
+    const m = await setupMarket({ minTimelockSecs: 0 });
+    const [adminB, adminC, newAdmin, stranger] = [0, 1, 2, 3].map(() => web3.Keypair.generate());
+    await sendTx([
+      web3.SystemProgram.transfer({ fromPubkey: wallet.publicKey, toPubkey: stranger.publicKey, lamports: 50_000_000 }),
+    ]);
+    for (const admin of [adminB, adminC]) {
+      await pg.program.methods.addAdmin(admin.publicKey).accounts({ authority: wallet.publicKey, market: m.marketPda }).rpc();
+    }
+    const raise = await createProposal(m.marketPda, { updateParams: { params: { adminThreshold: 2 } } });
+    await executeProposal(m.marketPda, raise);
+    assert.equal((await pg.program.account.market.fetch(m.marketPda)).adminThreshold, 2);
+
+    const addNew = { addAdmin: { admin: newAdmin.publicKey } };
+    const { proposalCount } = await pg.program.account.market.fetch(m.marketPda);
+    await expectError("proposal from a non-admin", "Unauthorized", () =>
+      pg.program.methods
+        .createProposal(addNew, 0)
+        .accounts({
+          proposer: stranger.publicKey,
+          market: m.marketPda,
+          proposal: proposalPda(m.marketPda, proposalCount),
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([stranger])
+        .rpc()
+    );
+
+    // The proposer's approval counts once; a second admin brings it to threshold
+    const id = await createProposal(m.marketPda, addNew);
+    await expectError("execute below threshold", "NotEnoughSigners", () => executeProposal(m.marketPda, id));
+    await expectError("proposer approving twice", "AlreadyApproved", () =>
+      pg.program.methods
+        .approveProposal()
+        .accounts({ admin: wallet.publicKey, market: m.marketPda, proposal: proposalPda(m.marketPda, id) })
+        .rpc()
+    );
+    await expectError("approval from a non-admin", "Unauthorized", () => approveProposal(m.marketPda, id, stranger));
+    await expectError("still below threshold", "NotEnoughSigners", () => executeProposal(m.marketPda, id));
+
+    await approveProposal(m.marketPda, id, adminB);
+    assert.equal((await pg.program.account.proposal.fetch(proposalPda(m.marketPda, id))).numApprovals, 2);
+    await executeProposal(m.marketPda, id);
+    const acc = await pg.program.account.market.fetch(m.marketPda);
+    assert.isTrue(acc.admins.some((a) => a.toBase58() === newAdmin.publicKey.toBase58()));
+    assert.isNull(await pg.connection.getAccountInfo(proposalPda(m.marketPda, id)), "proposal closed on execute");
+    await expectError("executing twice", "AccountNotInitialized", () =>
+      pg.program.methods
+        .executeProposal()
+        .accounts({
+          executor: wallet.publicKey,
+          market: m.marketPda,
+          proposal: proposalPda(m.marketPda, id),
+          proposer: wallet.publicKey,
+          stackDefinition: null,
+          marketVaultAuth: null,
+          feeVault: null,
+          treasuryAta: null,
+          insuranceAta: null,
+          oracleOperatorAta: null,
+          tokenProgram: null,
+          disputerRefundAta: null,
+          navHistory: null,
+          oracleSet: null,
+        })
+        .rpc()
+    );
+
+    // Only the proposer can withdraw a proposal on a multisig market
+    const pending = await createProposal(m.marketPda, { pause: { paused: true } });
+    const cancel = (signerKp) =>
+      pg.program.methods
+        .cancelProposal()
+        .accounts({
+          signer: signerKp.publicKey,
+          market: m.marketPda,
+          proposal: proposalPda(m.marketPda, pending),
+          proposer: wallet.publicKey,
+        })
+        .signers(signerKp === wallet ? [] : [signerKp])
+        .rpc();
+    await expectError("cancel by another admin", "Unauthorized", () => cancel(adminC));
+    await cancel(wallet);
+    assert.isNull(await pg.connection.getAccountInfo(proposalPda(m.marketPda, pending)), "proposal closed on cancel");
+    assert.isFalse((await pg.program.account.market.fetch(m.marketPda)).paused);
+  });
+
+  it("proposals: expiry window and approvals from removed admins", async () => {
+    const m = await setupMarket({ minTimelockSecs: 0 });
+    const [adminB, adminC] = [web3.Keypair.generate(), web3.Keypair.generate()];
+    for (const admin of [adminB, adminC]) {
+      await pg.program.methods.addAdmin(admin.publicKey).accounts({ authority: wallet.publicKey, market: m.marketPda }).rpc();
+    }
+    await executeProposal(m.marketPda, await createProposal(m.marketPda, { updateParams: { params: { adminThreshold: 2 } } }));
+
+    const id = await createProposal(m.marketPda, { setReduceOnly: { reduceOnly: true } });
+    let p = await pg.program.account.proposal.fetch(proposalPda(m.marketPda, id));
+    assert.equal(p.expiresAt.toNumber(), p.eta.toNumber() + 7 * 86_400);
+    await approveProposal(m.marketPda, id, adminB);
+
+    // adminB leaves before the proposal runs: its approval no longer counts
+    const remove = await createProposal(m.marketPda, { removeAdmin: { admin: adminB.publicKey } });
+    await approveProposal(m.marketPda, remove, adminC);
+    await executeProposal(m.marketPda, remove);
+    await expectError("execute on a removed admin's approval", "NotEnoughSigners", () => executeProposal(m.marketPda, id));
+
+    // The next approval drops the stale key from the approval slots
+    await approveProposal(m.marketPda, id, adminC);
+    p = await pg.program.account.proposal.fetch(proposalPda(m.marketPda, id));
+    assert.equal(p.numApprovals, 2);
+    assert.isFalse(p.approvals.some((k) => k.toBase58() === adminB.publicKey.toBase58()));
+    await executeProposal(m.marketPda, id);
+    assert.deepEqual((await pg.program.account.market.fetch(m.marketPda)).status, { reduceOnly: {} });
+  });
+
+  it("oracle set: setup-only direct changes, timelocked publisher changes afterwards", async () => {
+    const m = await setupMarket({ minTimelockSecs: 2 }, { initialNav: false });
+    const [oracleSet] = web3.PublicKey.findProgramAddressSync(
+      [VERSION_SEED, Buffer.from("oracle_set"), m.marketPda.toBuffer()],
+      PROGRAM_ID
+    );
+    const [pubA, pubB, pubC] = [web3.Keypair.generate(), web3.Keypair.generate(), web3.Keypair.generate()];
+
+    await pg.program.methods
+      .initOracleSet([pubA.publicKey, pubB.publicKey], 1)
+      .accounts({
+        authority: wallet.publicKey,
+        market: m.marketPda,
+        oracleSet,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+    await pg.program.methods
+      .submitNav(m.nav, null)
+      .accounts({ market: m.marketPda, oracleSet, publisher: pubA.publicKey, navHistory: null })
+      .signers([pubA])
+      .rpc();
+    assert.equal((await pg.program.account.market.fetch(m.marketPda)).lastNav.toNumber(), m.nav.toNumber());
+
+    // Live market: no instant publisher swap, and the proposal must respect min_timelock_secs
+    await expectError("direct set_oracle_publishers on a live market", "MarketAlreadyLive", () =>
+      pg.program.methods
+        .setOraclePublishers([pubC.publicKey], 1)
+        .accounts({ authority: wallet.publicKey, market: m.marketPda, oracleSet })
+        .rpc()
+    );
+    const action = { setOraclePublishers: { publishers: [pubC.publicKey], quorum: 1 } };
+    await expectError("publisher proposal without delay", "TimelockTooShort", () =>
+      createProposal(m.marketPda, action, 0)
+    );
+    const id = await createProposal(m.marketPda, action, 2);
+    await expectError("publisher proposal before its ETA", "TimelockNotExpired", () =>
+      executeProposal(m.marketPda, id, { oracleSet })
+    );
+    const { eta } = await pg.program.account.proposal.fetch(proposalPda(m.marketPda, id));
+    await waitForChainTime(eta.toNumber());
+    await executeProposal(m.marketPda, id, { oracleSet });
+
+    const set = await pg.program.account.oracleSet.fetch(oracleSet);
+    assert.equal(set.numPublishers, 1);
+    assert.equal(set.publishers[0].toBase58(), pubC.publicKey.toBase58());
+  });
 });
 
 //Test Output